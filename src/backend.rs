@@ -1,312 +1,165 @@
-use rand::{rngs::ThreadRng, Rng};
-use std::collections::BTreeMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
-pub enum Material {
-    Money,
-    Energy,
-    Sand,
-    Ore,
-    Gold,
-    Chip,
-    Wire,
-    Computer,
-    Log,
-    Plank,
-    Furniture,
-    RawOil,
-    Oil,
-    Glass,
-    Plastic,
-}
-
-impl Material {
-    pub fn get_texture_id(&self) -> String {
-        match self {
-            Self::Money => "money",
-            Self::Energy => "energy",
-            Self::Sand => "sand",
-            Self::Gold => "gold",
-            Self::Ore => "rocks",
-            Self::Chip => "chip",
-            Self::Wire => "wire",
-            Self::Computer => "computer",
-            Self::Log => "logs",
-            Self::Plank => "planks",
-            Self::Furniture => "chair",
-            Self::RawOil => "raw_oil",
-            Self::Oil => "oil",
-            Self::Glass => "glass",
-            Self::Plastic => "plastic",
-        }
-        .to_string()
-    }
-}
-
-pub struct Recipe {
-    pub materials: Vec<(Material, i32)>,
-}
+pub mod ai;
+pub mod construction;
+pub mod game;
+pub mod ledger;
+pub mod logistics;
+pub mod map;
+pub mod market;
+pub mod planner;
+pub mod registry;
+pub mod resolution;
+pub mod roster;
+pub mod routing;
+pub mod sale;
+pub mod save;
+pub mod setup;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod toll;
+pub mod trade;
+pub mod turn;
+pub mod upgrade;
+pub mod upkeep;
+pub mod valuation;
+pub mod victory;
 
-#[derive(PartialEq, Eq, Hash)]
-pub enum ValidRecipe {
-    MaterialImport(Material),
-    MaterialExport(Material),
-    ComputerAssembly,
-    PlankProduction,
-    FurnitureProduction,
-    OreMining,
-    MetalRefining,
-    SandCollecting,
-    ChipProduction,
-    GlassProduction,
-    OilDrilling,
-    OilRefining,
-    OilBurning,
-    PlasticProduction,
-    Forestation,
-}
-
-impl ValidRecipe {
-    pub fn get_recipe(&self) -> Recipe {
-        Recipe {
-            materials: match self {
-                Self::MaterialImport(material) => {
-                    let (quantity, price) = match material {
-                        Material::Money => (1, 1),
-                        Material::Energy => (1, 2),
-                        Material::Sand => (2, 1),
-                        Material::Ore => (1, 4),
-                        Material::Gold => (1, 8),
-                        Material::Chip => (1, 1),
-                        Material::Wire => (1, 4),
-                        Material::Computer => (1, 8),
-                        Material::Log => (1, 2),
-                        Material::Plank => (1, 1),
-                        Material::Furniture => (1, 8),
-                        Material::RawOil => (1, 1),
-                        Material::Oil => (1, 2),
-                        Material::Glass => (1, 1),
-                        Material::Plastic => (1, 1),
-                    };
-                    vec![(Material::Money, -price), (material.clone(), quantity)]
-                }
-                Self::MaterialExport(material) => {
-                    let (quantity, price) = match material {
-                        Material::Money => (1, 1),
-                        Material::Energy => (1, 2),
-                        Material::Sand => (2, 1),
-                        Material::Ore => (1, 4),
-                        Material::Gold => (1, 8),
-                        Material::Chip => (1, 1),
-                        Material::Wire => (1, 4),
-                        Material::Computer => (1, 8),
-                        Material::Log => (1, 2),
-                        Material::Plank => (1, 1),
-                        Material::Furniture => (1, 8),
-                        Material::RawOil => (1, 1),
-                        Material::Oil => (1, 2),
-                        Material::Glass => (1, 1),
-                        Material::Plastic => (1, 1),
-                    };
-                    vec![(material.clone(), -quantity), (Material::Money, price)]
-                }
-                Self::GlassProduction => vec![(Material::Sand, -1), (Material::Glass, 1)],
-                Self::OilDrilling => vec![
-                    (Material::Money, -1),
-                    (Material::Energy, -1),
-                    (Material::RawOil, 4),
-                ],
-                Self::OilRefining => vec![(Material::RawOil, -1), (Material::Oil, 2)],
-                Self::OilBurning => vec![(Material::Oil, -1), (Material::Energy, 2)],
-                Self::ComputerAssembly => vec![
-                    (Material::Chip, -1),
-                    (Material::Wire, -1),
-                    (Material::Computer, 1),
-                ],
-                Self::PlankProduction => vec![(Material::Log, -1), (Material::Plank, 4)],
-                Self::FurnitureProduction => vec![(Material::Plank, -4), (Material::Furniture, 1)],
-                Self::OreMining => vec![(Material::Energy, -1), (Material::Ore, 1)],
-                Self::MetalRefining => vec![
-                    (Material::Ore, -2),
-                    (Material::Wire, 1),
-                    (Material::Gold, 1),
-                ],
-                Self::SandCollecting => vec![(Material::Energy, -1), (Material::Sand, 2)],
-                Self::Forestation => vec![(Material::Energy, -1), (Material::Log, 1)],
-                Self::ChipProduction => vec![
-                    (Material::Energy, -1),
-                    (Material::Sand, -1),
-                    (Material::Chip, 1),
-                ],
-                Self::PlasticProduction => vec![(Material::Oil, -1), (Material::Plastic, 4)],
-            },
-        }
-    }
-}
+use ai::Controller;
+use ledger::{Ledger, Source};
+use logistics::Shipment;
+use registry::{BuildingTypeId, MaterialId, Registry, ValidRecipe};
+use resolution::{ActivityId, Throttle};
+use roster::PlayerSetup;
+use stats::Snapshot;
+use trade::{Contract, Offer};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ScaledValidRecipe {
     pub valid_recipe: ValidRecipe,
     pub scale: i32,
     pub max_scale: i32,
+    /// Higher priority recipes keep running when inputs fall short.
+    #[serde(default)]
+    pub priority: i32,
 }
 
-#[derive(Clone, Copy)]
-pub enum BuildingType {
-    Market,
-    EnergyMarket,
-    Sawmill,
-    FurnitureFactory,
-    WoodWorkingMarket,
-    ComputerFactory,
-    SandPlant,
-    Mine,
-    MetalRefinery,
-    GlassFactory,
-    OilRig,
-    OilRefinery,
-    PlasticFactory,
-    OilEnergyPlant,
-    TreeFarm,
-}
-
-impl BuildingType {
-    fn get_valid_recipes(&self) -> Vec<ValidRecipe> {
-        match self {
-            Self::Market => {
-                vec![
-                    ValidRecipe::MaterialExport(Material::Glass),
-                    ValidRecipe::MaterialImport(Material::Wire),
-                    ValidRecipe::MaterialExport(Material::Wire),
-                    ValidRecipe::MaterialExport(Material::Chip),
-                    ValidRecipe::MaterialExport(Material::Gold),
-                    ValidRecipe::MaterialExport(Material::Ore),
-                    ValidRecipe::MaterialImport(Material::Log),
-                    ValidRecipe::MaterialExport(Material::Log),
-                    ValidRecipe::MaterialExport(Material::Plastic),
-                    ValidRecipe::MaterialExport(Material::Computer),
-                ]
-            }
-            Self::EnergyMarket => {
-                vec![
-                    ValidRecipe::MaterialImport(Material::Energy),
-                    ValidRecipe::MaterialExport(Material::Energy),
-                ]
-            }
-            Self::WoodWorkingMarket => {
-                vec![
-                    ValidRecipe::MaterialImport(Material::Plank),
-                    ValidRecipe::MaterialExport(Material::Plank),
-                    ValidRecipe::MaterialExport(Material::Furniture),
-                ]
-            }
-            Self::ComputerFactory => {
-                vec![ValidRecipe::ChipProduction, ValidRecipe::ComputerAssembly]
-            }
-            Self::TreeFarm => vec![ValidRecipe::Forestation],
-            Self::Sawmill => vec![ValidRecipe::PlankProduction],
-            Self::FurnitureFactory => vec![ValidRecipe::FurnitureProduction],
-            Self::SandPlant => vec![ValidRecipe::SandCollecting],
-            Self::GlassFactory => vec![ValidRecipe::GlassProduction],
-            Self::Mine => vec![ValidRecipe::OreMining],
-            Self::MetalRefinery => vec![ValidRecipe::MetalRefining],
-            Self::OilRig => vec![ValidRecipe::OilDrilling],
-            Self::OilRefinery => vec![ValidRecipe::OilRefining],
-            Self::OilEnergyPlant => vec![ValidRecipe::OilBurning],
-            Self::PlasticFactory => vec![ValidRecipe::PlasticProduction],
-        }
-    }
-}
-
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OwnedBuilding {
-    pub building_type: BuildingType,
+    pub building_type: BuildingTypeId,
     pub production_scale: Vec<ScaledValidRecipe>,
     pub owner_id: Option<usize>,
     pub acquisition_cost: i32,
+    #[serde(default)]
+    pub market_prices: BTreeMap<MaterialId, f32>,
+    /// Upgrades bought so far.
+    #[serde(default)]
+    pub level: usize,
+    /// Rounds left until the building is constructed. It cannot run or be upgraded before.
+    #[serde(default)]
+    pub construction_rounds: u32,
+    /// Round the current owner bought or started constructing it in.
+    #[serde(default)]
+    pub acquired_round: u32,
 }
 
 impl OwnedBuilding {
-    pub fn new(building_type: BuildingType) -> OwnedBuilding {
+    pub fn new(registry: &Registry, building_type: BuildingTypeId) -> OwnedBuilding {
+        let building_type_def = &registry.building_types[&building_type];
         Self {
-            building_type: building_type,
-            production_scale: building_type
-                .get_valid_recipes()
+            production_scale: registry
+                .get_valid_recipes(&building_type)
                 .into_iter()
                 .map(|valid_recipe| ScaledValidRecipe {
-                    valid_recipe: valid_recipe,
+                    valid_recipe,
                     scale: 0,
-                    max_scale: 5,
+                    max_scale: building_type_def.max_scale,
+                    priority: 0,
                 })
                 .collect(),
             owner_id: None,
-            acquisition_cost: match building_type {
-                BuildingType::Market => 20,
-                BuildingType::EnergyMarket => 20,
-                BuildingType::Sawmill => 80,
-                BuildingType::FurnitureFactory => 60,
-                BuildingType::WoodWorkingMarket => 20,
-                BuildingType::ComputerFactory => 150,
-                BuildingType::TreeFarm => 50,
-                BuildingType::SandPlant => 80,
-                BuildingType::Mine => 100,
-                BuildingType::MetalRefinery => 100,
-                BuildingType::GlassFactory => 50,
-                BuildingType::OilRig => 100,
-                BuildingType::OilRefinery => 100,
-                BuildingType::PlasticFactory => 100,
-                BuildingType::OilEnergyPlant => 100,
-            },
+            acquisition_cost: building_type_def.acquisition_cost,
+            market_prices: BTreeMap::new(),
+            level: 0,
+            construction_rounds: 0,
+            acquired_round: 0,
+            building_type,
         }
     }
-    pub fn new_random(rng: &mut ThreadRng) -> Self {
-        let options = vec![
-            BuildingType::Market,
-            BuildingType::EnergyMarket,
-            BuildingType::Sawmill,
-            BuildingType::FurnitureFactory,
-            BuildingType::WoodWorkingMarket,
-            BuildingType::ComputerFactory,
-            BuildingType::SandPlant,
-            BuildingType::TreeFarm,
-            BuildingType::Mine,
-            BuildingType::MetalRefinery,
-            BuildingType::GlassFactory,
-            BuildingType::OilRig,
-            BuildingType::OilRefinery,
-            BuildingType::OilEnergyPlant,
-            BuildingType::PlasticFactory,
-        ];
-        Self::new(
-            options
-                .get(rng.gen_range(0..options.len()))
-                .unwrap()
-                .clone(),
-        )
+    pub fn new_random(registry: &Registry, rng: &mut impl Rng) -> Self {
+        let options: Vec<&BuildingTypeId> = registry.building_types.keys().collect();
+        Self::new(registry, options[rng.gen_range(0..options.len())].clone())
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct City {
     pub x: f32,
     pub y: f32,
-    pub owned_buildings: Vec<OwnedBuilding>,
+    /// Building slots. Demolishing a building empties its slot, so the others keep their ids.
+    pub owned_buildings: Vec<Option<OwnedBuilding>>,
 }
 
 impl City {
-    pub fn new_with_random_buildings(rng: &mut ThreadRng, x: f32, y: f32) -> Self {
+    pub fn new_with_random_buildings(
+        registry: &Registry,
+        rng: &mut impl Rng,
+        x: f32,
+        y: f32,
+    ) -> Self {
         Self {
-            x: x,
-            y: y,
-            owned_buildings: (0..rng.gen_range(1..7))
-                .into_iter()
-                .map(|_i| OwnedBuilding::new_random(rng))
+            x,
+            y,
+            owned_buildings: (0..rng.gen_range(1..construction::MAX_BUILDINGS + 1))
+                .map(|_i| Some(OwnedBuilding::new_random(registry, rng)))
                 .collect(),
         }
     }
+
+    /// Buildings standing in the city with their ids.
+    pub fn get_buildings(&self) -> impl Iterator<Item = (usize, &OwnedBuilding)> {
+        self.owned_buildings
+            .iter()
+            .enumerate()
+            .filter_map(|(building_id, slot)| Some((building_id, slot.as_ref()?)))
+    }
+
+    pub fn get_buildings_mut(&mut self) -> impl Iterator<Item = (usize, &mut OwnedBuilding)> {
+        self.owned_buildings
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(building_id, slot)| Some((building_id, slot.as_mut()?)))
+    }
+
+    pub fn get_building(&self, building_id: usize) -> Option<&OwnedBuilding> {
+        self.owned_buildings.get(building_id)?.as_ref()
+    }
+
+    pub fn get_building_mut(&mut self, building_id: usize) -> Option<&mut OwnedBuilding> {
+        self.owned_buildings.get_mut(building_id)?.as_mut()
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OwnedConnection {
     pub city_ids: Vec<usize>,
     pub owner_id: Option<usize>,
     pub acquisition_cost: i32,
+    pub capacity: i32,
+    pub transport_cost: i32,
+    pub maintenance_cost: i32,
+    /// Fee per round other businesses pay to use it. `None` keeps them off.
+    #[serde(default)]
+    pub toll: Option<i32>,
+    /// Rounds left until the connection is built. It carries nothing before.
+    #[serde(default)]
+    pub construction_rounds: u32,
+    /// Round the current owner bought or started constructing it in.
+    #[serde(default)]
+    pub acquired_round: u32,
 }
 
 impl OwnedConnection {
@@ -315,30 +168,66 @@ impl OwnedConnection {
             city_ids: vec![city_id_a, city_id_b],
             owner_id: None,
             acquisition_cost: 20,
+            capacity: 10,
+            transport_cost: 1,
+            maintenance_cost: 1,
+            toll: None,
+            construction_rounds: 0,
+            acquired_round: 0,
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Graph {
     pub cities: Vec<City>,
     pub connections: Vec<OwnedConnection>,
     pub businesses: Vec<Business>,
+    /// Trades proposed and not answered yet.
+    #[serde(default)]
+    pub offers: Vec<Offer>,
+    #[serde(default)]
+    pub contracts: Vec<Contract>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Business {
-    pub resources: BTreeMap<Material, i32>,
+    pub name: String,
+    pub color: [u8; 3],
+    pub money: i32,
+    pub inventories: BTreeMap<usize, BTreeMap<MaterialId, i32>>,
+    pub shipments: Vec<Shipment>,
+    #[serde(default)]
+    pub controller: Controller,
+    /// Activities that ran below plan when resources were last updated.
+    #[serde(default)]
+    pub throttles: Vec<Throttle>,
+    /// Set when the business went bankrupt. It no longer takes turns.
+    #[serde(default)]
+    pub eliminated: bool,
+    /// Connections of other businesses it pays a toll for every round.
+    #[serde(default)]
+    pub toll_connection_ids: BTreeSet<usize>,
+    #[serde(default)]
+    pub ledger: Ledger,
+    /// A snapshot for every round it ended in the game.
+    #[serde(default)]
+    pub history: Vec<Snapshot>,
 }
 
 impl Business {
-    pub fn new() -> Self {
+    pub fn new(player_id: usize, player_setup: &PlayerSetup, starting_money: i32) -> Self {
         Self {
-            resources: BTreeMap::from([(Material::Money, 250)]),
+            name: player_setup.name.clone(),
+            color: roster::get_palette_color(player_id),
+            money: starting_money,
+            controller: player_setup.controller,
+            ..Default::default()
         }
     }
 }
 
+#[derive(Default)]
 pub struct QuantityInfo {
     pub quantity: i32,
     pub gross_in: i32,
@@ -352,81 +241,95 @@ impl QuantityInfo {
     pub fn net_out(&self) -> i32 {
         self.gross_out - self.gross_in
     }
+    fn add_flow(&mut self, amount: i32) {
+        if amount > 0 {
+            self.gross_in += amount;
+        } else {
+            self.gross_out -= amount;
+        }
+    }
 }
 
 impl Graph {
-    pub fn get_resource_stock(&self, business_id: usize) -> BTreeMap<Material, QuantityInfo> {
-        let mut resource_stock: BTreeMap<Material, QuantityInfo> = BTreeMap::new();
-        for ScaledValidRecipe {
-            valid_recipe,
-            scale,
-            max_scale: _,
-        } in self
-            .cities
-            .iter()
-            .flat_map(|city| city.owned_buildings.iter())
-            .filter(|owned_building| owned_building.owner_id == Some(business_id))
-            .flat_map(|owned_building| owned_building.production_scale.iter())
-            .filter(|s| (s.scale != 0))
-        {
-            for (material, quantity) in valid_recipe.get_recipe().materials {
-                let scaled_quantity = quantity * scale.clone() as i32;
-                let gross_in = if scaled_quantity > 0 {
-                    scaled_quantity
-                } else {
-                    0
-                };
-                let gross_out = if scaled_quantity < 0 {
-                    -scaled_quantity
-                } else {
-                    0
-                };
-                if let Some(quantity_info) = resource_stock.get_mut(&material) {
-                    quantity_info.gross_in += gross_in;
-                    quantity_info.gross_out += gross_out;
-                } else {
-                    resource_stock.insert(
-                        material.clone(),
-                        QuantityInfo {
-                            quantity: self
-                                .businesses
-                                .get(business_id)
-                                .unwrap()
-                                .resources
-                                .get(&material)
-                                .unwrap_or(&0)
-                                .clone(),
-                            gross_in: gross_in,
-                            gross_out: gross_out,
-                        },
-                    );
-                }
+    /// Per round flows of a business keyed by the city they happen in. Money is pooled across
+    /// cities, but its flows are still attributed to the city where they are earned or spent.
+    pub fn get_flows(
+        &self,
+        registry: &Registry,
+        business_id: usize,
+    ) -> BTreeMap<(usize, MaterialId), QuantityInfo> {
+        let activities = self.get_activities(registry, business_id);
+        resolution::sum_flows(
+            &activities,
+            activities.iter().map(|activity| activity.planned),
+        )
+    }
+
+    /// Stock and per round flows of a business, either in one city or summed over all cities.
+    pub fn get_resource_stock(
+        &self,
+        registry: &Registry,
+        business_id: usize,
+        city_id: Option<usize>,
+    ) -> BTreeMap<MaterialId, QuantityInfo> {
+        let business = &self.businesses[business_id];
+        let mut resource_stock: BTreeMap<MaterialId, QuantityInfo> = BTreeMap::from([(
+            MaterialId::money(),
+            QuantityInfo {
+                quantity: business.money,
+                ..Default::default()
+            },
+        )]);
+        for (inventory_city_id, inventory) in &business.inventories {
+            if city_id.is_some_and(|city_id| city_id != *inventory_city_id) {
+                continue;
+            }
+            for (material, quantity) in inventory {
+                resource_stock.entry(material.clone()).or_default().quantity += quantity;
             }
         }
-        for (material, quantity) in self.businesses.get(business_id).unwrap().resources.iter() {
-            if resource_stock.contains_key(material) {
+        for ((flow_city_id, material), flow) in self.get_flows(registry, business_id) {
+            if !material.is_money() && city_id.is_some_and(|city_id| city_id != flow_city_id) {
                 continue;
             }
-            resource_stock.insert(
-                material.clone(),
-                QuantityInfo {
-                    quantity: quantity.clone(),
-                    gross_in: 0,
-                    gross_out: 0,
-                },
-            );
+            let quantity_info = resource_stock.entry(material).or_default();
+            quantity_info.gross_in += flow.gross_in;
+            quantity_info.gross_out += flow.gross_out;
         }
         resource_stock
     }
-    pub fn update_business_resources(&mut self, business_id: usize) {
-        let resource_stock = self.get_resource_stock(business_id);
-        let business = self.businesses.get_mut(business_id).unwrap();
-        for (material, quantity_info) in resource_stock {
-            let delta = quantity_info.gross_in - quantity_info.gross_out;
-            if let Some(quantity) = business.resources.get_mut(&material) {
-                *quantity += delta;
-            } else {
-                business.resources.insert(material, delta);
+
+    /// Applies a round of flows, throttling activities whose inputs can't be met.
+    pub fn update_business_resources(
+        &mut self,
+        registry: &Registry,
+        business_id: usize,
+        round: u32,
+    ) {
+        let activities = self.get_activities(registry, business_id);
+        let (throughputs, throttles) = self.resolve_throughputs(&activities, business_id);
+        let business = &mut self.businesses[business_id];
+        business.throttles = throttles;
+        for (activity, throughput) in activities.iter().zip(throughputs) {
+            let source = match activity.id {
+                ActivityId::Recipe {
+                    city_id,
+                    building_id,
+                    recipe_id,
+                } => Source::Production {
+                    city_id,
+                    building_id,
+                    recipe_id,
+                },
+                ActivityId::Shipment(_) => Source::Shipping,
+            };
+            for ((city_id, material), quantity) in &activity.unit_flows {
+                business.add_goods(
+                    round,
+                    source,
+                    *city_id,
+                    &[(material.clone(), quantity * throughput)],
+                );
             }
         }
     }
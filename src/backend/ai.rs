@@ -0,0 +1,1115 @@
+use super::{
+    game::{Command, Game, GameError},
+    planner::get_yield,
+    registry::{MaterialId, ValidRecipe},
+    routing::{Metric, Router},
+};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Controller {
+    #[default]
+    Human,
+    Ai(Difficulty),
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Easy => "Easy",
+            Self::Normal => "Normal",
+            Self::Hard => "Hard",
+        })
+    }
+}
+
+impl fmt::Display for Controller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Human => f.write_str("Human"),
+            Self::Ai(difficulty) => write!(f, "AI ({})", difficulty),
+        }
+    }
+}
+
+impl FromStr for Controller {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(Self::Human),
+            "easy" => Ok(Self::Ai(Difficulty::Easy)),
+            "normal" | "ai" => Ok(Self::Ai(Difficulty::Normal)),
+            "hard" => Ok(Self::Ai(Difficulty::Hard)),
+            _ => Err(format!(
+                "Unknown player type {:?}, expected human, easy, normal or hard",
+                s
+            )),
+        }
+    }
+}
+
+const PAYBACK_ROUNDS: f32 = 50.0;
+const SUPPLY_CHAIN_DEPTH: usize = 2;
+/// Plans with the best estimates tried out on a copy of the game before buying.
+const CANDIDATE_PLANS: usize = 4;
+/// Least ratio of value received to value given, at base prices, for a trade to be accepted.
+const TRADE_MARGIN: f32 = 1.1;
+
+struct Strategy {
+    /// Plans bought per turn, each with every asset it needs.
+    purchases_per_turn: usize,
+    /// Money kept back when buying assets, so imports can still be paid for.
+    cash_reserve: i32,
+    /// Least money per round a purchase has to add after upkeep, as a share of its price.
+    min_return: f32,
+    buys_connections: bool,
+    /// Share of each production recipe's max scale the AI is willing to run.
+    scale_share: f32,
+}
+
+impl Difficulty {
+    fn strategy(&self) -> Strategy {
+        match self {
+            Self::Easy => Strategy {
+                purchases_per_turn: 1,
+                cash_reserve: 60,
+                min_return: 0.02,
+                buys_connections: false,
+                scale_share: 0.6,
+            },
+            Self::Normal => Strategy {
+                purchases_per_turn: 2,
+                cash_reserve: 30,
+                min_return: 0.01,
+                buys_connections: true,
+                scale_share: 1.0,
+            },
+            Self::Hard => Strategy {
+                purchases_per_turn: 4,
+                cash_reserve: 10,
+                min_return: 0.002,
+                buys_connections: true,
+                scale_share: 1.0,
+            },
+        }
+    }
+}
+
+/// Buys the best plans it can afford, reschedules production and ends the current player's turn.
+pub fn play_turn(game: &mut Game, difficulty: Difficulty) {
+    let strategy = difficulty.strategy();
+    answer_offers(game);
+    for _ in 0..strategy.purchases_per_turn {
+        let Some(purchases) = find_best_purchase(game, &strategy) else {
+            break;
+        };
+        if !buy_all(game, purchases) {
+            break;
+        }
+    }
+    schedule_production(game, &strategy);
+    game.execute(Command::EndTurn)
+        .expect("ending a turn always succeeds");
+}
+
+/// Accepts one-off trades that pay off at market prices and turns everything else down, as the AI
+/// does not plan production around contract deliveries.
+fn answer_offers(game: &mut Game) {
+    let business_id = game.current_player_id;
+    for offer_id in (0..game.graph.offers.len()).rev() {
+        let offer = &game.graph.offers[offer_id];
+        if offer.to_id != business_id {
+            continue;
+        }
+        let get_value = |goods: &[(MaterialId, i32)]| -> f32 {
+            goods
+                .iter()
+                .map(|(material, quantity)| {
+                    game.graph.get_market_value(&game.registry, material) * *quantity as f32
+                })
+                .sum()
+        };
+        let pays_off = !offer.terms.is_contract()
+            && get_value(&offer.terms.gives) >= get_value(&offer.terms.takes) * TRADE_MARGIN;
+        if !pays_off || game.execute(Command::AcceptOffer { offer_id }).is_err() {
+            game.execute(Command::RejectOffer { offer_id })
+                .expect("the addressee can always reject an offer");
+        }
+    }
+}
+
+/// Assets to buy together for the plan that adds the most money per round for its price, once
+/// production is rescheduled around them. The planner's estimates only pick which plans are worth
+/// trying, as they count owned production as free to supply every plan at once.
+fn find_best_purchase(game: &Game, strategy: &Strategy) -> Option<Vec<Command>> {
+    let business_id = game.current_player_id;
+    let budget = game.graph.businesses[business_id].money - strategy.cash_reserve;
+    let planner = Planner::new(game, true, strategy.buys_connections);
+    let network_city_ids = game.graph.get_network_city_ids(business_id);
+    let mut candidates: Vec<(f32, Vec<Command>)> = vec![];
+    for (city_id, city) in game.graph.cities.iter().enumerate() {
+        for (building_id, building) in city.get_buildings() {
+            let mut purchases = vec![];
+            if building.owner_id.is_none() {
+                if game.check_buy_building(city_id, building_id).is_err() {
+                    let Some(route) = network_city_ids
+                        .iter()
+                        .filter_map(|from_city_id| {
+                            planner
+                                .router
+                                .find_path(*from_city_id, city_id, Metric::TransportCost)
+                        })
+                        .min_by(|a, b| a.cost.total_cmp(&b.cost))
+                    else {
+                        continue;
+                    };
+                    add_connection_purchases(game, &route.connection_ids, &mut purchases);
+                }
+                purchases.push(Command::BuyBuilding {
+                    city_id,
+                    building_id,
+                });
+            } else if building.owner_id != Some(business_id) {
+                continue;
+            } else if let (Some(gain), Ok(cost)) = (
+                planner.plan_upgrade((city_id, building_id)),
+                game.check_upgrade_building(city_id, building_id),
+            ) {
+                if cost <= budget {
+                    candidates.push((
+                        gain / cost as f32,
+                        vec![Command::UpgradeBuilding {
+                            city_id,
+                            building_id,
+                        }],
+                    ));
+                }
+            }
+            let Some(plan) = planner.plan_production((city_id, building_id), purchases) else {
+                continue;
+            };
+            let cost = plan.get_purchase_cost(game);
+            if cost <= 0 || cost > budget {
+                continue;
+            }
+            let estimate = (plan.profit - plan.get_upkeep(game) as f32) / cost as f32;
+            let is_new = candidates.iter().all(|(_, purchases)| {
+                purchases.len() != plan.purchases.len()
+                    || !plan
+                        .purchases
+                        .iter()
+                        .all(|command| purchases.contains(command))
+            });
+            if is_new {
+                candidates.push((estimate, plan.purchases));
+            }
+        }
+    }
+    candidates.retain(|(estimate, _)| *estimate >= strategy.min_return);
+    candidates.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    candidates.truncate(CANDIDATE_PLANS);
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let mut current = game.clone();
+    schedule_production(&mut current, strategy);
+    let money_in = get_money_in(&current);
+    let mut best: Option<(f32, Vec<Command>)> = None;
+    for (_, purchases) in candidates {
+        let cost: i32 = purchases
+            .iter()
+            .map(|command| get_purchase_cost(game, command))
+            .sum();
+        let mut trial = game.clone();
+        if !buy_all(&mut trial, purchases.clone()) {
+            continue;
+        }
+        schedule_production(&mut trial, strategy);
+        let score = (get_money_in(&trial) - money_in) as f32 / cost as f32;
+        if score >= strategy.min_return
+            && best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score > *best_score)
+        {
+            best = Some((score, purchases));
+        }
+    }
+    best.map(|(_, purchases)| purchases)
+}
+
+/// Executes every purchase of a plan, each once it connects to the network. Returns whether all
+/// of them went through.
+fn buy_all(game: &mut Game, mut purchases: Vec<Command>) -> bool {
+    while !purchases.is_empty() {
+        let Some(index) = purchases
+            .iter()
+            .position(|command| game.execute(command.clone()).is_ok())
+        else {
+            return false;
+        };
+        purchases.remove(index);
+    }
+    true
+}
+
+/// A way to run production at some scale.
+#[derive(Clone)]
+struct Plan {
+    /// Expected profit per round after market prices at the planned volume, shipping and upkeep
+    /// that grows with scale.
+    profit: f32,
+    /// Scale it runs at, below the one asked for when its supplies or buyers cannot keep up.
+    scale: f32,
+    /// Buildings and connections that still have to be bought for it.
+    purchases: Vec<Command>,
+}
+
+impl Plan {
+    fn get_purchase_cost(&self, game: &Game) -> i32 {
+        self.purchases
+            .iter()
+            .map(|command| get_purchase_cost(game, command))
+            .sum()
+    }
+
+    /// Upkeep the purchases cost every round even when idle.
+    fn get_upkeep(&self, game: &Game) -> i32 {
+        self.purchases
+            .iter()
+            .map(|command| match command {
+                Command::BuyBuilding {
+                    city_id,
+                    building_id,
+                } => {
+                    let building = game.graph.cities[*city_id]
+                        .get_building(*building_id)
+                        .unwrap();
+                    game.registry.building_types[&building.building_type].upkeep
+                }
+                Command::BuyConnection { connection_id } => {
+                    game.graph.connections[*connection_id].maintenance_cost
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn add_purchase(&mut self, command: Command) {
+        if !self.purchases.contains(&command) {
+            self.purchases.push(command);
+        }
+    }
+}
+
+fn get_purchase_cost(game: &Game, command: &Command) -> i32 {
+    match command {
+        Command::BuyBuilding {
+            city_id,
+            building_id,
+        } => {
+            game.graph.cities[*city_id]
+                .get_building(*building_id)
+                .unwrap()
+                .acquisition_cost
+        }
+        Command::BuyConnection { connection_id } => {
+            game.graph.connections[*connection_id].acquisition_cost
+        }
+        _ => 0,
+    }
+}
+
+fn add_connection_purchases(game: &Game, connection_ids: &[usize], purchases: &mut Vec<Command>) {
+    for connection_id in connection_ids.iter().copied() {
+        let command = Command::BuyConnection { connection_id };
+        if game.graph.connections[connection_id].owner_id.is_none() && !purchases.contains(&command)
+        {
+            purchases.push(command);
+        }
+    }
+}
+
+struct Link {
+    /// What a unit is worth to the recipe once shipped: positive for outputs, negative for inputs.
+    /// Markets count at the price they settle at under the planned volume, less their upkeep.
+    value: f32,
+    /// Units per round the other end and the route can handle.
+    capacity: f32,
+    connection_ids: Vec<usize>,
+    purchases: Vec<Command>,
+}
+
+type RecipePlanKey = ((usize, usize), ValidRecipe, i32, usize, Option<MaterialId>);
+
+/// Works out where production can get its inputs and send its outputs, over the connections
+/// `router` allows. Unless `buys` is set, only buildings the business already owns are used.
+struct Planner<'a> {
+    game: &'a Game,
+    router: Router<'a>,
+    buys: bool,
+    recipe_plans: RefCell<HashMap<RecipePlanKey, Option<Plan>>>,
+}
+
+impl<'a> Planner<'a> {
+    fn new(game: &'a Game, buys_buildings: bool, buys_connections: bool) -> Self {
+        let business_id = game.current_player_id;
+        Self {
+            game,
+            router: Router::new(&game.graph, move |connection_id, owned_connection| {
+                game.graph.can_use_connection(business_id, connection_id)
+                    || (buys_connections && owned_connection.owner_id.is_none())
+            }),
+            buys: buys_buildings,
+            recipe_plans: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Most profitable recipe of a production building at its best scale, after `purchases`.
+    fn plan_production(
+        &self,
+        building_id: (usize, usize),
+        purchases: Vec<Command>,
+    ) -> Option<Plan> {
+        let building = self.game.graph.cities[building_id.0]
+            .get_building(building_id.1)
+            .unwrap();
+        building
+            .production_scale
+            .iter()
+            .filter_map(|scaled_valid_recipe| {
+                let mut plan = self.plan_best_scale(
+                    building_id,
+                    &scaled_valid_recipe.valid_recipe,
+                    scaled_valid_recipe.max_scale,
+                    SUPPLY_CHAIN_DEPTH,
+                )?;
+                let mut purchases = purchases.clone();
+                for purchase in plan.purchases {
+                    if !purchases.contains(&purchase) {
+                        purchases.push(purchase);
+                    }
+                }
+                plan.purchases = purchases;
+                Some(plan)
+            })
+            .max_by(|a, b| a.profit.total_cmp(&b.profit))
+    }
+
+    /// Extra profit per round the next upgrade of an owned building would allow.
+    fn plan_upgrade(&self, building_id: (usize, usize)) -> Option<f32> {
+        let building = self.game.graph.cities[building_id.0]
+            .get_building(building_id.1)
+            .unwrap();
+        let upgrade = building.get_next_upgrade(&self.game.registry)?;
+        let get_profit = |valid_recipe: &ValidRecipe, max_scale: i32| {
+            let plan =
+                self.plan_best_scale(building_id, valid_recipe, max_scale, SUPPLY_CHAIN_DEPTH)?;
+            Some(plan.profit)
+        };
+        building
+            .production_scale
+            .iter()
+            .filter_map(|scaled_valid_recipe| {
+                let valid_recipe = &scaled_valid_recipe.valid_recipe;
+                let upgraded = get_profit(valid_recipe, upgrade.max_scale)?;
+                Some(
+                    upgraded
+                        - get_profit(valid_recipe, scaled_valid_recipe.max_scale).unwrap_or(0.0),
+                )
+            })
+            .max_by(|a, b| a.total_cmp(b))
+    }
+
+    /// Most profitable scale up to `max_scale` to run a production recipe at. Prices settle lower
+    /// the more a market is sold, so smaller scales can pay where the largest does not.
+    fn plan_best_scale(
+        &self,
+        building_id: (usize, usize),
+        valid_recipe: &ValidRecipe,
+        max_scale: i32,
+        depth: usize,
+    ) -> Option<Plan> {
+        (1..=max_scale)
+            .filter_map(|scale| self.plan_recipe(building_id, valid_recipe, scale, depth, None))
+            .max_by(|a, b| a.profit.total_cmp(&b.profit))
+    }
+
+    /// Profitable way to run a production recipe at `scale`, counting its building as owned.
+    /// Other production can supply or take materials if it can itself run within `depth` more
+    /// steps. A `linked` material is supplied or taken by the production asking for the plan, so
+    /// it is left out and the plan is kept even at a loss for the asker to weigh.
+    fn plan_recipe(
+        &self,
+        building_id: (usize, usize),
+        valid_recipe: &ValidRecipe,
+        scale: i32,
+        depth: usize,
+        linked: Option<&MaterialId>,
+    ) -> Option<Plan> {
+        if !matches!(valid_recipe, ValidRecipe::Production(_)) {
+            return None;
+        }
+        let key = (
+            building_id,
+            valid_recipe.clone(),
+            scale,
+            depth,
+            linked.cloned(),
+        );
+        if let Some(plan) = self.recipe_plans.borrow().get(&key) {
+            return plan.clone();
+        }
+        let plan = self.plan_recipe_uncached(building_id, valid_recipe, scale, depth, linked);
+        self.recipe_plans.borrow_mut().insert(key, plan.clone());
+        plan
+    }
+
+    fn plan_recipe_uncached(
+        &self,
+        building_id: (usize, usize),
+        valid_recipe: &ValidRecipe,
+        scale: i32,
+        depth: usize,
+        linked: Option<&MaterialId>,
+    ) -> Option<Plan> {
+        let building = self.game.graph.cities[building_id.0]
+            .get_building(building_id.1)
+            .unwrap();
+        let mut plan = Plan {
+            profit: -self.game.registry.building_types[&building.building_type].upkeep_per_scale,
+            scale: scale as f32,
+            purchases: vec![],
+        };
+        for (material, quantity) in self.game.registry.get_recipe(valid_recipe).materials {
+            if linked == Some(&material) {
+                continue;
+            }
+            if material.is_money() {
+                plan.profit += quantity as f32;
+                continue;
+            }
+            let units = (quantity.abs() * scale) as f32;
+            let link = self.find_link(building_id, &material, quantity < 0, units, depth)?;
+            plan.profit += quantity.abs() as f32 * link.value;
+            plan.scale = plan.scale.min(link.capacity / quantity.abs() as f32);
+            add_connection_purchases(self.game, &link.connection_ids, &mut plan.purchases);
+            for purchase in link.purchases {
+                plan.add_purchase(purchase);
+            }
+        }
+        plan.profit *= plan.scale;
+        (linked.is_some() || plan.profit > 0.0).then_some(plan)
+    }
+
+    /// Best place within reach to source (`input`) or get rid of `units` of a material every
+    /// round. What would have to be bought for it counts against it as if paid off over
+    /// `PAYBACK_ROUNDS` rounds.
+    fn find_link(
+        &self,
+        building_id: (usize, usize),
+        material: &MaterialId,
+        input: bool,
+        units: f32,
+        depth: usize,
+    ) -> Option<Link> {
+        let game = self.game;
+        let business_id = game.current_player_id;
+        let registry = &game.registry;
+        let quantity = registry.get_market_price(material).quantity as f32;
+        let lots = (units / quantity).ceil() as i32;
+        let mut best: Option<(f32, Link)> = None;
+        for (other_city_id, city) in game.graph.cities.iter().enumerate() {
+            let mut route_opt = None;
+            for (other_building_id, other) in city.get_buildings() {
+                let other_id = (other_city_id, other_building_id);
+                let owned = other.owner_id == Some(business_id) || other_id == building_id;
+                if !owned && (other.owner_id.is_some() || !self.buys) {
+                    continue;
+                }
+                // Markets are paid what they settle at once this volume trades there on top of
+                // what they already do, and their upkeep grows with every lot.
+                let market_upkeep =
+                    registry.building_types[&other.building_type].upkeep_per_scale / quantity;
+                for scaled_valid_recipe in &other.production_scale {
+                    let valid_recipe = &scaled_valid_recipe.valid_recipe;
+                    let max_scale = scaled_valid_recipe.max_scale as f32;
+                    let (unit_price, mut capacity, mut purchases) = match valid_recipe {
+                        ValidRecipe::MaterialImport(imported) if input && imported == material => (
+                            other.get_unit_price(registry, material, true, lots) + market_upkeep,
+                            max_scale * quantity,
+                            vec![],
+                        ),
+                        ValidRecipe::MaterialExport(exported) if !input && exported == material => {
+                            (
+                                other.get_unit_price(registry, material, false, lots)
+                                    - market_upkeep,
+                                max_scale * quantity,
+                                vec![],
+                            )
+                        }
+                        ValidRecipe::Production(_)
+                            if depth > 0
+                                && other_id != building_id
+                                && get_yield(registry, valid_recipe, material).signum()
+                                    == if input { 1 } else { -1 } =>
+                        {
+                            // The other end is worth what the rest of its recipe earns or costs per
+                            // unit it takes or supplies.
+                            let Some(plan) = self.plan_recipe(
+                                other_id,
+                                valid_recipe,
+                                scaled_valid_recipe.max_scale,
+                                depth - 1,
+                                Some(material),
+                            ) else {
+                                continue;
+                            };
+                            let other_quantity = get_yield(registry, valid_recipe, material).abs();
+                            let other_units = plan.scale * other_quantity as f32;
+                            if other_units <= 0.0 {
+                                continue;
+                            }
+                            let unit_value = plan.profit / other_units;
+                            (
+                                if input { -unit_value } else { unit_value },
+                                other_units,
+                                plan.purchases,
+                            )
+                        }
+                        _ => continue,
+                    };
+                    if route_opt.is_none() {
+                        route_opt = Some(self.router.find_path(
+                            building_id.0,
+                            other_city_id,
+                            Metric::TransportCost,
+                        ));
+                    }
+                    let Some(Some(route)) = &route_opt else {
+                        break;
+                    };
+                    if !owned {
+                        purchases.push(Command::BuyBuilding {
+                            city_id: other_city_id,
+                            building_id: other_building_id,
+                        });
+                    }
+                    add_connection_purchases(game, &route.connection_ids, &mut purchases);
+                    for connection_id in &route.connection_ids {
+                        let spare = game.graph.connections[*connection_id].capacity
+                            - game.graph.get_connection_load(*connection_id);
+                        capacity = capacity.min(spare as f32);
+                    }
+                    let value = if input {
+                        -unit_price - route.cost
+                    } else {
+                        unit_price - route.cost
+                    };
+                    let purchase_cost: i32 = purchases
+                        .iter()
+                        .map(|command| get_purchase_cost(game, command))
+                        .sum();
+                    let rank = value * units.min(capacity) - purchase_cost as f32 / PAYBACK_ROUNDS;
+                    if best.as_ref().is_none_or(|(best_rank, _)| rank > *best_rank) {
+                        best = Some((
+                            rank,
+                            Link {
+                                value,
+                                capacity,
+                                connection_ids: route.connection_ids.clone(),
+                                purchases,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        best.map(|(_, link)| link)
+    }
+}
+
+struct OwnedRecipe {
+    city_id: usize,
+    building_id: usize,
+    recipe_id: usize,
+    valid_recipe: ValidRecipe,
+    max_scale: i32,
+}
+
+enum Change<'a> {
+    Scale(&'a OwnedRecipe),
+    Shipment {
+        material: MaterialId,
+        from_city_id: usize,
+        to_city_id: usize,
+        quantity: i32,
+    },
+}
+
+/// Shuts everything down and ramps profitable production back up, feeding it from owned markets
+/// and surplus anywhere in the network and shipping whatever is left over to be sold.
+fn schedule_production(game: &mut Game, strategy: &Strategy) {
+    let business_id = game.current_player_id;
+    let mut owned_recipes = vec![];
+    for (city_id, city) in game.graph.cities.iter().enumerate() {
+        for (building_id, building) in city.get_buildings() {
+            if building.owner_id != Some(business_id) {
+                continue;
+            }
+            for (recipe_id, scaled_valid_recipe) in building.production_scale.iter().enumerate() {
+                owned_recipes.push(OwnedRecipe {
+                    city_id,
+                    building_id,
+                    recipe_id,
+                    valid_recipe: scaled_valid_recipe.valid_recipe.clone(),
+                    max_scale: match scaled_valid_recipe.valid_recipe {
+                        ValidRecipe::Production(_) => (scaled_valid_recipe.max_scale as f32
+                            * strategy.scale_share)
+                            .round() as i32,
+                        _ => scaled_valid_recipe.max_scale,
+                    },
+                });
+            }
+        }
+    }
+
+    // Lowering a recipe or shipment is refused while others still depend on its output, so
+    // repeat until nothing more can be shut down.
+    loop {
+        let mut changed = false;
+        for shipment_id in (0..game.graph.businesses[business_id].shipments.len()).rev() {
+            let command = Command::SetShipmentQuantity {
+                shipment_id,
+                quantity: 0,
+            };
+            changed |= game.execute(command).is_ok();
+        }
+        for owned_recipe in &owned_recipes {
+            if get_scale(game, owned_recipe) != 0 {
+                changed |= set_scale(game, owned_recipe, 0).is_ok();
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // Only recipes that pay off at the prices their volume settles at, with the assets already
+    // owned, are run.
+    let planner = Planner::new(game, false, false);
+    let mut production: Vec<(f32, &OwnedRecipe)> = owned_recipes
+        .iter()
+        .filter_map(|owned_recipe| {
+            let plan = planner.plan_best_scale(
+                (owned_recipe.city_id, owned_recipe.building_id),
+                &owned_recipe.valid_recipe,
+                owned_recipe.max_scale,
+                SUPPLY_CHAIN_DEPTH,
+            )?;
+            Some((plan.profit, owned_recipe))
+        })
+        .collect();
+    production.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    // The second pass picks up inputs that only became available once later recipes ran.
+    for _ in 0..2 {
+        for (_, owned_recipe) in &production {
+            while increase_with_supplies(game, &owned_recipes, owned_recipe) {}
+        }
+    }
+
+    for city_id in game.graph.get_network_city_ids(business_id) {
+        let resource_stock =
+            game.graph
+                .get_resource_stock(&game.registry, business_id, Some(city_id));
+        for (material, quantity_info) in resource_stock {
+            if material.is_money() || quantity_info.quantity + quantity_info.net_in() <= 0 {
+                continue;
+            }
+            let quantity = game.registry.get_market_price(&material).quantity;
+            let mut exports: Vec<&OwnedRecipe> = owned_recipes
+                .iter()
+                .filter(|owned_recipe| {
+                    owned_recipe.valid_recipe == ValidRecipe::MaterialExport(material.clone())
+                })
+                .collect();
+            exports.sort_by_key(|export| export.city_id != city_id);
+            for export in exports {
+                loop {
+                    let money_in = get_money_in(game);
+                    let mut changes = vec![];
+                    if export.city_id != city_id {
+                        match add_to_shipment(game, &material, city_id, export.city_id, quantity) {
+                            Ok(change) => changes.push(change),
+                            Err(_) => break,
+                        }
+                    }
+                    match raise(game, export) {
+                        Ok(change) => changes.push(change),
+                        Err(_) => {
+                            undo(game, changes);
+                            break;
+                        }
+                    }
+                    // Surplus is only worth selling where the price covers the shipping.
+                    if get_money_in(game) <= money_in {
+                        undo(game, changes);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Production was valued as if all output could be sold. Cut back whatever only piles up
+    // stock, along with the supplies it no longer needs.
+    loop {
+        let mut changed = false;
+        // Shipping costs money, so a shipment is lowered whenever its destination can spare it.
+        for shipment_id in (0..game.graph.businesses[business_id].shipments.len()).rev() {
+            let quantity = game.graph.businesses[business_id].shipments[shipment_id].quantity;
+            let command = Command::SetShipmentQuantity {
+                shipment_id,
+                quantity: quantity - 1,
+            };
+            changed |= game.execute(command).is_ok();
+        }
+        for owned_recipe in owned_recipes.iter().rev() {
+            let scale = get_scale(game, owned_recipe);
+            if scale == 0 {
+                continue;
+            }
+            let set_recipe_scale = |scale| Command::SetRecipeScale {
+                city_id: owned_recipe.city_id,
+                building_id: owned_recipe.building_id,
+                recipe_id: owned_recipe.recipe_id,
+                scale,
+            };
+            changed |=
+                lower_if_unprofitable(game, set_recipe_scale(scale - 1), set_recipe_scale(scale));
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Applies `lower` unless it costs money per round and `restore` can still revert it.
+fn lower_if_unprofitable(game: &mut Game, lower: Command, restore: Command) -> bool {
+    let money_in = get_money_in(game);
+    if game.execute(lower).is_err() {
+        return false;
+    }
+    !(get_money_in(game) < money_in && game.execute(restore).is_ok())
+}
+
+/// Raises a recipe by one, first arranging supplies of each input it is short of. Everything is
+/// rolled back if some input cannot be supplied or the supplies cost more than the recipe yields.
+fn increase_with_supplies(
+    game: &mut Game,
+    owned_recipes: &[OwnedRecipe],
+    owned_recipe: &OwnedRecipe,
+) -> bool {
+    let flow_value = get_flow_value(game);
+    let Some(changes) = raise_with_supplies(game, owned_recipes, owned_recipe, SUPPLY_CHAIN_DEPTH)
+    else {
+        return false;
+    };
+    if get_flow_value(game) > flow_value {
+        return true;
+    }
+    undo(game, changes);
+    false
+}
+
+/// Raises a recipe by one along with whatever supplies it, or changes nothing if some input cannot
+/// be supplied. Owned production can supply inputs if it can itself be supplied within `depth`
+/// more steps.
+fn raise_with_supplies<'a>(
+    game: &mut Game,
+    owned_recipes: &'a [OwnedRecipe],
+    owned_recipe: &'a OwnedRecipe,
+    depth: usize,
+) -> Option<Vec<Change<'a>>> {
+    let mut changes = vec![];
+    loop {
+        let material = match raise(game, owned_recipe) {
+            Ok(change) => {
+                changes.push(change);
+                return Some(changes);
+            }
+            Err(GameError::InsufficientResources(material)) if !material.is_money() => material,
+            Err(_) => break,
+        };
+        match supply(game, owned_recipes, owned_recipe.city_id, &material, depth) {
+            Some(supply_changes) => changes.extend(supply_changes),
+            None => break,
+        }
+    }
+    undo(game, changes);
+    None
+}
+
+/// Adds one lot of a material to a city's supply, preferring a local market, then surplus
+/// elsewhere in the network, then owned production in the city, then a remote market.
+fn supply<'a>(
+    game: &mut Game,
+    owned_recipes: &'a [OwnedRecipe],
+    city_id: usize,
+    material: &MaterialId,
+    depth: usize,
+) -> Option<Vec<Change<'a>>> {
+    let mut imports: Vec<&OwnedRecipe> = owned_recipes
+        .iter()
+        .filter(|owned_recipe| {
+            owned_recipe.valid_recipe == ValidRecipe::MaterialImport(material.clone())
+        })
+        .collect();
+    imports.sort_by_key(|import| import.city_id != city_id);
+    if let Some(import) = imports.first().filter(|import| import.city_id == city_id) {
+        if let Ok(change) = raise(game, import) {
+            return Some(vec![change]);
+        }
+    }
+    for from_city_id in game.graph.get_network_city_ids(game.current_player_id) {
+        if from_city_id == city_id {
+            continue;
+        }
+        if let Ok(change) = add_to_shipment(game, material, from_city_id, city_id, 1) {
+            return Some(vec![change]);
+        }
+    }
+    if depth > 0 {
+        let producers: Vec<&OwnedRecipe> = owned_recipes
+            .iter()
+            .filter(|owned_recipe| {
+                owned_recipe.city_id == city_id
+                    && matches!(owned_recipe.valid_recipe, ValidRecipe::Production(_))
+                    && game
+                        .registry
+                        .get_recipe(&owned_recipe.valid_recipe)
+                        .materials
+                        .iter()
+                        .any(|(output, quantity)| output == material && *quantity > 0)
+            })
+            .collect();
+        for producer in producers {
+            if let Some(changes) = raise_with_supplies(game, owned_recipes, producer, depth - 1) {
+                return Some(changes);
+            }
+        }
+    }
+    let quantity = game.registry.get_market_price(material).quantity;
+    for import in imports {
+        if import.city_id == city_id {
+            continue;
+        }
+        let Ok(import_change) = raise(game, import) else {
+            continue;
+        };
+        match add_to_shipment(game, material, import.city_id, city_id, quantity) {
+            Ok(shipment_change) => return Some(vec![import_change, shipment_change]),
+            Err(_) => undo(game, vec![import_change]),
+        }
+    }
+    None
+}
+
+/// Money earned per round after upkeep plus the other materials made per round, valued at market
+/// sell prices.
+fn get_flow_value(game: &Game) -> f32 {
+    let sell_factor = 1.0 - game.registry.market.spread / 2.0;
+    let materials_value: f32 = game
+        .graph
+        .get_resource_stock(&game.registry, game.current_player_id, None)
+        .iter()
+        .filter(|(material, _)| !material.is_money())
+        .map(|(material, quantity_info)| {
+            let value = game.graph.get_market_value(&game.registry, material);
+            quantity_info.net_in() as f32 * value * sell_factor
+        })
+        .sum();
+    get_money_in(game) as f32 + materials_value
+}
+
+/// Money earned per round after upkeep, once the owned markets have moved their prices to where
+/// the scheduled volumes settle them.
+fn get_money_in(game: &Game) -> i32 {
+    let business_id = game.current_player_id;
+    let registry = &game.registry;
+    let price_impact: i32 = game
+        .graph
+        .cities
+        .iter()
+        .flat_map(|city| city.owned_buildings.iter().flatten())
+        .filter(|building| building.owner_id == Some(business_id))
+        .flat_map(|building| {
+            building
+                .production_scale
+                .iter()
+                .map(move |scaled_valid_recipe| (building, scaled_valid_recipe))
+        })
+        .map(|(building, scaled_valid_recipe)| {
+            let settled_price = |material| {
+                building.get_settled_price(registry, material, building.get_net_demand(material))
+            };
+            let change = match &scaled_valid_recipe.valid_recipe {
+                ValidRecipe::MaterialImport(material) => {
+                    building.get_buy_price(registry, material)
+                        - registry.market.get_buy_price(settled_price(material))
+                }
+                ValidRecipe::MaterialExport(material) => {
+                    registry.market.get_sell_price(settled_price(material))
+                        - building.get_sell_price(registry, material)
+                }
+                ValidRecipe::Production(_) => 0,
+            };
+            change * scaled_valid_recipe.scale
+        })
+        .sum();
+    game.graph.get_resource_stock(registry, business_id, None)[&MaterialId::money()].net_in()
+        + price_impact
+        - game.graph.get_upkeep(registry, business_id)
+}
+
+fn raise<'a>(game: &mut Game, owned_recipe: &'a OwnedRecipe) -> Result<Change<'a>, GameError> {
+    let scale = get_scale(game, owned_recipe) + 1;
+    if scale > owned_recipe.max_scale {
+        return Err(GameError::ScaleOutOfRange {
+            scale,
+            max_scale: owned_recipe.max_scale,
+        });
+    }
+    set_scale(game, owned_recipe, scale)?;
+    Ok(Change::Scale(owned_recipe))
+}
+
+fn add_to_shipment<'a>(
+    game: &mut Game,
+    material: &MaterialId,
+    from_city_id: usize,
+    to_city_id: usize,
+    quantity: i32,
+) -> Result<Change<'a>, GameError> {
+    let existing = game.graph.businesses[game.current_player_id]
+        .shipments
+        .iter()
+        .enumerate()
+        .find(|(_, shipment)| {
+            shipment.material == *material
+                && shipment.from_city_id == from_city_id
+                && shipment.to_city_id == to_city_id
+        })
+        .map(|(shipment_id, shipment)| (shipment_id, shipment.quantity));
+    game.execute(match existing {
+        Some((shipment_id, current)) => Command::SetShipmentQuantity {
+            shipment_id,
+            quantity: current + quantity,
+        },
+        None => Command::AddShipment {
+            material: material.clone(),
+            from_city_id,
+            to_city_id,
+            quantity,
+        },
+    })?;
+    Ok(Change::Shipment {
+        material: material.clone(),
+        from_city_id,
+        to_city_id,
+        quantity,
+    })
+}
+
+fn undo(game: &mut Game, changes: Vec<Change>) {
+    for change in changes.into_iter().rev() {
+        let _ = match change {
+            Change::Scale(owned_recipe) => {
+                let scale = get_scale(game, owned_recipe) - 1;
+                set_scale(game, owned_recipe, scale)
+            }
+            Change::Shipment {
+                material,
+                from_city_id,
+                to_city_id,
+                quantity,
+            } => add_to_shipment(game, &material, from_city_id, to_city_id, -quantity).map(|_| ()),
+        };
+    }
+}
+
+fn get_scale(game: &Game, owned_recipe: &OwnedRecipe) -> i32 {
+    game.graph.cities[owned_recipe.city_id]
+        .get_building(owned_recipe.building_id)
+        .unwrap()
+        .production_scale[owned_recipe.recipe_id]
+        .scale
+}
+
+fn set_scale(game: &mut Game, owned_recipe: &OwnedRecipe, scale: i32) -> Result<(), GameError> {
+    game.execute(Command::SetRecipeScale {
+        city_id: owned_recipe.city_id,
+        building_id: owned_recipe.building_id,
+        recipe_id: owned_recipe.recipe_id,
+        scale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing;
+
+    /// Oil drilled, refined and burnt for energy in one city pays off without shipping.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(
+            &[(
+                0.0,
+                0.0,
+                &["OilRig", "OilRefinery", "OilEnergyPlant", "EnergyMarket"],
+            )],
+            &[],
+        );
+        for business in &mut game.graph.businesses {
+            business.money = 1000;
+        }
+        game
+    }
+
+    fn is_producing(game: &Game, business_id: usize) -> bool {
+        game.graph.cities.iter().any(|city| {
+            city.get_buildings().any(|(_, building)| {
+                building.owner_id == Some(business_id)
+                    && building.production_scale.iter().any(|scaled_valid_recipe| {
+                        matches!(scaled_valid_recipe.valid_recipe, ValidRecipe::Production(_))
+                            && scaled_valid_recipe.scale > 0
+                    })
+            })
+        })
+    }
+
+    #[test]
+    fn ai_buys_a_supply_chain_and_runs_it() {
+        let mut game = new_game();
+        for _ in 0..10 {
+            play_turn(&mut game, Difficulty::Hard);
+        }
+        assert!(is_producing(&game, 0));
+        let money = game.graph.businesses[0].money;
+        for _ in 0..20 {
+            play_turn(&mut game, Difficulty::Hard);
+        }
+        assert!(is_producing(&game, 0));
+        assert!(game.graph.businesses[0].money > money);
+    }
+}
@@ -0,0 +1,211 @@
+use super::{
+    game::Game,
+    registry::{ConstructionDef, MaterialId},
+    City, Graph, OwnedBuilding, OwnedConnection,
+};
+use serde::{Deserialize, Serialize};
+
+/// Building slots per city.
+pub const MAX_BUILDINGS: usize = 6;
+/// Closest a new connection may pass by a city it does not join.
+pub const CITY_CLEARANCE: f32 = 50.0;
+
+/// What a new connection costs for every started `segment_length` of distance between its cities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionConstructionParams {
+    pub segment_length: f32,
+    pub cost: i32,
+    pub rounds: u32,
+    #[serde(default)]
+    pub materials: Vec<(MaterialId, i32)>,
+}
+
+impl Default for ConnectionConstructionParams {
+    fn default() -> Self {
+        Self {
+            segment_length: 100.0,
+            cost: 25,
+            rounds: 1,
+            materials: vec![],
+        }
+    }
+}
+
+impl ConnectionConstructionParams {
+    /// Money and construction needs of a connection of the given length.
+    pub fn get_costs(&self, length: f32) -> (i32, ConstructionDef) {
+        let segments = (length / self.segment_length).ceil().max(1.0) as i32;
+        (
+            self.cost * segments,
+            ConstructionDef {
+                rounds: self.rounds * segments as u32,
+                materials: self
+                    .materials
+                    .iter()
+                    .map(|(material, quantity)| (material.clone(), quantity * segments))
+                    .collect(),
+            },
+        )
+    }
+}
+
+impl City {
+    pub fn has_free_slot(&self) -> bool {
+        self.get_buildings().count() < MAX_BUILDINGS
+    }
+
+    /// The first empty slot, or a new one after the last.
+    pub fn get_free_building_id(&self) -> usize {
+        self.owned_buildings
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.owned_buildings.len())
+    }
+
+    pub fn place_building(&mut self, building_id: usize, owned_building: OwnedBuilding) {
+        if building_id == self.owned_buildings.len() {
+            self.owned_buildings.push(None);
+        }
+        self.owned_buildings[building_id] = Some(owned_building);
+    }
+}
+
+impl OwnedBuilding {
+    pub fn is_under_construction(&self) -> bool {
+        self.construction_rounds > 0
+    }
+}
+
+impl OwnedConnection {
+    pub fn is_under_construction(&self) -> bool {
+        self.construction_rounds > 0
+    }
+}
+
+impl Graph {
+    pub fn find_connection(&self, city_id_a: usize, city_id_b: usize) -> Option<usize> {
+        self.connections.iter().position(|owned_connection| {
+            owned_connection.city_ids.contains(&city_id_a)
+                && owned_connection.city_ids.contains(&city_id_b)
+        })
+    }
+
+    /// A connection that a straight edge between the two cities would cross. Edges that only
+    /// share a city do not cross.
+    pub fn find_crossed_connection(&self, city_id_a: usize, city_id_b: usize) -> Option<usize> {
+        let a = self.get_city_position(city_id_a);
+        let b = self.get_city_position(city_id_b);
+        self.connections.iter().position(|owned_connection| {
+            let [city_id_c, city_id_d] =
+                [owned_connection.city_ids[0], owned_connection.city_ids[1]];
+            if [city_id_c, city_id_d]
+                .iter()
+                .any(|city_id| *city_id == city_id_a || *city_id == city_id_b)
+            {
+                return false;
+            }
+            let c = self.get_city_position(city_id_c);
+            let d = self.get_city_position(city_id_d);
+            orientation(a, b, c) * orientation(a, b, d) < 0.0
+                && orientation(c, d, a) * orientation(c, d, b) < 0.0
+        })
+    }
+
+    /// A city that a straight edge between the two cities would pass within `CITY_CLEARANCE` of.
+    pub fn find_city_in_the_way(&self, city_id_a: usize, city_id_b: usize) -> Option<usize> {
+        let a = self.get_city_position(city_id_a);
+        let b = self.get_city_position(city_id_b);
+        let length_squared = (b.0 - a.0).powi(2) + (b.1 - a.1).powi(2);
+        (0..self.cities.len())
+            .filter(|city_id| *city_id != city_id_a && *city_id != city_id_b)
+            .find(|city_id| {
+                let c = self.get_city_position(*city_id);
+                let t = (((c.0 - a.0) * (b.0 - a.0) + (c.1 - a.1) * (b.1 - a.1)) / length_squared)
+                    .clamp(0.0, 1.0);
+                let closest = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+                (c.0 - closest.0).powi(2) + (c.1 - closest.1).powi(2)
+                    < CITY_CLEARANCE * CITY_CLEARANCE
+            })
+    }
+
+    fn get_city_position(&self, city_id: usize) -> (f32, f32) {
+        let city = &self.cities[city_id];
+        (city.x, city.y)
+    }
+}
+
+/// Positive when `c` lies to the left of the line from `a` to `b`, negative to the right.
+fn orientation(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Moves every construction site one round closer to completion.
+pub fn advance_construction(game: &mut Game) {
+    for city in &mut game.graph.cities {
+        for owned_building in city.owned_buildings.iter_mut().flatten() {
+            owned_building.construction_rounds =
+                owned_building.construction_rounds.saturating_sub(1);
+        }
+    }
+    for owned_connection in &mut game.graph.connections {
+        owned_connection.construction_rounds =
+            owned_connection.construction_rounds.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::testing;
+
+    #[test]
+    fn crossing_connections() {
+        let game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &[]),
+                (200.0, 0.0, &[]),
+                (100.0, -100.0, &[]),
+                (100.0, 100.0, &[]),
+                (0.0, 100.0, &[]),
+                (200.0, 100.0, &[]),
+                (300.0, -100.0, &[]),
+            ],
+            &[(0, 1)],
+        );
+        let graph = &game.graph;
+        assert_eq!(graph.find_crossed_connection(2, 3), Some(0));
+        assert_eq!(graph.find_crossed_connection(2, 5), Some(0));
+        assert_eq!(graph.find_crossed_connection(0, 3), None);
+        assert_eq!(graph.find_crossed_connection(4, 5), None);
+        assert_eq!(graph.find_crossed_connection(6, 5), None);
+    }
+
+    #[test]
+    fn cities_in_the_way() {
+        let game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &[]),
+                (400.0, 0.0, &[]),
+                (200.0, 40.0, &[]),
+                (200.0, -60.0, &[]),
+                (470.0, 0.0, &[]),
+            ],
+            &[],
+        );
+        let graph = &game.graph;
+        assert_eq!(graph.find_city_in_the_way(0, 1), Some(2));
+        assert_eq!(graph.find_city_in_the_way(0, 4), Some(1));
+        assert_eq!(graph.find_city_in_the_way(0, 3), None);
+        assert_eq!(graph.find_city_in_the_way(1, 4), None);
+    }
+
+    #[test]
+    fn connection_costs_grow_per_started_segment() {
+        let game = testing::new_game();
+        let params = &game.registry.connection_construction;
+        assert_eq!(params.get_costs(50.0).0, params.cost);
+        assert_eq!(params.get_costs(100.0).0, params.cost);
+        let (cost, construction) = params.get_costs(250.0);
+        assert_eq!(cost, 3 * params.cost);
+        assert_eq!(construction.rounds, 3 * params.rounds);
+    }
+}
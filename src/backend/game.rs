@@ -0,0 +1,1794 @@
+use super::{
+    ai::Controller,
+    ledger::Source,
+    logistics::Shipment,
+    map,
+    registry::{BuildingTypeId, ConstructionDef, MaterialId, Registry},
+    sale::{self, AssetId},
+    setup::GameSetup,
+    toll::MAX_TOLL,
+    trade::{Contract, Offer, Terms},
+    victory::{Outcome, WinCondition},
+    Business, Graph, OwnedBuilding, OwnedConnection, ScaledValidRecipe,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    BuyBuilding {
+        city_id: usize,
+        building_id: usize,
+    },
+    BuyConnection {
+        connection_id: usize,
+    },
+    UpgradeBuilding {
+        city_id: usize,
+        building_id: usize,
+    },
+    SellBuilding {
+        city_id: usize,
+        building_id: usize,
+    },
+    SellConnection {
+        connection_id: usize,
+    },
+    /// `None` closes the connection to other businesses. Closing it or raising the toll drops the
+    /// businesses paying it, as they agreed to the old toll.
+    SetToll {
+        connection_id: usize,
+        toll: Option<i32>,
+    },
+    StartPayingToll {
+        connection_id: usize,
+    },
+    StopPayingToll {
+        connection_id: usize,
+    },
+    /// Tears the building down without a refund to free its slot.
+    DemolishBuilding {
+        city_id: usize,
+        building_id: usize,
+    },
+    ConstructBuilding {
+        city_id: usize,
+        building_type: BuildingTypeId,
+    },
+    /// Materials are taken from the stock in `from_city_id`.
+    ConstructConnection {
+        from_city_id: usize,
+        to_city_id: usize,
+    },
+    SetRecipeScale {
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+        scale: i32,
+    },
+    SetRecipePriority {
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+        priority: i32,
+    },
+    AddShipment {
+        material: MaterialId,
+        from_city_id: usize,
+        to_city_id: usize,
+        quantity: i32,
+    },
+    SetShipmentQuantity {
+        shipment_id: usize,
+        quantity: i32,
+    },
+    ProposeTrade {
+        to_id: usize,
+        terms: Terms,
+    },
+    AcceptOffer {
+        offer_id: usize,
+    },
+    /// Replaces an offer with terms proposed back to its sender.
+    CounterOffer {
+        offer_id: usize,
+        terms: Terms,
+    },
+    /// Turns down an offer, or withdraws it when sent by the current player.
+    RejectOffer {
+        offer_id: usize,
+    },
+    EndTurn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameError {
+    InsufficientFunds { required: i32, available: i32 },
+    NotConnected,
+    AlreadyOwned { owner: String },
+    NotOwner,
+    FullyUpgraded,
+    UnderConstruction,
+    CityFull,
+    NotConstructible(BuildingTypeId),
+    AlreadyConnected,
+    CrossesConnection(usize),
+    CityInTheWay(usize),
+    ConnectionInUse,
+    NoToll,
+    AlreadyPayingToll,
+    NotPayingToll,
+    InvalidTrade,
+    NotYourOffer,
+    Bankrupt(String),
+    ScaleOutOfRange { scale: i32, max_scale: i32 },
+    InsufficientResources(MaterialId),
+    InvalidQuantity(i32),
+    NoRoute,
+    CapacityExceeded { connection_id: usize },
+    UnknownCity(usize),
+    UnknownBuilding(usize),
+    UnknownConnection(usize),
+    UnknownRecipe(usize),
+    UnknownShipment(usize),
+    UnknownBusiness(usize),
+    UnknownOffer(usize),
+    GameOver,
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientFunds {
+                required,
+                available,
+            } => write!(
+                f,
+                "Insufficient funds: {}$ required, {}$ available",
+                required, available
+            ),
+            Self::NotConnected => write!(f, "Not connected to your network"),
+            Self::AlreadyOwned { owner } => write!(f, "Already owned by {}", owner),
+            Self::NotOwner => write!(f, "Not owned by you"),
+            Self::FullyUpgraded => write!(f, "Already at the highest level"),
+            Self::UnderConstruction => write!(f, "Still under construction"),
+            Self::CityFull => write!(f, "No free building slot in this city"),
+            Self::NotConstructible(building_type) => {
+                write!(f, "{} cannot be constructed", building_type)
+            }
+            Self::AlreadyConnected => write!(f, "These cities are already connected"),
+            Self::CrossesConnection(connection_id) => {
+                write!(f, "Would cross connection {}", connection_id)
+            }
+            Self::CityInTheWay(city_id) => write!(f, "City {} is in the way", city_id),
+            Self::ConnectionInUse => write!(f, "Your shipments still use this connection"),
+            Self::NoToll => write!(f, "The owner does not open this connection to others"),
+            Self::AlreadyPayingToll => write!(f, "You already pay a toll for this connection"),
+            Self::NotPayingToll => write!(f, "You do not pay a toll for this connection"),
+            Self::InvalidTrade => write!(f, "A trade needs known materials on at least one side"),
+            Self::NotYourOffer => write!(f, "This offer is not addressed to you"),
+            Self::Bankrupt(name) => write!(f, "{} went bankrupt", name),
+            Self::ScaleOutOfRange { scale, max_scale } => {
+                write!(f, "Scale {} is outside 0..={}", scale, max_scale)
+            }
+            Self::InsufficientResources(material) => {
+                write!(f, "Insufficient {} to run at this scale", material)
+            }
+            Self::InvalidQuantity(quantity) => write!(f, "Invalid quantity {}", quantity),
+            Self::NoRoute => write!(f, "No route with spare capacity over your connections"),
+            Self::CapacityExceeded { connection_id } => {
+                write!(f, "Connection {} is at capacity", connection_id)
+            }
+            Self::UnknownCity(id) => write!(f, "Unknown city {}", id),
+            Self::UnknownBuilding(id) => write!(f, "Unknown building {}", id),
+            Self::UnknownConnection(id) => write!(f, "Unknown connection {}", id),
+            Self::UnknownRecipe(id) => write!(f, "Unknown recipe {}", id),
+            Self::UnknownShipment(id) => write!(f, "Unknown shipment {}", id),
+            Self::UnknownBusiness(id) => write!(f, "Unknown business {}", id),
+            Self::UnknownOffer(id) => write!(f, "Unknown offer {}", id),
+            Self::GameOver => write!(f, "The game is over"),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Game {
+    pub seed: u64,
+    pub registry: Registry,
+    pub graph: Graph,
+    pub current_player_id: usize,
+    #[serde(default = "first_round")]
+    pub round: u32,
+    #[serde(default)]
+    pub win_condition: WinCondition,
+    #[serde(default)]
+    pub outcome: Option<Outcome>,
+}
+
+fn first_round() -> u32 {
+    1
+}
+
+impl Game {
+    pub fn new(setup: &GameSetup, registry: Registry) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(setup.seed);
+        let mut graph = map::generate(&registry, &setup.map_size.get_params(), &mut rng);
+        graph.businesses = setup
+            .players
+            .iter()
+            .enumerate()
+            .map(|(player_id, player_setup)| {
+                Business::new(player_id, player_setup, setup.starting_money)
+            })
+            .collect();
+        Self {
+            seed: setup.seed,
+            win_condition: setup.win_condition,
+            outcome: None,
+            registry,
+            graph,
+            current_player_id: 0,
+            round: first_round(),
+        }
+    }
+
+    pub fn execute(&mut self, command: Command) -> Result<(), GameError> {
+        if self.outcome.is_some() {
+            return Err(GameError::GameOver);
+        }
+        match command {
+            Command::BuyBuilding {
+                city_id,
+                building_id,
+            } => {
+                self.check_buy_building(city_id, building_id)?;
+                let building = &mut self.graph.cities[city_id]
+                    .get_building_mut(building_id)
+                    .unwrap();
+                building.owner_id = Some(self.current_player_id);
+                building.acquired_round = self.round;
+                let cost = building.acquisition_cost;
+                self.pay(
+                    Source::Purchase(AssetId::Building {
+                        city_id,
+                        building_id,
+                    }),
+                    cost,
+                );
+            }
+            Command::BuyConnection { connection_id } => {
+                self.check_buy_connection(connection_id)?;
+                let owned_connection = &mut self.graph.connections[connection_id];
+                owned_connection.owner_id = Some(self.current_player_id);
+                owned_connection.acquired_round = self.round;
+                let cost = owned_connection.acquisition_cost;
+                self.pay(Source::Purchase(AssetId::Connection(connection_id)), cost);
+            }
+            Command::UpgradeBuilding {
+                city_id,
+                building_id,
+            } => {
+                let cost = self.check_upgrade_building(city_id, building_id)?;
+                self.graph.cities[city_id]
+                    .get_building_mut(building_id)
+                    .unwrap()
+                    .upgrade(&self.registry);
+                self.pay(
+                    Source::Upgrade(AssetId::Building {
+                        city_id,
+                        building_id,
+                    }),
+                    cost,
+                );
+            }
+            Command::SellBuilding {
+                city_id,
+                building_id,
+            } => {
+                let price = self.check_sell_building(city_id, building_id)?;
+                self.graph.cities[city_id]
+                    .get_building_mut(building_id)
+                    .unwrap()
+                    .release();
+                self.pay(
+                    Source::Sale(AssetId::Building {
+                        city_id,
+                        building_id,
+                    }),
+                    -price,
+                );
+            }
+            Command::SellConnection { connection_id } => {
+                let price = self.check_sell_connection(connection_id)?;
+                self.graph.release_connection(connection_id);
+                self.pay(Source::Sale(AssetId::Connection(connection_id)), -price);
+            }
+            Command::SetToll {
+                connection_id,
+                toll,
+            } => {
+                self.check_set_toll(connection_id, toll)?;
+                let owned_connection = &mut self.graph.connections[connection_id];
+                let revoke = toll.is_none() || toll > owned_connection.toll;
+                owned_connection.toll = toll;
+                if revoke {
+                    self.graph.revoke_tolls(connection_id);
+                }
+            }
+            Command::StartPayingToll { connection_id } => {
+                self.check_start_paying_toll(connection_id)?;
+                self.graph.businesses[self.current_player_id]
+                    .toll_connection_ids
+                    .insert(connection_id);
+            }
+            Command::StopPayingToll { connection_id } => {
+                self.check_stop_paying_toll(connection_id)?;
+                self.graph.businesses[self.current_player_id]
+                    .toll_connection_ids
+                    .remove(&connection_id);
+            }
+            Command::DemolishBuilding {
+                city_id,
+                building_id,
+            } => {
+                self.get_owned_building(city_id, building_id)?;
+                self.graph.remove_building(city_id, building_id);
+            }
+            Command::ConstructBuilding {
+                city_id,
+                building_type,
+            } => {
+                let ConstructionDef { rounds, materials } = self
+                    .check_construct_building(city_id, &building_type)?
+                    .clone();
+                let building_id = self.graph.cities[city_id].get_free_building_id();
+                let source = Source::Construction(AssetId::Building {
+                    city_id,
+                    building_id,
+                });
+                self.take_materials(source, city_id, materials);
+                let mut building = OwnedBuilding::new(&self.registry, building_type);
+                building.owner_id = Some(self.current_player_id);
+                building.construction_rounds = rounds;
+                building.acquired_round = self.round;
+                let cost = building.acquisition_cost;
+                self.graph.cities[city_id].place_building(building_id, building);
+                self.pay(source, cost);
+            }
+            Command::ConstructConnection {
+                from_city_id,
+                to_city_id,
+            } => {
+                let (cost, ConstructionDef { rounds, materials }) =
+                    self.check_construct_connection(from_city_id, to_city_id)?;
+                let source =
+                    Source::Construction(AssetId::Connection(self.graph.connections.len()));
+                self.take_materials(source, from_city_id, materials);
+                let mut owned_connection = OwnedConnection::new(from_city_id, to_city_id);
+                owned_connection.owner_id = Some(self.current_player_id);
+                owned_connection.acquisition_cost = cost;
+                owned_connection.construction_rounds = rounds;
+                owned_connection.acquired_round = self.round;
+                self.graph.connections.push(owned_connection);
+                self.pay(source, cost);
+            }
+            Command::SetRecipeScale {
+                city_id,
+                building_id,
+                recipe_id,
+                scale,
+            } => {
+                self.check_recipe_scale(city_id, building_id, recipe_id, scale)?;
+                self.graph.cities[city_id]
+                    .get_building_mut(building_id)
+                    .unwrap()
+                    .production_scale[recipe_id]
+                    .scale = scale;
+            }
+            Command::SetRecipePriority {
+                city_id,
+                building_id,
+                recipe_id,
+                priority,
+            } => {
+                self.get_owned_recipe(city_id, building_id, recipe_id)?;
+                self.graph.cities[city_id]
+                    .get_building_mut(building_id)
+                    .unwrap()
+                    .production_scale[recipe_id]
+                    .priority = priority;
+            }
+            Command::AddShipment {
+                material,
+                from_city_id,
+                to_city_id,
+                quantity,
+            } => {
+                let connection_ids =
+                    self.check_add_shipment(&material, from_city_id, to_city_id, quantity)?;
+                self.graph.businesses[self.current_player_id]
+                    .shipments
+                    .push(Shipment {
+                        material,
+                        from_city_id,
+                        to_city_id,
+                        quantity,
+                        connection_ids,
+                    });
+            }
+            Command::SetShipmentQuantity {
+                shipment_id,
+                quantity,
+            } => {
+                self.check_shipment_quantity(shipment_id, quantity)?;
+                if quantity == 0 {
+                    self.graph
+                        .remove_shipment(self.current_player_id, shipment_id);
+                } else {
+                    self.graph.businesses[self.current_player_id].shipments[shipment_id].quantity =
+                        quantity;
+                }
+            }
+            Command::ProposeTrade { to_id, terms } => {
+                self.check_propose_trade(to_id, &terms)?;
+                self.graph.offers.push(Offer {
+                    from_id: self.current_player_id,
+                    to_id,
+                    terms,
+                });
+            }
+            Command::AcceptOffer { offer_id } => {
+                self.check_accept_offer(offer_id)?;
+                let offer = self.graph.offers.remove(offer_id);
+                if offer.terms.is_contract() {
+                    self.graph.contracts.push(Contract::new(offer));
+                } else {
+                    let Offer {
+                        from_id,
+                        to_id,
+                        terms,
+                    } = offer;
+                    for (parties, goods) in [
+                        ((from_id, to_id), &terms.gives),
+                        ((to_id, from_id), &terms.takes),
+                    ] {
+                        self.graph.transfer(
+                            self.round,
+                            parties,
+                            terms.city_id,
+                            goods,
+                            Source::Trade,
+                        );
+                    }
+                }
+            }
+            Command::CounterOffer { offer_id, terms } => {
+                let to_id = self.check_counter_offer(offer_id, &terms)?;
+                self.graph.offers[offer_id] = Offer {
+                    from_id: self.current_player_id,
+                    to_id,
+                    terms,
+                };
+            }
+            Command::RejectOffer { offer_id } => {
+                self.check_reject_offer(offer_id)?;
+                self.graph.offers.remove(offer_id);
+            }
+            Command::EndTurn => self.end_turn(),
+        }
+        Ok(())
+    }
+
+    pub fn get_current_controller(&self) -> Controller {
+        self.graph.businesses[self.current_player_id].controller
+    }
+
+    pub fn check_buy_building(&self, city_id: usize, building_id: usize) -> Result<(), GameError> {
+        let city = self
+            .graph
+            .cities
+            .get(city_id)
+            .ok_or(GameError::UnknownCity(city_id))?;
+        let building = city
+            .get_building(building_id)
+            .ok_or(GameError::UnknownBuilding(building_id))?;
+        if let Some(owner_id) = building.owner_id {
+            return Err(self.already_owned(owner_id));
+        }
+        let connected = self.owns_nothing()
+            || self
+                .graph
+                .get_network_city_ids(self.current_player_id)
+                .contains(&city_id);
+        if !connected {
+            return Err(GameError::NotConnected);
+        }
+        self.check_funds(building.acquisition_cost)
+    }
+
+    pub fn check_buy_connection(&self, connection_id: usize) -> Result<(), GameError> {
+        let connection = self
+            .graph
+            .connections
+            .get(connection_id)
+            .ok_or(GameError::UnknownConnection(connection_id))?;
+        if let Some(owner_id) = connection.owner_id {
+            return Err(self.already_owned(owner_id));
+        }
+        let network_city_ids = self.graph.get_network_city_ids(self.current_player_id);
+        if !connection
+            .city_ids
+            .iter()
+            .any(|city_id| network_city_ids.contains(city_id))
+        {
+            return Err(GameError::NotConnected);
+        }
+        self.check_funds(connection.acquisition_cost)
+    }
+
+    /// Returns the cost of the next upgrade.
+    pub fn check_upgrade_building(
+        &self,
+        city_id: usize,
+        building_id: usize,
+    ) -> Result<i32, GameError> {
+        let building = self.get_owned_building(city_id, building_id)?;
+        if building.is_under_construction() {
+            return Err(GameError::UnderConstruction);
+        }
+        let cost = building
+            .get_next_upgrade(&self.registry)
+            .ok_or(GameError::FullyUpgraded)?
+            .cost;
+        self.check_funds(cost)?;
+        Ok(cost)
+    }
+
+    /// Returns what the building sells for.
+    pub fn check_sell_building(
+        &self,
+        city_id: usize,
+        building_id: usize,
+    ) -> Result<i32, GameError> {
+        let building = self.get_owned_building(city_id, building_id)?;
+        if building.is_under_construction() {
+            return Err(GameError::UnderConstruction);
+        }
+        Ok(sale::get_sale_price(building.acquisition_cost))
+    }
+
+    /// Returns what the connection sells for.
+    pub fn check_sell_connection(&self, connection_id: usize) -> Result<i32, GameError> {
+        let connection = self
+            .graph
+            .connections
+            .get(connection_id)
+            .ok_or(GameError::UnknownConnection(connection_id))?;
+        if connection.owner_id != Some(self.current_player_id) {
+            return Err(GameError::NotOwner);
+        }
+        if connection.is_under_construction() {
+            return Err(GameError::UnderConstruction);
+        }
+        if self.graph.businesses[self.current_player_id]
+            .shipments
+            .iter()
+            .any(|shipment| shipment.connection_ids.contains(&connection_id))
+        {
+            return Err(GameError::ConnectionInUse);
+        }
+        Ok(sale::get_sale_price(connection.acquisition_cost))
+    }
+
+    pub fn check_set_toll(&self, connection_id: usize, toll: Option<i32>) -> Result<(), GameError> {
+        let connection = self
+            .graph
+            .connections
+            .get(connection_id)
+            .ok_or(GameError::UnknownConnection(connection_id))?;
+        if connection.owner_id != Some(self.current_player_id) {
+            return Err(GameError::NotOwner);
+        }
+        match toll {
+            Some(toll) if !(0..=MAX_TOLL).contains(&toll) => Err(GameError::InvalidQuantity(toll)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_start_paying_toll(&self, connection_id: usize) -> Result<(), GameError> {
+        let connection = self
+            .graph
+            .connections
+            .get(connection_id)
+            .ok_or(GameError::UnknownConnection(connection_id))?;
+        if connection.owner_id == Some(self.current_player_id) {
+            return Err(self.already_owned(self.current_player_id));
+        }
+        let (Some(_), Some(toll)) = (connection.owner_id, connection.toll) else {
+            return Err(GameError::NoToll);
+        };
+        if self.graph.businesses[self.current_player_id]
+            .toll_connection_ids
+            .contains(&connection_id)
+        {
+            return Err(GameError::AlreadyPayingToll);
+        }
+        if connection.is_under_construction() {
+            return Err(GameError::UnderConstruction);
+        }
+        let network_city_ids = self.graph.get_network_city_ids(self.current_player_id);
+        if !connection
+            .city_ids
+            .iter()
+            .any(|city_id| network_city_ids.contains(city_id))
+        {
+            return Err(GameError::NotConnected);
+        }
+        self.check_funds(toll)
+    }
+
+    pub fn check_stop_paying_toll(&self, connection_id: usize) -> Result<(), GameError> {
+        let business = &self.graph.businesses[self.current_player_id];
+        if !business.toll_connection_ids.contains(&connection_id) {
+            return Err(GameError::NotPayingToll);
+        }
+        if business
+            .shipments
+            .iter()
+            .any(|shipment| shipment.connection_ids.contains(&connection_id))
+        {
+            return Err(GameError::ConnectionInUse);
+        }
+        Ok(())
+    }
+
+    pub fn check_construct_building(
+        &self,
+        city_id: usize,
+        building_type: &BuildingTypeId,
+    ) -> Result<&ConstructionDef, GameError> {
+        let city = self
+            .graph
+            .cities
+            .get(city_id)
+            .ok_or(GameError::UnknownCity(city_id))?;
+        let building_type_def = self
+            .registry
+            .building_types
+            .get(building_type)
+            .ok_or_else(|| GameError::NotConstructible(building_type.clone()))?;
+        let construction = building_type_def
+            .construction
+            .as_ref()
+            .ok_or_else(|| GameError::NotConstructible(building_type.clone()))?;
+        if !city.has_free_slot() {
+            return Err(GameError::CityFull);
+        }
+        let connected = self.owns_nothing()
+            || self
+                .graph
+                .get_network_city_ids(self.current_player_id)
+                .contains(&city_id);
+        if !connected {
+            return Err(GameError::NotConnected);
+        }
+        self.check_funds(building_type_def.acquisition_cost)?;
+        self.check_materials(self.current_player_id, city_id, &construction.materials)?;
+        Ok(construction)
+    }
+
+    /// Returns the money cost and what else the connection needs to be built.
+    pub fn check_construct_connection(
+        &self,
+        from_city_id: usize,
+        to_city_id: usize,
+    ) -> Result<(i32, ConstructionDef), GameError> {
+        for city_id in [from_city_id, to_city_id] {
+            if city_id >= self.graph.cities.len() {
+                return Err(GameError::UnknownCity(city_id));
+            }
+        }
+        if from_city_id == to_city_id
+            || self
+                .graph
+                .find_connection(from_city_id, to_city_id)
+                .is_some()
+        {
+            return Err(GameError::AlreadyConnected);
+        }
+        if !self
+            .graph
+            .get_network_city_ids(self.current_player_id)
+            .contains(&from_city_id)
+        {
+            return Err(GameError::NotConnected);
+        }
+        if let Some(connection_id) = self.graph.find_crossed_connection(from_city_id, to_city_id) {
+            return Err(GameError::CrossesConnection(connection_id));
+        }
+        if let Some(city_id) = self.graph.find_city_in_the_way(from_city_id, to_city_id) {
+            return Err(GameError::CityInTheWay(city_id));
+        }
+        let (cost, construction) = self
+            .registry
+            .connection_construction
+            .get_costs(self.graph.get_city_distance(from_city_id, to_city_id));
+        self.check_funds(cost)?;
+        self.check_materials(
+            self.current_player_id,
+            from_city_id,
+            &construction.materials,
+        )?;
+        Ok((cost, construction))
+    }
+
+    pub fn check_recipe_scale(
+        &self,
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+        scale: i32,
+    ) -> Result<(), GameError> {
+        let (
+            building,
+            ScaledValidRecipe {
+                valid_recipe,
+                scale: current_scale,
+                max_scale,
+                ..
+            },
+        ) = self.get_owned_recipe(city_id, building_id, recipe_id)?;
+        if building.is_under_construction() {
+            return Err(GameError::UnderConstruction);
+        }
+        if !(0..=*max_scale).contains(&scale) {
+            return Err(GameError::ScaleOutOfRange {
+                scale,
+                max_scale: *max_scale,
+            });
+        }
+        let increment = scale - current_scale;
+        let changes: Vec<(MaterialId, i32)> = building
+            .get_recipe(&self.registry, valid_recipe)
+            .materials
+            .into_iter()
+            .map(|(material, quantity)| (material, increment * quantity))
+            .collect();
+        self.check_flow_changes(self.current_player_id, city_id, &changes)
+    }
+
+    fn get_owned_building(
+        &self,
+        city_id: usize,
+        building_id: usize,
+    ) -> Result<&OwnedBuilding, GameError> {
+        let building = self
+            .graph
+            .cities
+            .get(city_id)
+            .ok_or(GameError::UnknownCity(city_id))?
+            .get_building(building_id)
+            .ok_or(GameError::UnknownBuilding(building_id))?;
+        if building.owner_id != Some(self.current_player_id) {
+            return Err(GameError::NotOwner);
+        }
+        Ok(building)
+    }
+
+    fn get_owned_recipe(
+        &self,
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+    ) -> Result<(&OwnedBuilding, &ScaledValidRecipe), GameError> {
+        let building = self.get_owned_building(city_id, building_id)?;
+        let scaled_valid_recipe = building
+            .production_scale
+            .get(recipe_id)
+            .ok_or(GameError::UnknownRecipe(recipe_id))?;
+        Ok((building, scaled_valid_recipe))
+    }
+
+    pub fn check_add_shipment(
+        &self,
+        material: &MaterialId,
+        from_city_id: usize,
+        to_city_id: usize,
+        quantity: i32,
+    ) -> Result<Vec<usize>, GameError> {
+        for city_id in [from_city_id, to_city_id] {
+            if city_id >= self.graph.cities.len() {
+                return Err(GameError::UnknownCity(city_id));
+            }
+        }
+        if quantity <= 0 || material.is_money() || from_city_id == to_city_id {
+            return Err(GameError::InvalidQuantity(quantity));
+        }
+        let connection_ids = self
+            .graph
+            .find_route(self.current_player_id, from_city_id, to_city_id, quantity)
+            .ok_or(GameError::NoRoute)?;
+        let shipping_cost = self.graph.get_shipping_cost(&connection_ids, quantity);
+        self.check_flow_changes(
+            self.current_player_id,
+            from_city_id,
+            &[
+                (material.clone(), -quantity),
+                (MaterialId::money(), -shipping_cost),
+            ],
+        )?;
+        Ok(connection_ids)
+    }
+
+    pub fn check_shipment_quantity(
+        &self,
+        shipment_id: usize,
+        quantity: i32,
+    ) -> Result<(), GameError> {
+        let shipment = self.graph.businesses[self.current_player_id]
+            .shipments
+            .get(shipment_id)
+            .ok_or(GameError::UnknownShipment(shipment_id))?;
+        if quantity < 0 {
+            return Err(GameError::InvalidQuantity(quantity));
+        }
+        let increment = quantity - shipment.quantity;
+        for connection_id in &shipment.connection_ids {
+            if self.graph.get_connection_load(*connection_id) + increment
+                > self.graph.connections[*connection_id].capacity
+            {
+                return Err(GameError::CapacityExceeded {
+                    connection_id: *connection_id,
+                });
+            }
+        }
+        let shipping_cost = self
+            .graph
+            .get_shipping_cost(&shipment.connection_ids, increment);
+        self.check_flow_changes(
+            self.current_player_id,
+            shipment.from_city_id,
+            &[
+                (shipment.material.clone(), -increment),
+                (MaterialId::money(), -shipping_cost),
+            ],
+        )?;
+        self.check_flow_changes(
+            self.current_player_id,
+            shipment.to_city_id,
+            &[(shipment.material.clone(), increment)],
+        )
+    }
+
+    pub fn check_propose_trade(&self, to_id: usize, terms: &Terms) -> Result<(), GameError> {
+        let partner = self
+            .graph
+            .businesses
+            .get(to_id)
+            .filter(|_| to_id != self.current_player_id)
+            .ok_or(GameError::UnknownBusiness(to_id))?;
+        if partner.eliminated {
+            return Err(GameError::Bankrupt(partner.name.clone()));
+        }
+        if terms.city_id >= self.graph.cities.len() {
+            return Err(GameError::UnknownCity(terms.city_id));
+        }
+        if terms.gives.is_empty() && terms.takes.is_empty() {
+            return Err(GameError::InvalidTrade);
+        }
+        for (material, quantity) in terms.gives.iter().chain(&terms.takes) {
+            if !self.registry.materials.contains_key(material) {
+                return Err(GameError::InvalidTrade);
+            }
+            if *quantity <= 0 {
+                return Err(GameError::InvalidQuantity(*quantity));
+            }
+        }
+        if terms.penalty < 0 {
+            return Err(GameError::InvalidQuantity(terms.penalty));
+        }
+        if terms.is_contract() {
+            return Ok(());
+        }
+        self.check_materials(self.current_player_id, terms.city_id, &terms.gives)
+    }
+
+    /// One-off trades need both businesses to have the goods now. Contracts only need them every
+    /// round, but both businesses have to reach the city for as long as the contract runs.
+    pub fn check_accept_offer(&self, offer_id: usize) -> Result<(), GameError> {
+        let offer = self.get_received_offer(offer_id)?;
+        if offer.terms.is_contract() {
+            if [offer.from_id, offer.to_id].iter().any(|business_id| {
+                !self
+                    .graph
+                    .get_network_city_ids(*business_id)
+                    .contains(&offer.terms.city_id)
+            }) {
+                return Err(GameError::NotConnected);
+            }
+            return Ok(());
+        }
+        let terms = &offer.terms;
+        self.check_materials(offer.from_id, terms.city_id, &terms.gives)?;
+        self.check_materials(offer.to_id, terms.city_id, &terms.takes)
+    }
+
+    /// Returns who the counter offer goes to.
+    pub fn check_counter_offer(&self, offer_id: usize, terms: &Terms) -> Result<usize, GameError> {
+        let from_id = self.get_received_offer(offer_id)?.from_id;
+        self.check_propose_trade(from_id, terms)?;
+        Ok(from_id)
+    }
+
+    pub fn check_reject_offer(&self, offer_id: usize) -> Result<(), GameError> {
+        let offer = self
+            .graph
+            .offers
+            .get(offer_id)
+            .ok_or(GameError::UnknownOffer(offer_id))?;
+        if offer.from_id != self.current_player_id && offer.to_id != self.current_player_id {
+            return Err(GameError::NotYourOffer);
+        }
+        Ok(())
+    }
+
+    fn get_received_offer(&self, offer_id: usize) -> Result<&Offer, GameError> {
+        let offer = self
+            .graph
+            .offers
+            .get(offer_id)
+            .ok_or(GameError::UnknownOffer(offer_id))?;
+        if offer.to_id != self.current_player_id {
+            return Err(GameError::NotYourOffer);
+        }
+        Ok(offer)
+    }
+
+    /// Rejects changes to the per round flows in a city that would drive its stock negative.
+    fn check_flow_changes(
+        &self,
+        business_id: usize,
+        city_id: usize,
+        changes: &[(MaterialId, i32)],
+    ) -> Result<(), GameError> {
+        let resource_stock =
+            self.graph
+                .get_resource_stock(&self.registry, business_id, Some(city_id));
+        for (material, change) in changes {
+            if *change >= 0 {
+                continue;
+            }
+            let projected = resource_stock.get(material).map_or(0, |quantity_info| {
+                quantity_info.quantity + quantity_info.net_in()
+            }) + change;
+            if projected < 0 {
+                return Err(GameError::InsufficientResources(material.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects taking materials from a city's stock that its per round flows still need.
+    fn check_materials(
+        &self,
+        business_id: usize,
+        city_id: usize,
+        materials: &[(MaterialId, i32)],
+    ) -> Result<(), GameError> {
+        let changes: Vec<(MaterialId, i32)> = materials
+            .iter()
+            .map(|(material, quantity)| (material.clone(), -quantity))
+            .collect();
+        self.check_flow_changes(business_id, city_id, &changes)
+    }
+
+    fn take_materials(
+        &mut self,
+        source: Source,
+        city_id: usize,
+        materials: Vec<(MaterialId, i32)>,
+    ) {
+        let taken: Vec<(MaterialId, i32)> = materials
+            .into_iter()
+            .map(|(material, quantity)| (material, -quantity))
+            .collect();
+        self.graph.businesses[self.current_player_id]
+            .add_goods(self.round, source, city_id, &taken);
+    }
+
+    fn owns_nothing(&self) -> bool {
+        self.graph
+            .get_network_city_ids(self.current_player_id)
+            .is_empty()
+    }
+
+    fn check_funds(&self, required: i32) -> Result<(), GameError> {
+        let available = self.graph.businesses[self.current_player_id].money;
+        if available < required {
+            return Err(GameError::InsufficientFunds {
+                required,
+                available,
+            });
+        }
+        Ok(())
+    }
+
+    fn already_owned(&self, owner_id: usize) -> GameError {
+        GameError::AlreadyOwned {
+            owner: self.graph.businesses[owner_id].name.clone(),
+        }
+    }
+
+    fn pay(&mut self, source: Source, amount: i32) {
+        self.graph.businesses[self.current_player_id].add_money(self.round, source, -amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        registry::{BuildingTypeId, RecipeId, ValidRecipe},
+        resolution::{ActivityId, Throttle},
+        testing,
+    };
+
+    /// Player 0 owns the sawmill in city 0 and the connection to city 1, player 1 the mine in
+    /// city 2 and the connection from city 1 to it. The connection from city 3 to city 4 crosses
+    /// the line from city 1 to city 5, and city 6 sits between cities 0 and 7.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &["Sawmill", "Market"]),
+                (200.0, 0.0, &["WoodWorkingMarket"]),
+                (400.0, 0.0, &["Mine"]),
+                (300.0, 200.0, &[]),
+                (300.0, -200.0, &[]),
+                (600.0, 0.0, &["Market"]),
+                (0.0, 200.0, &[]),
+                (0.0, 400.0, &[]),
+            ],
+            &[(0, 1), (1, 2), (3, 4)],
+        );
+        testing::own_building(&mut game, 0, 0, 0);
+        testing::own_connection(&mut game, 0, 0);
+        testing::own_building(&mut game, 1, 2, 0);
+        testing::own_connection(&mut game, 1, 1);
+        game
+    }
+
+    fn set_money(game: &mut Game, money: i32) {
+        game.graph.businesses[game.current_player_id].money = money;
+    }
+
+    fn plank() -> MaterialId {
+        testing::material("Plank")
+    }
+
+    fn one_off(city_id: usize, gives: Vec<(MaterialId, i32)>) -> Terms {
+        Terms {
+            city_id,
+            gives,
+            takes: vec![(MaterialId::money(), 5)],
+            rounds: 0,
+            penalty: 0,
+        }
+    }
+
+    #[test]
+    fn buy_building_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_buy_building(99, 0),
+            Err(GameError::UnknownCity(99))
+        );
+        assert_eq!(
+            game.check_buy_building(0, 5),
+            Err(GameError::UnknownBuilding(5))
+        );
+        let err = game.check_buy_building(2, 0).unwrap_err();
+        assert_eq!(
+            err,
+            GameError::AlreadyOwned {
+                owner: "Player 2".to_string()
+            }
+        );
+        assert_eq!(err.to_string(), "Already owned by Player 2");
+        assert_eq!(game.check_buy_building(5, 0), Err(GameError::NotConnected));
+        assert_eq!(game.check_buy_building(1, 0), Ok(()));
+        set_money(&mut game, 10);
+        assert_eq!(
+            game.check_buy_building(0, 1),
+            Err(GameError::InsufficientFunds {
+                required: 20,
+                available: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn first_building_can_be_bought_anywhere() {
+        let mut game = new_game();
+        game.current_player_id = 1;
+        game.graph.cities[2].get_building_mut(0).unwrap().owner_id = None;
+        game.graph.connections[1].owner_id = None;
+        assert_eq!(game.check_buy_building(5, 0), Ok(()));
+    }
+
+    #[test]
+    fn buy_connection_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_buy_connection(9),
+            Err(GameError::UnknownConnection(9))
+        );
+        assert_eq!(
+            game.check_buy_connection(1),
+            Err(GameError::AlreadyOwned {
+                owner: game.graph.businesses[1].name.clone()
+            })
+        );
+        assert_eq!(game.check_buy_connection(2), Err(GameError::NotConnected));
+        game.graph.connections[1].owner_id = None;
+        assert_eq!(game.check_buy_connection(1), Ok(()));
+        set_money(&mut game, 10);
+        assert_eq!(
+            game.check_buy_connection(1),
+            Err(GameError::InsufficientFunds {
+                required: 20,
+                available: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn recipe_scale_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_recipe_scale(0, 1, 0, 1),
+            Err(GameError::NotOwner)
+        );
+        assert_eq!(
+            game.check_recipe_scale(0, 0, 9, 1),
+            Err(GameError::UnknownRecipe(9))
+        );
+        assert_eq!(
+            game.check_recipe_scale(0, 0, 0, 6),
+            Err(GameError::ScaleOutOfRange {
+                scale: 6,
+                max_scale: 5,
+            })
+        );
+        assert_eq!(
+            game.check_recipe_scale(0, 0, 0, -1),
+            Err(GameError::ScaleOutOfRange {
+                scale: -1,
+                max_scale: 5,
+            })
+        );
+        assert_eq!(
+            game.check_recipe_scale(0, 0, 0, 1),
+            Err(GameError::InsufficientResources(testing::material("Log")))
+        );
+        testing::give(&mut game, 0, 0, &[(testing::material("Log"), 1)]);
+        assert_eq!(game.check_recipe_scale(0, 0, 0, 1), Ok(()));
+        game.graph.cities[0]
+            .get_building_mut(0)
+            .unwrap()
+            .construction_rounds = 1;
+        assert_eq!(
+            game.check_recipe_scale(0, 0, 0, 1),
+            Err(GameError::UnderConstruction)
+        );
+    }
+
+    #[test]
+    fn buying_assets_charges_the_buyer() {
+        let mut game = new_game();
+        game.round = 3;
+        game.execute(Command::BuyBuilding {
+            city_id: 1,
+            building_id: 0,
+        })
+        .unwrap();
+        game.graph.connections[1].owner_id = None;
+        game.execute(Command::BuyConnection { connection_id: 1 })
+            .unwrap();
+
+        let building = game.graph.cities[1].get_building(0).unwrap();
+        assert_eq!(building.owner_id, Some(0));
+        assert_eq!(building.acquired_round, 3);
+        let owned_connection = &game.graph.connections[1];
+        assert_eq!(owned_connection.owner_id, Some(0));
+        assert_eq!(owned_connection.acquired_round, 3);
+        assert_eq!(
+            game.graph.businesses[0].money,
+            250 - building.acquisition_cost - owned_connection.acquisition_cost
+        );
+    }
+
+    #[test]
+    fn sell_checks() {
+        let mut game = new_game();
+        assert_eq!(game.check_sell_building(0, 1), Err(GameError::NotOwner));
+        assert_eq!(game.check_sell_building(0, 0), Ok(40));
+        assert_eq!(
+            game.check_sell_connection(9),
+            Err(GameError::UnknownConnection(9))
+        );
+        assert_eq!(game.check_sell_connection(1), Err(GameError::NotOwner));
+        assert_eq!(game.check_sell_connection(0), Ok(10));
+        game.graph.businesses[0].shipments.push(Shipment {
+            material: plank(),
+            from_city_id: 0,
+            to_city_id: 1,
+            quantity: 1,
+            connection_ids: vec![0],
+        });
+        assert_eq!(
+            game.check_sell_connection(0),
+            Err(GameError::ConnectionInUse)
+        );
+        game.graph.cities[0]
+            .get_building_mut(0)
+            .unwrap()
+            .construction_rounds = 1;
+        game.graph.connections[0].construction_rounds = 1;
+        assert_eq!(
+            game.check_sell_building(0, 0),
+            Err(GameError::UnderConstruction)
+        );
+        assert_eq!(
+            game.check_sell_connection(0),
+            Err(GameError::UnderConstruction)
+        );
+    }
+
+    #[test]
+    fn toll_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_set_toll(9, Some(1)),
+            Err(GameError::UnknownConnection(9))
+        );
+        assert_eq!(game.check_set_toll(1, Some(1)), Err(GameError::NotOwner));
+        assert_eq!(
+            game.check_set_toll(0, Some(-1)),
+            Err(GameError::InvalidQuantity(-1))
+        );
+        assert_eq!(
+            game.check_set_toll(0, Some(MAX_TOLL + 1)),
+            Err(GameError::InvalidQuantity(MAX_TOLL + 1))
+        );
+        assert_eq!(game.check_set_toll(0, Some(MAX_TOLL)), Ok(()));
+        assert_eq!(game.check_set_toll(0, None), Ok(()));
+
+        assert_eq!(
+            game.check_start_paying_toll(0),
+            Err(GameError::AlreadyOwned {
+                owner: "Player 1".to_string(),
+            })
+        );
+        assert_eq!(game.check_start_paying_toll(1), Err(GameError::NoToll));
+        game.graph.connections[1].toll = Some(3);
+        assert_eq!(game.check_start_paying_toll(1), Ok(()));
+        set_money(&mut game, 1);
+        assert_eq!(
+            game.check_start_paying_toll(1),
+            Err(GameError::InsufficientFunds {
+                required: 3,
+                available: 1,
+            })
+        );
+        game.graph.connections[1].construction_rounds = 1;
+        assert_eq!(
+            game.check_start_paying_toll(1),
+            Err(GameError::UnderConstruction)
+        );
+        game.graph.businesses[0].toll_connection_ids.insert(1);
+        assert_eq!(
+            game.check_start_paying_toll(1),
+            Err(GameError::AlreadyPayingToll)
+        );
+        testing::own_connection(&mut game, 1, 2);
+        game.graph.connections[2].toll = Some(3);
+        assert_eq!(
+            game.check_start_paying_toll(2),
+            Err(GameError::NotConnected)
+        );
+
+        assert_eq!(
+            game.check_stop_paying_toll(2),
+            Err(GameError::NotPayingToll)
+        );
+        assert_eq!(game.check_stop_paying_toll(1), Ok(()));
+        game.graph.businesses[0].shipments.push(Shipment {
+            material: plank(),
+            from_city_id: 0,
+            to_city_id: 2,
+            quantity: 1,
+            connection_ids: vec![0, 1],
+        });
+        assert_eq!(
+            game.check_stop_paying_toll(1),
+            Err(GameError::ConnectionInUse)
+        );
+    }
+
+    #[test]
+    fn upgrade_building_checks() {
+        let mut game = new_game();
+        assert_eq!(game.check_upgrade_building(0, 1), Err(GameError::NotOwner));
+        assert_eq!(game.check_upgrade_building(0, 0), Ok(40));
+        set_money(&mut game, 10);
+        assert_eq!(
+            game.check_upgrade_building(0, 0),
+            Err(GameError::InsufficientFunds {
+                required: 40,
+                available: 10,
+            })
+        );
+        let sawmill = game.graph.cities[0].get_building_mut(0).unwrap();
+        sawmill.level = 2;
+        assert_eq!(
+            game.check_upgrade_building(0, 0),
+            Err(GameError::FullyUpgraded)
+        );
+        game.graph.cities[0]
+            .get_building_mut(0)
+            .unwrap()
+            .construction_rounds = 1;
+        assert_eq!(
+            game.check_upgrade_building(0, 0),
+            Err(GameError::UnderConstruction)
+        );
+    }
+
+    #[test]
+    fn upgrades_raise_max_scale_unlock_recipes_and_charge_the_owner() {
+        let mut game = new_game();
+        for _ in 0..2 {
+            game.execute(Command::UpgradeBuilding {
+                city_id: 0,
+                building_id: 0,
+            })
+            .unwrap();
+        }
+
+        let sawmill = game.graph.cities[0].get_building(0).unwrap();
+        assert_eq!(sawmill.level, 2);
+        assert!(sawmill
+            .production_scale
+            .iter()
+            .all(|scaled_valid_recipe| scaled_valid_recipe.max_scale == 12));
+        assert!(sawmill.production_scale.iter().any(|scaled_valid_recipe| {
+            scaled_valid_recipe.valid_recipe
+                == ValidRecipe::Production(RecipeId("FurnitureProduction".to_string()))
+        }));
+        assert_eq!(game.graph.businesses[0].money, 250 - 40 - 80);
+        assert_eq!(
+            game.graph.businesses[0].ledger.rounds[&game.round]
+                .iter()
+                .map(|entry| entry.quantity)
+                .collect::<Vec<i32>>(),
+            vec![-40, -80]
+        );
+    }
+
+    #[test]
+    fn construct_building_checks() {
+        let mut game = new_game();
+        let market = BuildingTypeId("Market".to_string());
+        assert_eq!(
+            game.check_construct_building(99, &market).err(),
+            Some(GameError::UnknownCity(99))
+        );
+        let unknown = BuildingTypeId("Castle".to_string());
+        assert_eq!(
+            game.check_construct_building(0, &unknown).err(),
+            Some(GameError::NotConstructible(unknown))
+        );
+        assert_eq!(
+            game.check_construct_building(5, &market).err(),
+            Some(GameError::NotConnected)
+        );
+        assert_eq!(
+            game.check_construct_building(1, &market).err(),
+            Some(GameError::InsufficientResources(plank()))
+        );
+        testing::give(&mut game, 0, 1, &[(plank(), 4)]);
+        assert!(game.check_construct_building(1, &market).is_ok());
+        set_money(&mut game, 10);
+        assert_eq!(
+            game.check_construct_building(1, &market).err(),
+            Some(GameError::InsufficientFunds {
+                required: 20,
+                available: 10,
+            })
+        );
+        while game.graph.cities[1].has_free_slot() {
+            let building = OwnedBuilding::new(&game.registry, market.clone());
+            game.graph.cities[1].owned_buildings.push(Some(building));
+        }
+        assert_eq!(
+            game.check_construct_building(1, &market).err(),
+            Some(GameError::CityFull)
+        );
+    }
+
+    #[test]
+    fn constructed_buildings_take_materials_and_open_after_their_build_time() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 1, &[(plank(), 6)]);
+        game.execute(Command::ConstructBuilding {
+            city_id: 1,
+            building_type: BuildingTypeId("Market".to_string()),
+        })
+        .unwrap();
+
+        let market = game.graph.cities[1].get_building(1).unwrap();
+        assert_eq!(market.owner_id, Some(0));
+        assert_eq!(market.construction_rounds, 2);
+        assert_eq!(game.graph.businesses[0].money, 250 - 20);
+        assert_eq!(game.graph.businesses[0].inventories[&1][&plank()], 2);
+        for rounds_left in [1, 0] {
+            for _ in 0..game.graph.businesses.len() {
+                game.execute(Command::EndTurn).unwrap();
+            }
+            let market = game.graph.cities[1].get_building(1).unwrap();
+            assert_eq!(market.construction_rounds, rounds_left);
+        }
+        assert!(game.check_upgrade_building(1, 1).is_ok());
+    }
+
+    #[test]
+    fn construct_connection_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_construct_connection(0, 99).err(),
+            Some(GameError::UnknownCity(99))
+        );
+        assert_eq!(
+            game.check_construct_connection(0, 0).err(),
+            Some(GameError::AlreadyConnected)
+        );
+        assert_eq!(
+            game.check_construct_connection(1, 0).err(),
+            Some(GameError::AlreadyConnected)
+        );
+        assert_eq!(
+            game.check_construct_connection(6, 0).err(),
+            Some(GameError::NotConnected)
+        );
+        assert_eq!(
+            game.check_construct_connection(1, 5).err(),
+            Some(GameError::CrossesConnection(2))
+        );
+        assert_eq!(
+            game.check_construct_connection(0, 7).err(),
+            Some(GameError::CityInTheWay(6))
+        );
+        assert_eq!(
+            game.check_construct_connection(0, 6).err(),
+            Some(GameError::InsufficientResources(plank()))
+        );
+        testing::give(
+            &mut game,
+            0,
+            0,
+            &[(plank(), 8), (testing::material("Wire"), 2)],
+        );
+        let (cost, construction) = game.check_construct_connection(0, 6).unwrap();
+        assert_eq!(cost, 50);
+        assert_eq!(construction.rounds, 2);
+        set_money(&mut game, 10);
+        assert_eq!(
+            game.check_construct_connection(0, 6).err(),
+            Some(GameError::InsufficientFunds {
+                required: 50,
+                available: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn constructed_connections_open_after_their_build_time() {
+        let mut game = new_game();
+        testing::give(
+            &mut game,
+            0,
+            0,
+            &[(plank(), 8), (testing::material("Wire"), 2)],
+        );
+        game.execute(Command::ConstructConnection {
+            from_city_id: 0,
+            to_city_id: 6,
+        })
+        .unwrap();
+
+        let connection_id = game.graph.find_connection(0, 6).unwrap();
+        let owned_connection = &game.graph.connections[connection_id];
+        assert_eq!(owned_connection.owner_id, Some(0));
+        assert_eq!(owned_connection.acquisition_cost, 50);
+        assert_eq!(owned_connection.construction_rounds, 2);
+        assert_eq!(game.graph.businesses[0].money, 250 - 50);
+        assert!(game.graph.businesses[0].inventories[&0]
+            .values()
+            .all(|quantity| *quantity == 0));
+        assert!(!game.graph.get_reachable_city_ids(0).contains(&6));
+        for _ in 0..2 * game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+        assert!(game.graph.get_reachable_city_ids(0).contains(&6));
+    }
+
+    #[test]
+    fn failed_commands_change_nothing() {
+        let mut game = new_game();
+        set_money(&mut game, 10);
+        assert!(game
+            .execute(Command::BuyBuilding {
+                city_id: 0,
+                building_id: 1,
+            })
+            .is_err());
+        assert_eq!(game.graph.cities[0].get_building(1).unwrap().owner_id, None);
+        assert_eq!(game.graph.businesses[0].money, 10);
+    }
+
+    #[test]
+    fn recipes_run_at_their_scale_once_every_player_ended_the_turn() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(testing::material("Log"), 3)]);
+        game.execute(Command::SetRecipeScale {
+            city_id: 0,
+            building_id: 0,
+            recipe_id: 0,
+            scale: 2,
+        })
+        .unwrap();
+        game.execute(Command::EndTurn).unwrap();
+        assert_eq!((game.current_player_id, game.round), (1, 1));
+        assert_eq!(
+            game.graph.businesses[0].inventories[&0][&testing::material("Log")],
+            3
+        );
+
+        game.execute(Command::EndTurn).unwrap();
+        assert_eq!((game.current_player_id, game.round), (0, 2));
+        let inventory = &game.graph.businesses[0].inventories[&0];
+        assert_eq!(inventory[&testing::material("Log")], 1);
+        assert_eq!(inventory[&testing::material("Plank")], 8);
+    }
+
+    #[test]
+    fn shipment_checks() {
+        let mut game = new_game();
+        assert_eq!(
+            game.check_add_shipment(&plank(), 0, 99, 1),
+            Err(GameError::UnknownCity(99))
+        );
+        assert_eq!(
+            game.check_add_shipment(&plank(), 0, 1, 0),
+            Err(GameError::InvalidQuantity(0))
+        );
+        assert_eq!(
+            game.check_add_shipment(&MaterialId::money(), 0, 1, 1),
+            Err(GameError::InvalidQuantity(1))
+        );
+        assert_eq!(
+            game.check_add_shipment(&plank(), 0, 2, 1),
+            Err(GameError::NoRoute)
+        );
+        assert_eq!(
+            game.check_add_shipment(&plank(), 0, 1, 3),
+            Err(GameError::InsufficientResources(plank()))
+        );
+        testing::give(&mut game, 0, 0, &[(plank(), 20)]);
+        assert_eq!(game.check_add_shipment(&plank(), 0, 1, 3), Ok(vec![0]));
+        assert_eq!(
+            game.check_add_shipment(&plank(), 0, 1, 11),
+            Err(GameError::NoRoute)
+        );
+
+        assert_eq!(
+            game.check_shipment_quantity(0, 1),
+            Err(GameError::UnknownShipment(0))
+        );
+        game.execute(Command::AddShipment {
+            material: plank(),
+            from_city_id: 0,
+            to_city_id: 1,
+            quantity: 3,
+        })
+        .unwrap();
+        assert_eq!(
+            game.check_shipment_quantity(0, -1),
+            Err(GameError::InvalidQuantity(-1))
+        );
+        assert_eq!(
+            game.check_shipment_quantity(0, 11),
+            Err(GameError::CapacityExceeded { connection_id: 0 })
+        );
+        assert_eq!(game.check_shipment_quantity(0, 10), Ok(()));
+    }
+
+    #[test]
+    fn shipments_move_goods_and_charge_transport_at_round_end() {
+        let mut game = new_game();
+        game.graph.cities[0].get_building_mut(0).unwrap().owner_id = None;
+        testing::give(&mut game, 0, 0, &[(plank(), 5)]);
+        game.execute(Command::AddShipment {
+            material: plank(),
+            from_city_id: 0,
+            to_city_id: 1,
+            quantity: 3,
+        })
+        .unwrap();
+        game.execute(Command::EndTurn).unwrap();
+        game.execute(Command::EndTurn).unwrap();
+
+        let business = &game.graph.businesses[0];
+        assert_eq!(business.inventories[&0][&plank()], 2);
+        assert_eq!(business.inventories[&1][&plank()], 3);
+        assert_eq!(business.shipments.len(), 1);
+        let upkeep = game.graph.connections[0].maintenance_cost;
+        assert_eq!(business.money, 250 - 3 - upkeep);
+    }
+
+    #[test]
+    fn cancelling_a_shipment_keeps_throttles_on_the_right_shipments() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(plank(), 10)]);
+        for quantity in [1, 2, 3] {
+            game.execute(Command::AddShipment {
+                material: plank(),
+                from_city_id: 0,
+                to_city_id: 1,
+                quantity,
+            })
+            .unwrap();
+        }
+        game.graph.businesses[0].throttles = [0, 2]
+            .map(|shipment_id| Throttle {
+                id: ActivityId::Shipment(shipment_id),
+                planned: 6,
+                throughput: 1,
+                shortage: plank(),
+            })
+            .to_vec();
+
+        game.execute(Command::SetShipmentQuantity {
+            shipment_id: 0,
+            quantity: 0,
+        })
+        .unwrap();
+
+        let business = &game.graph.businesses[0];
+        let quantities: Vec<i32> = business
+            .shipments
+            .iter()
+            .map(|shipment| shipment.quantity)
+            .collect();
+        assert_eq!(quantities, vec![2, 3]);
+        let throttled: Vec<ActivityId> = business
+            .throttles
+            .iter()
+            .map(|throttle| throttle.id)
+            .collect();
+        assert_eq!(throttled, vec![ActivityId::Shipment(1)]);
+    }
+
+    #[test]
+    fn propose_trade_checks() {
+        let mut game = new_game();
+        let terms = one_off(1, vec![(plank(), 2)]);
+        assert_eq!(
+            game.check_propose_trade(0, &terms),
+            Err(GameError::UnknownBusiness(0))
+        );
+        assert_eq!(
+            game.check_propose_trade(5, &terms),
+            Err(GameError::UnknownBusiness(5))
+        );
+        assert_eq!(
+            game.check_propose_trade(1, &one_off(99, vec![(plank(), 2)])),
+            Err(GameError::UnknownCity(99))
+        );
+        let empty = Terms {
+            takes: vec![],
+            ..one_off(1, vec![])
+        };
+        assert_eq!(
+            game.check_propose_trade(1, &empty),
+            Err(GameError::InvalidTrade)
+        );
+        assert_eq!(
+            game.check_propose_trade(1, &one_off(1, vec![(testing::material("Gem"), 2)])),
+            Err(GameError::InvalidTrade)
+        );
+        assert_eq!(
+            game.check_propose_trade(1, &one_off(1, vec![(plank(), 0)])),
+            Err(GameError::InvalidQuantity(0))
+        );
+        let negative_penalty = Terms {
+            penalty: -1,
+            ..terms.clone()
+        };
+        assert_eq!(
+            game.check_propose_trade(1, &negative_penalty),
+            Err(GameError::InvalidQuantity(-1))
+        );
+        assert_eq!(
+            game.check_propose_trade(1, &terms),
+            Err(GameError::InsufficientResources(plank()))
+        );
+        let contract = Terms {
+            rounds: 3,
+            ..terms.clone()
+        };
+        assert_eq!(game.check_propose_trade(1, &contract), Ok(()));
+        game.graph.businesses[1].eliminated = true;
+        assert_eq!(
+            game.check_propose_trade(1, &contract),
+            Err(GameError::Bankrupt("Player 2".to_string()))
+        );
+    }
+
+    #[test]
+    fn offer_answer_checks() {
+        let mut game = new_game();
+        game.graph.businesses.push(Business::default());
+        assert_eq!(game.check_accept_offer(0), Err(GameError::UnknownOffer(0)));
+        assert_eq!(game.check_reject_offer(0), Err(GameError::UnknownOffer(0)));
+        let offer = |from_id, to_id, terms| Offer {
+            from_id,
+            to_id,
+            terms,
+        };
+        game.graph.offers = vec![
+            offer(0, 1, one_off(1, vec![(plank(), 2)])),
+            offer(1, 2, one_off(1, vec![(plank(), 2)])),
+            offer(1, 0, one_off(1, vec![(plank(), 2)])),
+            offer(
+                1,
+                0,
+                Terms {
+                    rounds: 3,
+                    ..one_off(5, vec![(plank(), 2)])
+                },
+            ),
+        ];
+        assert_eq!(game.check_accept_offer(0), Err(GameError::NotYourOffer));
+        assert_eq!(game.check_reject_offer(0), Ok(()));
+        assert_eq!(game.check_reject_offer(1), Err(GameError::NotYourOffer));
+        assert_eq!(
+            game.check_accept_offer(2),
+            Err(GameError::InsufficientResources(plank()))
+        );
+        assert_eq!(game.check_accept_offer(3), Err(GameError::NotConnected));
+        assert_eq!(
+            game.check_counter_offer(0, &one_off(1, vec![])),
+            Err(GameError::NotYourOffer)
+        );
+        let counter = Terms {
+            rounds: 3,
+            ..one_off(1, vec![(plank(), 1)])
+        };
+        assert_eq!(game.check_counter_offer(2, &counter), Ok(1));
+    }
+
+    #[test]
+    fn commands_fail_once_the_game_is_over() {
+        let mut game = new_game();
+        game.outcome = Some(Outcome {
+            winner_id: Some(0),
+            round: 1,
+        });
+        assert_eq!(game.execute(Command::EndTurn), Err(GameError::GameOver));
+    }
+}
@@ -0,0 +1,298 @@
+use super::{registry::MaterialId, sale::AssetId, Business, QuantityInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Rounds of history kept in a ledger. Older rounds are dropped.
+pub const LEDGER_ROUNDS: usize = 50;
+
+/// What a resource change was booked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    Production {
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+    },
+    Shipping,
+    Purchase(AssetId),
+    Upgrade(AssetId),
+    Construction(AssetId),
+    Sale(AssetId),
+    Upkeep(AssetId),
+    /// Paid to or received from the owner of a connection.
+    Toll(usize),
+    /// A one-off trade with another business.
+    Trade(usize),
+    /// A contract delivery to or from another business.
+    Contract(usize),
+    /// A contract penalty paid to or by another business.
+    Penalty(usize),
+    /// Anything booked for a building that has since been demolished.
+    Demolished,
+}
+
+impl Source {
+    /// The asset whose running the change is part of. Buying and selling assets is not.
+    pub fn get_operating_asset(&self) -> Option<AssetId> {
+        match *self {
+            Self::Production {
+                city_id,
+                building_id,
+                ..
+            } => Some(AssetId::Building {
+                city_id,
+                building_id,
+            }),
+            Self::Upkeep(asset_id) => Some(asset_id),
+            Self::Toll(connection_id) => Some(AssetId::Connection(connection_id)),
+            _ => None,
+        }
+    }
+
+    pub fn get_kind(&self) -> &'static str {
+        match self {
+            Self::Production { .. } => "Production",
+            Self::Shipping => "Shipping",
+            Self::Purchase(_) => "Purchases",
+            Self::Upgrade(_) => "Upgrades",
+            Self::Construction(_) => "Construction",
+            Self::Sale(_) => "Sales",
+            Self::Upkeep(_) => "Upkeep",
+            Self::Toll(_) => "Tolls",
+            Self::Trade(_) => "Trades",
+            Self::Contract(_) => "Contracts",
+            Self::Penalty(_) => "Penalties",
+            Self::Demolished => "Demolished buildings",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub source: Source,
+    pub material: MaterialId,
+    pub quantity: i32,
+}
+
+/// Every resource change of a business, by round.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Ledger {
+    pub rounds: BTreeMap<u32, Vec<Entry>>,
+}
+
+/// Summary of a round of ledger entries.
+#[derive(Default)]
+pub struct IncomeStatement {
+    /// Value earned and spent running each asset, with materials at market value.
+    pub assets: BTreeMap<AssetId, QuantityInfo>,
+    /// Value earned and spent on everything else, by kind of source.
+    pub other: BTreeMap<&'static str, QuantityInfo>,
+    /// Every material gained and used, money included.
+    pub materials: BTreeMap<MaterialId, QuantityInfo>,
+}
+
+impl Ledger {
+    pub fn record(&mut self, round: u32, source: Source, material: MaterialId, quantity: i32) {
+        if quantity == 0 {
+            return;
+        }
+        self.rounds.entry(round).or_default().push(Entry {
+            source,
+            material,
+            quantity,
+        });
+        while self.rounds.len() > LEDGER_ROUNDS {
+            self.rounds.pop_first();
+        }
+    }
+
+    /// Values materials at `market_values`, as returned by `Graph::get_market_values`.
+    pub fn get_income_statement(
+        &self,
+        round: u32,
+        market_values: &BTreeMap<MaterialId, f32>,
+    ) -> IncomeStatement {
+        let mut income_statement = IncomeStatement::default();
+        for entry in self.rounds.get(&round).into_iter().flatten() {
+            income_statement
+                .materials
+                .entry(entry.material.clone())
+                .or_default()
+                .add_flow(entry.quantity);
+            let market_value = market_values.get(&entry.material).copied().unwrap_or(0.0);
+            let value = (entry.quantity as f32 * market_value).round() as i32;
+            if value == 0 {
+                continue;
+            }
+            let quantity_info = match entry.source.get_operating_asset() {
+                Some(asset_id) => income_statement.assets.entry(asset_id).or_default(),
+                None => income_statement
+                    .other
+                    .entry(entry.source.get_kind())
+                    .or_default(),
+            };
+            quantity_info.add_flow(value);
+        }
+        income_statement
+    }
+
+    /// Books the entries of a removed building as demolished, so a building later constructed in
+    /// its slot is not credited with them.
+    pub fn remove_building(&mut self, city_id: usize, building_id: usize) {
+        let removed = AssetId::Building {
+            city_id,
+            building_id,
+        };
+        for entry in self.rounds.values_mut().flatten() {
+            let asset_id = match entry.source {
+                Source::Production {
+                    city_id,
+                    building_id,
+                    ..
+                } => AssetId::Building {
+                    city_id,
+                    building_id,
+                },
+                Source::Purchase(asset_id)
+                | Source::Upgrade(asset_id)
+                | Source::Construction(asset_id)
+                | Source::Sale(asset_id)
+                | Source::Upkeep(asset_id) => asset_id,
+                _ => continue,
+            };
+            if asset_id == removed {
+                entry.source = Source::Demolished;
+            }
+        }
+    }
+}
+
+impl Business {
+    pub fn add_money(&mut self, round: u32, source: Source, amount: i32) {
+        self.money += amount;
+        self.ledger
+            .record(round, source, MaterialId::money(), amount);
+    }
+
+    /// Adds goods to the stock in a city, or takes them with a negative quantity, and books the
+    /// change.
+    pub fn add_goods(
+        &mut self,
+        round: u32,
+        source: Source,
+        city_id: usize,
+        goods: &[(MaterialId, i32)],
+    ) {
+        for (material, quantity) in goods {
+            if material.is_money() {
+                self.add_money(round, source, *quantity);
+                continue;
+            }
+            *self
+                .inventories
+                .entry(city_id)
+                .or_default()
+                .entry(material.clone())
+                .or_insert(0) += quantity;
+            self.ledger
+                .record(round, source, material.clone(), *quantity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        game::Command,
+        registry::{MaterialId, ValidRecipe},
+        testing,
+    };
+
+    fn log() -> MaterialId {
+        testing::material("Log")
+    }
+
+    #[test]
+    fn income_statements_value_materials_at_market_value() {
+        let sawmill = Source::Production {
+            city_id: 0,
+            building_id: 0,
+            recipe_id: 0,
+        };
+        let mut ledger = Ledger::default();
+        ledger.record(1, sawmill, MaterialId::money(), -5);
+        ledger.record(1, sawmill, log(), 2);
+        ledger.record(1, Source::Shipping, log(), -2);
+        ledger.record(1, Source::Shipping, log(), 2);
+        ledger.record(1, Source::Shipping, testing::material("Gem"), 1);
+        let market_values = BTreeMap::from([(MaterialId::money(), 1.0), (log(), 3.0)]);
+
+        let income_statement = ledger.get_income_statement(1, &market_values);
+        let running = &income_statement.assets[&sawmill.get_operating_asset().unwrap()];
+        assert_eq!((running.gross_in, running.gross_out), (6, 5));
+        let shipping = &income_statement.other["Shipping"];
+        assert_eq!((shipping.gross_in, shipping.gross_out), (6, 6));
+        let logs = &income_statement.materials[&log()];
+        assert_eq!((logs.gross_in, logs.gross_out), (4, 2));
+        assert_eq!(
+            income_statement.materials[&testing::material("Gem")].gross_in,
+            1
+        );
+        assert!(ledger
+            .get_income_statement(2, &market_values)
+            .assets
+            .is_empty());
+    }
+
+    #[test]
+    fn rounds_book_purchases_and_production_by_asset() {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &["Market"])], &[]);
+        game.execute(Command::BuyBuilding {
+            city_id: 0,
+            building_id: 0,
+        })
+        .unwrap();
+        let recipe_id = game.graph.cities[0]
+            .get_building(0)
+            .unwrap()
+            .production_scale
+            .iter()
+            .position(|scaled_valid_recipe| {
+                scaled_valid_recipe.valid_recipe == ValidRecipe::MaterialImport(log())
+            })
+            .unwrap();
+        game.execute(Command::SetRecipeScale {
+            city_id: 0,
+            building_id: 0,
+            recipe_id,
+            scale: 1,
+        })
+        .unwrap();
+        let round = game.round;
+        for _ in 0..game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+
+        let market_values = game.graph.get_market_values(&game.registry);
+        let income_statement = game.graph.businesses[0]
+            .ledger
+            .get_income_statement(round, &market_values);
+        let cost = game.graph.cities[0]
+            .get_building(0)
+            .unwrap()
+            .acquisition_cost;
+        assert_eq!(income_statement.other["Purchases"].gross_out, cost);
+        let logs = income_statement.materials[&log()].gross_in;
+        let paid = income_statement.materials[&MaterialId::money()].gross_out - cost;
+        assert_eq!(logs, game.registry.get_market_price(&log()).quantity);
+        let market = AssetId::Building {
+            city_id: 0,
+            building_id: 0,
+        };
+        assert_eq!(
+            income_statement.assets[&market].net_in(),
+            (logs as f32 * market_values[&log()]).round() as i32 - paid
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use super::{
+    registry::MaterialId,
+    resolution::ActivityId,
+    routing::{Metric, Router},
+    Graph,
+};
+use serde::{Deserialize, Serialize};
+
+/// A standing order to move `quantity` of a material every round along `connection_ids`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Shipment {
+    pub material: MaterialId,
+    pub from_city_id: usize,
+    pub to_city_id: usize,
+    pub quantity: i32,
+    pub connection_ids: Vec<usize>,
+}
+
+impl Graph {
+    pub fn get_connection_load(&self, connection_id: usize) -> i32 {
+        self.businesses
+            .iter()
+            .flat_map(|business| business.shipments.iter())
+            .filter(|shipment| shipment.connection_ids.contains(&connection_id))
+            .map(|shipment| shipment.quantity)
+            .sum()
+    }
+
+    /// Cancels a shipment, keeping the throttles of the business pointed at the right shipments.
+    pub fn remove_shipment(&mut self, business_id: usize, shipment_id: usize) {
+        let business = &mut self.businesses[business_id];
+        business.shipments.remove(shipment_id);
+        business
+            .throttles
+            .retain_mut(|throttle| match &mut throttle.id {
+                ActivityId::Shipment(throttle_shipment_id) => {
+                    if *throttle_shipment_id == shipment_id {
+                        return false;
+                    }
+                    if *throttle_shipment_id > shipment_id {
+                        *throttle_shipment_id -= 1;
+                    }
+                    true
+                }
+                _ => true,
+            });
+    }
+
+    pub fn get_shipping_cost(&self, connection_ids: &[usize], quantity: i32) -> i32 {
+        connection_ids
+            .iter()
+            .map(|connection_id| self.connections[*connection_id].transport_cost)
+            .sum::<i32>()
+            * quantity
+    }
+
+    /// Cheapest route over the connections the business can use that can carry `quantity` more
+    /// units.
+    pub fn find_route(
+        &self,
+        business_id: usize,
+        from_city_id: usize,
+        to_city_id: usize,
+        quantity: i32,
+    ) -> Option<Vec<usize>> {
+        Router::new(self, |connection_id, owned_connection| {
+            self.can_use_connection(business_id, connection_id)
+                && self.get_connection_load(connection_id) + quantity <= owned_connection.capacity
+        })
+        .find_path(from_city_id, to_city_id, Metric::TransportCost)
+        .map(|route| route.connection_ids)
+    }
+}
@@ -0,0 +1,226 @@
+use super::{registry::Registry, City, Graph, OwnedConnection};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct MapParams {
+    pub city_count: usize,
+    pub width: f32,
+    pub height: f32,
+    /// Share of the optional (non spanning tree) Gabriel graph edges to keep, from 0.0 to 1.0.
+    pub connection_density: f64,
+    pub min_city_spacing: f32,
+}
+
+impl Default for MapParams {
+    fn default() -> Self {
+        Self {
+            city_count: 20,
+            width: 900.0,
+            height: 480.0,
+            connection_density: 0.5,
+            min_city_spacing: 120.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl MapSize {
+    pub const ALL: [MapSize; 3] = [MapSize::Small, MapSize::Medium, MapSize::Large];
+
+    pub fn get_params(self) -> MapParams {
+        let (city_count, width, height) = match self {
+            MapSize::Small => (12, 700.0, 400.0),
+            MapSize::Medium => (20, 900.0, 480.0),
+            MapSize::Large => (32, 1300.0, 820.0),
+        };
+        MapParams {
+            city_count,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for MapSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+const PLACEMENT_ATTEMPTS_PER_CITY: usize = 1000;
+
+/// Cities that still overlap others after a bounded number of placement attempts are dropped,
+/// so a spacing too large for the map size yields fewer cities than requested.
+pub fn generate(registry: &Registry, params: &MapParams, rng: &mut impl Rng) -> Graph {
+    let positions = place_cities(params, rng);
+    let connections = connect_cities(&positions, params.connection_density, rng);
+    Graph {
+        cities: positions
+            .into_iter()
+            .map(|(x, y)| City::new_with_random_buildings(registry, rng, x, y))
+            .collect(),
+        connections,
+        businesses: vec![],
+        offers: vec![],
+        contracts: vec![],
+    }
+}
+
+fn place_cities(params: &MapParams, rng: &mut impl Rng) -> Vec<(f32, f32)> {
+    let mut positions: Vec<(f32, f32)> = Vec::with_capacity(params.city_count);
+    let min_distance_squared = params.min_city_spacing * params.min_city_spacing;
+    for _ in 0..params.city_count * PLACEMENT_ATTEMPTS_PER_CITY {
+        if positions.len() == params.city_count {
+            break;
+        }
+        let candidate = (
+            rng.gen_range(0.0..=params.width),
+            rng.gen_range(0.0..=params.height),
+        );
+        if positions
+            .iter()
+            .all(|position| distance_squared(*position, candidate) >= min_distance_squared)
+        {
+            positions.push(candidate);
+        }
+    }
+    positions
+}
+
+fn connect_cities(
+    positions: &[(f32, f32)],
+    connection_density: f64,
+    rng: &mut impl Rng,
+) -> Vec<OwnedConnection> {
+    let mut edges = gabriel_edges(positions);
+    edges.sort_by(|(a, b), (c, d)| {
+        distance_squared(positions[*a], positions[*b])
+            .total_cmp(&distance_squared(positions[*c], positions[*d]))
+    });
+
+    let mut roots: Vec<usize> = (0..positions.len()).collect();
+    let mut connections = vec![];
+    for (city_id_a, city_id_b) in edges {
+        let root_a = find_root(&mut roots, city_id_a);
+        let root_b = find_root(&mut roots, city_id_b);
+        let in_spanning_tree = root_a != root_b;
+        roots[root_a] = root_b;
+        if in_spanning_tree || rng.gen_bool(connection_density.clamp(0.0, 1.0)) {
+            connections.push(OwnedConnection::new(city_id_a, city_id_b));
+        }
+    }
+    connections.sort_by_key(|owned_connection| owned_connection.city_ids.clone());
+    connections
+}
+
+/// Two cities are joined when no third city lies inside or on the circle that has the pair as
+/// its diameter. The result is planar and contains the minimum spanning tree, so it is connected.
+fn gabriel_edges(positions: &[(f32, f32)]) -> Vec<(usize, usize)> {
+    let mut edges = vec![];
+    for (a, position_a) in positions.iter().enumerate() {
+        for (b, position_b) in positions.iter().enumerate().skip(a + 1) {
+            let center = (
+                (position_a.0 + position_b.0) / 2.0,
+                (position_a.1 + position_b.1) / 2.0,
+            );
+            let radius_squared = distance_squared(*position_a, *position_b) / 4.0;
+            let blocked = positions.iter().enumerate().any(|(c, position_c)| {
+                c != a && c != b && distance_squared(center, *position_c) <= radius_squared
+            });
+            if !blocked {
+                edges.push((a, b));
+            }
+        }
+    }
+    edges
+}
+
+pub(super) fn find_root(roots: &mut [usize], city_id: usize) -> usize {
+    let mut root = city_id;
+    while roots[root] != root {
+        roots[root] = roots[roots[root]];
+        root = roots[root];
+    }
+    root
+}
+
+fn distance_squared(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Game, setup::GameSetup};
+
+    fn new_game(seed: u64, map_size: MapSize) -> Game {
+        let setup = GameSetup {
+            seed,
+            map_size,
+            ..Default::default()
+        };
+        Game::new(&setup, Registry::builtin())
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_world() {
+        for map_size in MapSize::ALL {
+            let game = new_game(42, map_size);
+            assert_eq!(
+                serde_json::to_value(&new_game(42, map_size).graph).unwrap(),
+                serde_json::to_value(&game.graph).unwrap()
+            );
+            assert_ne!(
+                serde_json::to_value(&new_game(43, map_size).graph).unwrap(),
+                serde_json::to_value(&game.graph).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn maps_are_connected_planar_and_spaced() {
+        for map_size in MapSize::ALL {
+            let params = map_size.get_params();
+            for seed in 0..8 {
+                let graph = new_game(seed, map_size).graph;
+                assert_eq!(graph.cities.len(), params.city_count);
+                let mut roots: Vec<usize> = (0..graph.cities.len()).collect();
+                for owned_connection in &graph.connections {
+                    let [city_id_a, city_id_b] =
+                        [owned_connection.city_ids[0], owned_connection.city_ids[1]];
+                    assert!(
+                        graph
+                            .find_crossed_connection(city_id_a, city_id_b)
+                            .is_none(),
+                        "seed {} crosses at {:?}",
+                        seed,
+                        owned_connection.city_ids
+                    );
+                    let root_a = find_root(&mut roots, city_id_a);
+                    let root_b = find_root(&mut roots, city_id_b);
+                    roots[root_a] = root_b;
+                }
+                let root = find_root(&mut roots, 0);
+                assert!(
+                    (0..graph.cities.len()).all(|city_id| find_root(&mut roots, city_id) == root)
+                );
+                for (city_id_a, city_a) in graph.cities.iter().enumerate() {
+                    for city_b in &graph.cities[city_id_a + 1..] {
+                        let distance = distance_squared((city_a.x, city_a.y), (city_b.x, city_b.y));
+                        assert!(distance >= params.min_city_spacing.powi(2));
+                    }
+                }
+            }
+        }
+    }
+}
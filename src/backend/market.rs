@@ -0,0 +1,303 @@
+use super::{
+    registry::{MarketPrice, MaterialId, Recipe, Registry, ValidRecipe},
+    resolution::ActivityId,
+    Graph, OwnedBuilding,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MarketParams {
+    /// Gap between the buy and sell price as a share of the mid price.
+    pub spread: f32,
+    /// Relative mid price change per lot of net demand in a round.
+    pub sensitivity: f32,
+    /// Share of the gap to the base price that closes every round.
+    pub recovery: f32,
+    pub min_price_factor: f32,
+    pub max_price_factor: f32,
+}
+
+impl Default for MarketParams {
+    fn default() -> Self {
+        Self {
+            spread: 0.25,
+            sensitivity: 0.01,
+            recovery: 0.5,
+            min_price_factor: 0.25,
+            max_price_factor: 4.0,
+        }
+    }
+}
+
+impl MarketParams {
+    /// Mid price a market settles at once `lots` of net demand trade there every round.
+    pub fn get_settled_price(&self, base_price: f32, lots: i32) -> f32 {
+        let kept = (1.0 - self.recovery) * (1.0 + self.sensitivity).powi(lots);
+        let max_price = base_price * self.max_price_factor;
+        if kept >= 1.0 {
+            return max_price;
+        }
+        (base_price * self.recovery / (1.0 - kept))
+            .clamp(base_price * self.min_price_factor, max_price)
+    }
+
+    /// At least 1 above the sell price, so rounding never closes the spread on cheap materials.
+    pub fn get_buy_price(&self, mid_price: f32) -> i32 {
+        ((mid_price * (1.0 + self.spread / 2.0)).round() as i32)
+            .max(self.get_sell_price(mid_price) + 1)
+    }
+
+    pub fn get_sell_price(&self, mid_price: f32) -> i32 {
+        ((mid_price * (1.0 - self.spread / 2.0)).round() as i32).max(0)
+    }
+}
+
+impl OwnedBuilding {
+    pub fn get_mid_price(&self, registry: &Registry, material: &MaterialId) -> f32 {
+        self.market_prices
+            .get(material)
+            .copied()
+            .unwrap_or_else(|| registry.get_market_price(material).price as f32)
+    }
+
+    pub fn get_buy_price(&self, registry: &Registry, material: &MaterialId) -> i32 {
+        registry
+            .market
+            .get_buy_price(self.get_mid_price(registry, material))
+    }
+
+    pub fn get_sell_price(&self, registry: &Registry, material: &MaterialId) -> i32 {
+        registry
+            .market
+            .get_sell_price(self.get_mid_price(registry, material))
+    }
+
+    /// Lots of a material bought minus lots sold every round at the current scales.
+    pub fn get_net_demand(&self, material: &MaterialId) -> i32 {
+        self.production_scale
+            .iter()
+            .map(
+                |scaled_valid_recipe| match &scaled_valid_recipe.valid_recipe {
+                    ValidRecipe::MaterialImport(imported) if imported == material => {
+                        scaled_valid_recipe.scale
+                    }
+                    ValidRecipe::MaterialExport(exported) if exported == material => {
+                        -scaled_valid_recipe.scale
+                    }
+                    _ => 0,
+                },
+            )
+            .sum()
+    }
+
+    /// Mid price this market settles at once it trades `lots` of net demand in a material every
+    /// round.
+    pub fn get_settled_price(&self, registry: &Registry, material: &MaterialId, lots: i32) -> f32 {
+        let base_price = registry.get_market_price(material).price as f32;
+        registry.market.get_settled_price(base_price, lots)
+    }
+
+    /// Price of a unit of a material once `lots` more lots than now are bought here (`buying`)
+    /// or sold here every round.
+    pub fn get_unit_price(
+        &self,
+        registry: &Registry,
+        material: &MaterialId,
+        buying: bool,
+        lots: i32,
+    ) -> f32 {
+        let demand = self.get_net_demand(material);
+        let quantity = registry.get_market_price(material).quantity as f32;
+        let price = if buying {
+            let mid_price = self.get_settled_price(registry, material, demand + lots);
+            registry.market.get_buy_price(mid_price)
+        } else {
+            let mid_price = self.get_settled_price(registry, material, demand - lots);
+            registry.market.get_sell_price(mid_price)
+        };
+        price as f32 / quantity
+    }
+
+    pub fn get_recipe(&self, registry: &Registry, valid_recipe: &ValidRecipe) -> Recipe {
+        match valid_recipe {
+            ValidRecipe::MaterialImport(material) => Recipe {
+                materials: vec![
+                    (MaterialId::money(), -self.get_buy_price(registry, material)),
+                    (
+                        material.clone(),
+                        registry.get_market_price(material).quantity,
+                    ),
+                ],
+            },
+            ValidRecipe::MaterialExport(material) => Recipe {
+                materials: vec![
+                    (
+                        material.clone(),
+                        -registry.get_market_price(material).quantity,
+                    ),
+                    (MaterialId::money(), self.get_sell_price(registry, material)),
+                ],
+            },
+            ValidRecipe::Production(_) => registry.get_recipe(valid_recipe),
+        }
+    }
+
+    /// Moves mid prices with this round's net demand in lots, then back toward the base price.
+    /// `throughputs` holds the lots each recipe actually traded this round.
+    pub fn update_market_prices(&mut self, registry: &Registry, throughputs: &[i32]) {
+        let mut net_demand: BTreeMap<MaterialId, i32> = BTreeMap::new();
+        for (scaled_valid_recipe, throughput) in self.production_scale.iter().zip(throughputs) {
+            match &scaled_valid_recipe.valid_recipe {
+                ValidRecipe::MaterialImport(material) => {
+                    *net_demand.entry(material.clone()).or_insert(0) += throughput
+                }
+                ValidRecipe::MaterialExport(material) => {
+                    *net_demand.entry(material.clone()).or_insert(0) -= throughput
+                }
+                ValidRecipe::Production(_) => {}
+            }
+        }
+        let params = &registry.market;
+        for (material, lots) in net_demand {
+            let MarketPrice {
+                price: base_price, ..
+            } = registry.get_market_price(&material);
+            let base_price = base_price as f32;
+            let mut mid_price = self.get_mid_price(registry, &material);
+            mid_price *= (1.0 + params.sensitivity).powi(lots);
+            mid_price += (base_price - mid_price) * params.recovery;
+            mid_price = mid_price.clamp(
+                base_price * params.min_price_factor,
+                base_price * params.max_price_factor,
+            );
+            self.market_prices.insert(material, mid_price);
+        }
+    }
+}
+
+impl Graph {
+    /// Moves the prices of every market with what it traded in the round just resolved, throttled
+    /// recipes counting at the throughput they ran at. Markets without an owner trade nothing and
+    /// recover toward their base prices.
+    pub fn update_market_prices(&mut self, registry: &Registry) {
+        let businesses = &self.businesses;
+        for (city_id, city) in self.cities.iter_mut().enumerate() {
+            for (building_id, owned_building) in city.get_buildings_mut() {
+                let throttles = owned_building
+                    .owner_id
+                    .map_or(&[][..], |owner_id| &businesses[owner_id].throttles);
+                let throughputs: Vec<i32> = owned_building
+                    .production_scale
+                    .iter()
+                    .enumerate()
+                    .map(|(recipe_id, scaled_valid_recipe)| {
+                        let id = ActivityId::Recipe {
+                            city_id,
+                            building_id,
+                            recipe_id,
+                        };
+                        throttles
+                            .iter()
+                            .find(|throttle| throttle.id == id)
+                            .map_or(scaled_valid_recipe.scale, |throttle| throttle.throughput)
+                    })
+                    .collect();
+                owned_building.update_market_prices(registry, &throughputs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Game, resolution::Throttle, testing};
+
+    const IMPORT_LOG: usize = 1;
+
+    fn log() -> MaterialId {
+        testing::material("Log")
+    }
+
+    /// Player 0 owns the market in city 0 and imports logs there at scale 5.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &["Market"])], &[]);
+        testing::own_building(&mut game, 0, 0, 0);
+        let market = game.graph.cities[0].get_building_mut(0).unwrap();
+        assert_eq!(
+            market.production_scale[IMPORT_LOG].valid_recipe,
+            ValidRecipe::MaterialImport(log())
+        );
+        market.production_scale[IMPORT_LOG].scale = 5;
+        game
+    }
+
+    fn get_mid_price(game: &Game) -> f32 {
+        game.graph.cities[0]
+            .get_building(0)
+            .unwrap()
+            .get_mid_price(&game.registry, &log())
+    }
+
+    #[test]
+    fn cheap_materials_keep_a_spread() {
+        let params = MarketParams::default();
+        for mid_price in [0.0, 0.4, 1.0, 1.6, 2.0] {
+            assert!(params.get_buy_price(mid_price) > params.get_sell_price(mid_price));
+        }
+        assert_eq!(params.get_sell_price(-1.0), 0);
+    }
+
+    #[test]
+    fn prices_move_by_the_lots_actually_traded() {
+        let mut game = new_game();
+        let base_price = get_mid_price(&game);
+        game.graph.update_market_prices(&game.registry);
+        let params = game.registry.market;
+        let moved = base_price * (1.0 + params.sensitivity).powi(5);
+        let expected = moved + (base_price - moved) * params.recovery;
+        assert!((get_mid_price(&game) - expected).abs() < 1e-4);
+
+        let mut throttled = new_game();
+        throttled.graph.businesses[0].throttles.push(Throttle {
+            id: ActivityId::Recipe {
+                city_id: 0,
+                building_id: 0,
+                recipe_id: IMPORT_LOG,
+            },
+            planned: 5,
+            throughput: 2,
+            shortage: MaterialId::money(),
+        });
+        throttled.graph.update_market_prices(&throttled.registry);
+        assert!(get_mid_price(&throttled) > base_price);
+        assert!(get_mid_price(&throttled) < get_mid_price(&game));
+    }
+
+    #[test]
+    fn prices_settle_where_the_market_predicts() {
+        let mut game = new_game();
+        for _ in 0..50 {
+            game.graph.update_market_prices(&game.registry);
+        }
+        let settled = game.graph.cities[0]
+            .get_building(0)
+            .unwrap()
+            .get_settled_price(&game.registry, &log(), 5);
+        assert!((get_mid_price(&game) - settled).abs() < 1e-3);
+    }
+
+    #[test]
+    fn markets_recover_after_they_are_sold() {
+        let mut game = new_game();
+        let base_price = get_mid_price(&game);
+        let market = game.graph.cities[0].get_building_mut(0).unwrap();
+        market.market_prices.insert(log(), base_price * 3.0);
+        market.release();
+        for _ in 0..20 {
+            game.graph.update_market_prices(&game.registry);
+        }
+        assert!((get_mid_price(&game) - base_price).abs() < 1e-3);
+    }
+}
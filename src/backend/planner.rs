@@ -0,0 +1,473 @@
+use super::{
+    registry::{BuildingTypeId, MarketPrice, MaterialId, Registry, ValidRecipe},
+    Graph, OwnedBuilding,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A material to export every round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub material: MaterialId,
+    pub quantity: i32,
+}
+
+impl Target {
+    /// One unit per round of the first material that can be exported.
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            material: Self::get_materials(registry)
+                .into_iter()
+                .next()
+                .unwrap_or_else(MaterialId::money),
+            quantity: 1,
+        }
+    }
+
+    /// Materials some building type exports.
+    pub fn get_materials(registry: &Registry) -> Vec<MaterialId> {
+        registry
+            .building_types
+            .values()
+            .flat_map(|building_type_def| building_type_def.exports.iter().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// A recipe a plan runs, and where a business can run it.
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub valid_recipe: ValidRecipe,
+    pub scale: i32,
+    /// Building types that run the recipe, some only after an upgrade.
+    pub building_types: Vec<BuildingTypeId>,
+    /// Scale the business can run in buildings it owns.
+    pub owned_scale: i32,
+    /// Unowned buildings running the recipe that the business can buy, as city and building ids.
+    pub reachable_buildings: Vec<(usize, usize)>,
+    pub reachable_scale: i32,
+}
+
+impl PlanStep {
+    /// Scale neither owned nor for sale within reach, which has to be constructed or upgraded.
+    pub fn get_gap(&self) -> i32 {
+        (self.scale - self.owned_scale - self.reachable_scale).max(0)
+    }
+}
+
+/// Recipes needed to meet a target, consumers before the recipes supplying them.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+    /// Materials needed that no building type imports or makes without looping back to them.
+    pub unsourced: Vec<MaterialId>,
+    /// Materials made per round beyond what the plan uses.
+    pub surplus: BTreeMap<MaterialId, i32>,
+    /// Money earned per round before upkeep, trading at the best prices of the markets the
+    /// business owns or can buy once they settle under the plan. Markets out of reach count at
+    /// base prices.
+    pub money_per_round: i32,
+}
+
+/// Building types that run a recipe at their first level, then those that unlock it later.
+pub fn get_building_types(registry: &Registry, valid_recipe: &ValidRecipe) -> Vec<BuildingTypeId> {
+    let (base, upgraded): (Vec<_>, Vec<_>) = registry
+        .building_types
+        .iter()
+        .filter_map(|(building_type, building_type_def)| {
+            if registry
+                .get_valid_recipes(building_type)
+                .contains(valid_recipe)
+            {
+                return Some((building_type.clone(), false));
+            }
+            let ValidRecipe::Production(recipe_id) = valid_recipe else {
+                return None;
+            };
+            building_type_def
+                .upgrades
+                .iter()
+                .any(|upgrade| upgrade.recipes.contains(recipe_id))
+                .then(|| (building_type.clone(), true))
+        })
+        .partition(|(_, upgraded)| !upgraded);
+    base.into_iter()
+        .chain(upgraded)
+        .map(|(building_type, _)| building_type)
+        .collect()
+}
+
+/// Quantity of a material a recipe makes per unit of scale, negative when it uses it.
+pub fn get_yield(registry: &Registry, valid_recipe: &ValidRecipe, material: &MaterialId) -> i32 {
+    registry
+        .get_recipe(valid_recipe)
+        .materials
+        .iter()
+        .filter(|(recipe_material, _)| recipe_material == material)
+        .map(|(_, quantity)| quantity)
+        .sum()
+}
+
+/// Cheapest recipe to get a unit of a material from and its cost at base prices, importing on a
+/// tie unless `importable` is unset. Materials on `path` are not used as inputs, so supply chains
+/// never loop.
+fn get_source(
+    registry: &Registry,
+    material: &MaterialId,
+    importable: bool,
+    path: &mut Vec<MaterialId>,
+) -> Option<(f32, ValidRecipe)> {
+    let import = ValidRecipe::MaterialImport(material.clone());
+    let mut candidates = vec![];
+    if importable && !get_building_types(registry, &import).is_empty() {
+        let MarketPrice { quantity, price } = registry.get_market_price(material);
+        candidates.push((price as f32 / quantity as f32, import));
+    }
+    path.push(material.clone());
+    for recipe_id in registry.recipes.keys() {
+        let valid_recipe = ValidRecipe::Production(recipe_id.clone());
+        let output = get_yield(registry, &valid_recipe, material);
+        if output <= 0 || get_building_types(registry, &valid_recipe).is_empty() {
+            continue;
+        }
+        let input_cost: Option<f32> = registry
+            .get_recipe(&valid_recipe)
+            .materials
+            .iter()
+            .filter(|(_, quantity)| *quantity < 0)
+            .map(|(input, quantity)| {
+                if input.is_money() {
+                    Some(-quantity as f32)
+                } else if path.contains(input) {
+                    None
+                } else {
+                    get_source(registry, input, true, path).map(|(cost, _)| cost * -quantity as f32)
+                }
+            })
+            .sum();
+        if let Some(input_cost) = input_cost {
+            candidates.push((input_cost / output as f32, valid_recipe));
+        }
+    }
+    path.pop();
+    candidates.into_iter().reduce(|best, candidate| {
+        if candidate.0 < best.0 {
+            candidate
+        } else {
+            best
+        }
+    })
+}
+
+/// Picks a source for a material and everything it is made from, keeping the first pick of each.
+/// The target itself is never imported, as buying it only to sell it again loses the spread.
+fn choose_sources(
+    registry: &Registry,
+    material: &MaterialId,
+    path: &mut Vec<MaterialId>,
+    sources: &mut BTreeMap<MaterialId, Option<ValidRecipe>>,
+) {
+    if material.is_money() || sources.contains_key(material) {
+        return;
+    }
+    let importable = !path.is_empty();
+    let source =
+        get_source(registry, material, importable, path).map(|(_, valid_recipe)| valid_recipe);
+    sources.insert(material.clone(), source.clone());
+    let Some(valid_recipe) = source else {
+        return;
+    };
+    path.push(material.clone());
+    for (input, quantity) in registry.get_recipe(&valid_recipe).materials {
+        if quantity < 0 {
+            choose_sources(registry, &input, path, sources);
+        }
+    }
+    path.pop();
+}
+
+/// Adds the inputs of a material before the material. Reversed, every material comes after all
+/// the materials made from it.
+fn sort_materials(
+    registry: &Registry,
+    material: &MaterialId,
+    sources: &BTreeMap<MaterialId, Option<ValidRecipe>>,
+    visited: &mut BTreeSet<MaterialId>,
+    order: &mut Vec<MaterialId>,
+) {
+    if !visited.insert(material.clone()) {
+        return;
+    }
+    if let Some(Some(valid_recipe)) = sources.get(material) {
+        for (input, quantity) in registry.get_recipe(valid_recipe).materials {
+            if quantity < 0 && !input.is_money() {
+                sort_materials(registry, &input, sources, visited, order);
+            }
+        }
+    }
+    order.push(material.clone());
+}
+
+/// Money a recipe earns per round at `scale`. Markets trade at the best price among `markets`
+/// once it settles under the extra volume, or at base prices when there are none.
+fn get_money(
+    registry: &Registry,
+    valid_recipe: &ValidRecipe,
+    scale: i32,
+    markets: &[&OwnedBuilding],
+) -> f32 {
+    let (material, buying) = match valid_recipe {
+        ValidRecipe::MaterialImport(material) => (material, true),
+        ValidRecipe::MaterialExport(material) => (material, false),
+        ValidRecipe::Production(_) => {
+            return (scale * get_yield(registry, valid_recipe, &MaterialId::money())) as f32;
+        }
+    };
+    let unit_prices = markets
+        .iter()
+        .map(|market| market.get_unit_price(registry, material, buying, scale));
+    let unit_price_opt = if buying {
+        unit_prices.reduce(f32::min)
+    } else {
+        unit_prices.reduce(f32::max)
+    };
+    let unit_price = unit_price_opt.unwrap_or_else(|| {
+        let MarketPrice { quantity, price } = registry.get_market_price(material);
+        let price = if buying {
+            registry.market.get_buy_price(price as f32)
+        } else {
+            registry.market.get_sell_price(price as f32)
+        };
+        price as f32 / quantity as f32
+    });
+    // Imports yield the material they are paid for and exports use up what they are paid for.
+    -unit_price * (scale * get_yield(registry, valid_recipe, material)) as f32
+}
+
+impl Graph {
+    /// Works back from the target through the cheapest recipes at base prices, and compares the
+    /// scale each needs with what the business owns and can buy.
+    pub fn plan_production(
+        &self,
+        registry: &Registry,
+        business_id: usize,
+        target: &Target,
+    ) -> Plan {
+        let export = ValidRecipe::MaterialExport(target.material.clone());
+        let lot = -get_yield(registry, &export, &target.material);
+        let mut scales = vec![(
+            export,
+            (target.quantity as f32 / lot.max(1) as f32).ceil() as i32,
+        )];
+        let mut demand = BTreeMap::from([(target.material.clone(), scales[0].1 * lot)]);
+
+        let mut sources = BTreeMap::new();
+        choose_sources(registry, &target.material, &mut vec![], &mut sources);
+        let mut order = vec![];
+        sort_materials(
+            registry,
+            &target.material,
+            &sources,
+            &mut BTreeSet::new(),
+            &mut order,
+        );
+        let mut unsourced = vec![];
+        for material in order.into_iter().rev() {
+            let quantity = demand.get(&material).copied().unwrap_or(0);
+            if quantity <= 0 {
+                continue;
+            }
+            let Some(valid_recipe) = sources[&material].clone() else {
+                unsourced.push(material);
+                continue;
+            };
+            let output = get_yield(registry, &valid_recipe, &material);
+            let scale = (quantity + output - 1) / output;
+            for (input, input_quantity) in registry.get_recipe(&valid_recipe).materials {
+                if input_quantity < 0 {
+                    *demand.entry(input).or_insert(0) -= scale * input_quantity;
+                }
+            }
+            scales.push((valid_recipe, scale));
+        }
+
+        let mut net: BTreeMap<MaterialId, i32> = BTreeMap::new();
+        for (valid_recipe, scale) in &scales {
+            for (material, quantity) in registry.get_recipe(valid_recipe).materials {
+                if !material.is_money() {
+                    *net.entry(material).or_insert(0) += scale * quantity;
+                }
+            }
+        }
+        net.retain(|_, quantity| *quantity > 0);
+
+        let mut reachable_city_ids = self.get_network_city_ids(business_id);
+        if reachable_city_ids.is_empty() {
+            reachable_city_ids = (0..self.cities.len()).collect();
+        }
+        let mut money_per_round = 0.0;
+        let steps = scales
+            .into_iter()
+            .map(|(valid_recipe, scale)| {
+                let mut owned_scale = 0;
+                let mut reachable_buildings = vec![];
+                let mut reachable_scale = 0;
+                let mut markets = vec![];
+                for (city_id, city) in self.cities.iter().enumerate() {
+                    for (building_id, owned_building) in city.get_buildings() {
+                        let Some(max_scale) = owned_building
+                            .production_scale
+                            .iter()
+                            .find(|scaled_valid_recipe| {
+                                scaled_valid_recipe.valid_recipe == valid_recipe
+                            })
+                            .map(|scaled_valid_recipe| scaled_valid_recipe.max_scale)
+                        else {
+                            continue;
+                        };
+                        match owned_building.owner_id {
+                            Some(owner_id) if owner_id == business_id => owned_scale += max_scale,
+                            None if reachable_city_ids.contains(&city_id) => {
+                                reachable_buildings.push((city_id, building_id));
+                                reachable_scale += max_scale;
+                            }
+                            _ => continue,
+                        }
+                        markets.push(owned_building);
+                    }
+                }
+                money_per_round += get_money(registry, &valid_recipe, scale, &markets);
+                PlanStep {
+                    building_types: get_building_types(registry, &valid_recipe),
+                    valid_recipe,
+                    scale,
+                    owned_scale,
+                    reachable_buildings,
+                    reachable_scale,
+                }
+            })
+            .collect();
+        Plan {
+            steps,
+            unsourced,
+            surplus: net,
+            money_per_round: money_per_round.round() as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Game, registry::RecipeId, testing};
+
+    /// Player 0 owns the sawmill in city 0, which also has a woodworking market and a market.
+    /// City 1 has another woodworking market.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &["WoodWorkingMarket", "Sawmill", "Market"]),
+                (200.0, 0.0, &["WoodWorkingMarket"]),
+            ],
+            &[(0, 1)],
+        );
+        testing::own_building(&mut game, 0, 0, 1);
+        game
+    }
+
+    fn plan_for(game: &Game, business_id: usize, material: &str) -> Plan {
+        let target = Target {
+            material: testing::material(material),
+            quantity: 8,
+        };
+        game.graph
+            .plan_production(&game.registry, business_id, &target)
+    }
+
+    fn get_scales(plan: &Plan) -> Vec<(ValidRecipe, i32)> {
+        plan.steps
+            .iter()
+            .map(|step| (step.valid_recipe.clone(), step.scale))
+            .collect()
+    }
+
+    #[test]
+    fn plans_work_back_from_the_target_to_its_supplies() {
+        let game = new_game();
+        let plan = plan_for(&game, 0, "Plank");
+        assert_eq!(
+            get_scales(&plan),
+            vec![
+                (ValidRecipe::MaterialExport(testing::material("Plank")), 8),
+                (
+                    ValidRecipe::Production(RecipeId("PlankProduction".to_string())),
+                    2
+                ),
+                (ValidRecipe::MaterialImport(testing::material("Log")), 2),
+            ]
+        );
+        assert!(plan.unsourced.is_empty());
+        assert!(plan.surplus.is_empty());
+
+        let [export, production, import] = &plan.steps[..] else {
+            panic!("three steps expected");
+        };
+        assert_eq!(export.reachable_buildings, vec![(0, 0)]);
+        assert_eq!(export.get_gap(), 3);
+        assert_eq!((production.owned_scale, production.get_gap()), (5, 0));
+        assert_eq!(import.reachable_buildings, vec![(0, 2)]);
+    }
+
+    #[test]
+    fn the_target_is_made_rather_than_bought_to_sell_again() {
+        let game = new_game();
+        let plan = plan_for(&game, 0, "Log");
+        assert_eq!(
+            plan.steps[1].valid_recipe,
+            ValidRecipe::Production(RecipeId("Forestation".to_string()))
+        );
+        assert!(
+            plan.steps
+                .iter()
+                .all(|step| step.valid_recipe
+                    != ValidRecipe::MaterialImport(testing::material("Log")))
+        );
+    }
+
+    #[test]
+    fn plans_earn_what_markets_pay_under_their_volume() {
+        let game = new_game();
+        let city = &game.graph.cities[0];
+        let get_unit_price = |building_id, material, buying, lots| {
+            city.get_building(building_id).unwrap().get_unit_price(
+                &game.registry,
+                &testing::material(material),
+                buying,
+                lots,
+            )
+        };
+        let plan = plan_for(&game, 0, "Plank");
+        let expected =
+            8.0 * get_unit_price(0, "Plank", false, 8) - 2.0 * get_unit_price(2, "Log", true, 2);
+        assert_eq!(plan.money_per_round, expected.round() as i32);
+
+        // No energy market is in reach, so energy costs its base buy price.
+        let plan = plan_for(&game, 0, "Log");
+        let energy_price = game.registry.market.get_buy_price(2.0);
+        let expected = 8.0 * get_unit_price(2, "Log", false, 8) - 8.0 * energy_price as f32;
+        assert_eq!(plan.money_per_round, expected.round() as i32);
+    }
+
+    #[test]
+    fn businesses_without_assets_can_buy_anywhere() {
+        let game = new_game();
+        let plan = plan_for(&game, 1, "Plank");
+        let export = &plan.steps[0];
+        assert_eq!(export.reachable_buildings, vec![(0, 0), (1, 0)]);
+        assert_eq!(export.get_gap(), 0);
+        let production = &plan.steps[1];
+        assert_eq!((production.owned_scale, production.reachable_scale), (0, 0));
+        assert_eq!(production.get_gap(), 2);
+    }
+}
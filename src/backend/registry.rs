@@ -0,0 +1,398 @@
+use super::{construction::ConnectionConstructionParams, market::MarketParams};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+const BUILTIN_REGISTRY: &str = include_str!("../../assets/data/registry.toml");
+pub const MONEY: &str = "Money";
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MaterialId(pub String);
+
+impl MaterialId {
+    pub fn money() -> Self {
+        Self(MONEY.to_string())
+    }
+    pub fn is_money(&self) -> bool {
+        self.0 == MONEY
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecipeId(pub String);
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BuildingTypeId(pub String);
+
+macro_rules! impl_id_display {
+    ($($id:ty),*) => {$(
+        impl fmt::Display for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    )*};
+}
+
+impl_id_display!(MaterialId, RecipeId, BuildingTypeId);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MarketPrice {
+    pub quantity: i32,
+    pub price: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaterialDef {
+    pub id: MaterialId,
+    pub texture: String,
+    pub market: Option<MarketPrice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeDef {
+    pub id: RecipeId,
+    pub materials: Vec<(MaterialId, i32)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeDef {
+    pub cost: i32,
+    pub max_scale: i32,
+    /// Recipes the building can run from this level on.
+    #[serde(default)]
+    pub recipes: Vec<RecipeId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConstructionDef {
+    pub rounds: u32,
+    /// Taken from the stock of the city the building goes up in, on top of its acquisition cost.
+    pub materials: Vec<(MaterialId, i32)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildingTypeDef {
+    pub id: BuildingTypeId,
+    pub acquisition_cost: i32,
+    pub max_scale: i32,
+    /// Money paid every round the building is owned.
+    pub upkeep: i32,
+    /// Extra upkeep per unit of scale its recipes run at.
+    pub upkeep_per_scale: f32,
+    #[serde(default)]
+    pub recipes: Vec<RecipeId>,
+    #[serde(default)]
+    pub imports: Vec<MaterialId>,
+    #[serde(default)]
+    pub exports: Vec<MaterialId>,
+    /// Levels a building can be upgraded through, in order.
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeDef>,
+    /// Building types without it can only be bought where the map placed them.
+    #[serde(default)]
+    pub construction: Option<ConstructionDef>,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+pub enum ValidRecipe {
+    MaterialImport(MaterialId),
+    MaterialExport(MaterialId),
+    Production(RecipeId),
+}
+
+impl fmt::Display for ValidRecipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaterialImport(material) => write!(f, "Import {}", material),
+            Self::MaterialExport(material) => write!(f, "Export {}", material),
+            Self::Production(recipe_id) => write!(f, "{}", recipe_id),
+        }
+    }
+}
+
+pub struct Recipe {
+    pub materials: Vec<(MaterialId, i32)>,
+}
+
+#[derive(Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    market: MarketParams,
+    #[serde(default)]
+    connection_construction: ConnectionConstructionParams,
+    materials: Vec<MaterialDef>,
+    recipes: Vec<RecipeDef>,
+    building_types: Vec<BuildingTypeDef>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    DuplicateId(String),
+    UnknownMaterial(MaterialId),
+    UnknownRecipe(RecipeId),
+    MissingMarketPrice(MaterialId),
+    MissingMoney,
+    NoBuildingTypes,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not read game data: {}", err),
+            Self::Parse(err) => write!(f, "Invalid game data: {}", err),
+            Self::DuplicateId(id) => write!(f, "{} is defined more than once", id),
+            Self::UnknownMaterial(id) => write!(f, "Unknown material {}", id),
+            Self::UnknownRecipe(id) => write!(f, "Unknown recipe {}", id),
+            Self::MissingMarketPrice(id) => {
+                write!(f, "{} is traded on a market but has no market price", id)
+            }
+            Self::MissingMoney => write!(f, "The {} material is required", MONEY),
+            Self::NoBuildingTypes => write!(f, "At least one building type is required"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub market: MarketParams,
+    pub connection_construction: ConnectionConstructionParams,
+    pub materials: BTreeMap<MaterialId, MaterialDef>,
+    pub recipes: BTreeMap<RecipeId, RecipeDef>,
+    pub building_types: BTreeMap<BuildingTypeId, BuildingTypeDef>,
+}
+
+impl Registry {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_REGISTRY).expect("builtin game data is valid")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        Self::parse(&fs::read_to_string(path).map_err(RegistryError::Io)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, RegistryError> {
+        let file: RegistryFile = toml::from_str(contents).map_err(RegistryError::Parse)?;
+        let mut registry = Self {
+            market: file.market,
+            connection_construction: file.connection_construction,
+            materials: BTreeMap::new(),
+            recipes: BTreeMap::new(),
+            building_types: BTreeMap::new(),
+        };
+        for material in file.materials {
+            if let Some(duplicate) = registry.materials.insert(material.id.clone(), material) {
+                return Err(RegistryError::DuplicateId(duplicate.id.0));
+            }
+        }
+        for recipe in file.recipes {
+            if let Some(duplicate) = registry.recipes.insert(recipe.id.clone(), recipe) {
+                return Err(RegistryError::DuplicateId(duplicate.id.0));
+            }
+        }
+        for building_type in file.building_types {
+            if let Some(duplicate) = registry
+                .building_types
+                .insert(building_type.id.clone(), building_type)
+            {
+                return Err(RegistryError::DuplicateId(duplicate.id.0));
+            }
+        }
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    pub fn validate(&self) -> Result<(), RegistryError> {
+        if !self.materials.contains_key(&MaterialId::money()) {
+            return Err(RegistryError::MissingMoney);
+        }
+        if self.building_types.is_empty() {
+            return Err(RegistryError::NoBuildingTypes);
+        }
+        for (material, _) in &self.connection_construction.materials {
+            self.material(material)?;
+        }
+        for recipe in self.recipes.values() {
+            for (material, _) in &recipe.materials {
+                self.material(material)?;
+            }
+        }
+        for building_type in self.building_types.values() {
+            for recipe in building_type.recipes.iter().chain(
+                building_type
+                    .upgrades
+                    .iter()
+                    .flat_map(|upgrade| &upgrade.recipes),
+            ) {
+                if !self.recipes.contains_key(recipe) {
+                    return Err(RegistryError::UnknownRecipe(recipe.clone()));
+                }
+            }
+            for (material, _) in building_type
+                .construction
+                .iter()
+                .flat_map(|construction| &construction.materials)
+            {
+                self.material(material)?;
+            }
+            for material in building_type.imports.iter().chain(&building_type.exports) {
+                if self.material(material)?.market.is_none() {
+                    return Err(RegistryError::MissingMarketPrice(material.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn material(&self, material: &MaterialId) -> Result<&MaterialDef, RegistryError> {
+        self.materials
+            .get(material)
+            .ok_or_else(|| RegistryError::UnknownMaterial(material.clone()))
+    }
+
+    pub fn get_texture_id(&self, material: &MaterialId) -> &str {
+        &self.materials[material].texture
+    }
+
+    pub fn get_valid_recipes(&self, building_type: &BuildingTypeId) -> Vec<ValidRecipe> {
+        let building_type = &self.building_types[building_type];
+        building_type
+            .recipes
+            .iter()
+            .cloned()
+            .map(ValidRecipe::Production)
+            .chain(
+                building_type
+                    .imports
+                    .iter()
+                    .cloned()
+                    .map(ValidRecipe::MaterialImport),
+            )
+            .chain(
+                building_type
+                    .exports
+                    .iter()
+                    .cloned()
+                    .map(ValidRecipe::MaterialExport),
+            )
+            .collect()
+    }
+
+    /// Base price of a lot of the material. Materials no market trades are worth nothing, in lots
+    /// of one.
+    pub fn get_market_price(&self, material: &MaterialId) -> MarketPrice {
+        self.materials
+            .get(material)
+            .and_then(|material_def| material_def.market)
+            .unwrap_or(MarketPrice {
+                quantity: 1,
+                price: 0,
+            })
+    }
+
+    /// Recipe at base market prices. Use `OwnedBuilding::get_recipe` for a market's current prices.
+    pub fn get_recipe(&self, valid_recipe: &ValidRecipe) -> Recipe {
+        Recipe {
+            materials: match valid_recipe {
+                ValidRecipe::MaterialImport(material) => {
+                    let MarketPrice { quantity, price } = self.get_market_price(material);
+                    vec![(MaterialId::money(), -price), (material.clone(), quantity)]
+                }
+                ValidRecipe::MaterialExport(material) => {
+                    let MarketPrice { quantity, price } = self.get_market_price(material);
+                    vec![(material.clone(), -quantity), (MaterialId::money(), price)]
+                }
+                ValidRecipe::Production(recipe) => self.recipes[recipe].materials.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+        [[materials]]
+        id = "Money"
+        texture = "money"
+
+        [[materials]]
+        id = "Log"
+        texture = "logs"
+        market = { quantity = 1, price = 2 }
+
+        [[recipes]]
+        id = "Forestation"
+        materials = [["Money", -1], ["Log", 1]]
+
+        [[building_types]]
+        id = "TreeFarm"
+        acquisition_cost = 50
+        max_scale = 5
+        upkeep = 1
+        upkeep_per_scale = 0.2
+        recipes = ["Forestation"]
+        exports = ["Log"]
+    "#;
+
+    fn parse_with(from: &str, to: &str) -> Result<Registry, RegistryError> {
+        assert!(MINIMAL.contains(from));
+        Registry::parse(&MINIMAL.replacen(from, to, 1))
+    }
+
+    #[test]
+    fn builtin_and_minimal_data_are_valid() {
+        let registry = Registry::builtin();
+        assert!(registry.validate().is_ok());
+        assert!(registry.materials.contains_key(&MaterialId::money()));
+        let registry = Registry::parse(MINIMAL).unwrap();
+        assert_eq!(
+            registry.get_valid_recipes(&BuildingTypeId("TreeFarm".to_string())),
+            vec![
+                ValidRecipe::Production(RecipeId("Forestation".to_string())),
+                ValidRecipe::MaterialExport(MaterialId("Log".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        assert!(matches!(
+            parse_with("id = \"Money\"", "id = \"Cash\""),
+            Err(RegistryError::MissingMoney)
+        ));
+        assert!(matches!(
+            parse_with("id = \"Log\"", "id = \"Money\""),
+            Err(RegistryError::DuplicateId(id)) if id == "Money"
+        ));
+        assert!(matches!(
+            parse_with("[\"Log\", 1]", "[\"Wood\", 1]"),
+            Err(RegistryError::UnknownMaterial(MaterialId(id))) if id == "Wood"
+        ));
+        assert!(matches!(
+            parse_with("recipes = [\"Forestation\"]", "recipes = [\"Logging\"]"),
+            Err(RegistryError::UnknownRecipe(RecipeId(id))) if id == "Logging"
+        ));
+        assert!(matches!(
+            parse_with("market = { quantity = 1, price = 2 }", ""),
+            Err(RegistryError::MissingMarketPrice(MaterialId(id))) if id == "Log"
+        ));
+        assert!(matches!(
+            parse_with("[[building_types]]", "[[unused]]"),
+            Err(RegistryError::Parse(_))
+        ));
+        let without_building_types = &MINIMAL[..MINIMAL.find("[[building_types]]").unwrap()];
+        assert!(matches!(
+            Registry::parse(&format!("building_types = []\n{}", without_building_types)),
+            Err(RegistryError::NoBuildingTypes)
+        ));
+    }
+}
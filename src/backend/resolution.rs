@@ -0,0 +1,347 @@
+use super::{
+    registry::{MaterialId, Registry},
+    Graph, QuantityInfo, ScaledValidRecipe,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActivityId {
+    Recipe {
+        city_id: usize,
+        building_id: usize,
+        recipe_id: usize,
+    },
+    Shipment(usize),
+}
+
+/// Something a business runs every round, as flows per unit of throughput.
+pub struct Activity {
+    pub id: ActivityId,
+    pub priority: i32,
+    pub planned: i32,
+    pub unit_flows: Vec<((usize, MaterialId), i32)>,
+}
+
+/// An activity that ran below its planned throughput because `shortage` ran out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Throttle {
+    pub id: ActivityId,
+    pub planned: i32,
+    pub throughput: i32,
+    pub shortage: MaterialId,
+}
+
+/// Money is pooled across cities, every other material is stocked per city.
+type StockKey = (Option<usize>, MaterialId);
+
+fn get_stock_key(city_id: usize, material: &MaterialId) -> StockKey {
+    ((!material.is_money()).then_some(city_id), material.clone())
+}
+
+impl Graph {
+    /// Active recipes and shipments of a business, highest priority first. Shipments have
+    /// priority 0 and come after recipes of the same priority.
+    pub fn get_activities(&self, registry: &Registry, business_id: usize) -> Vec<Activity> {
+        let mut activities = Vec::new();
+        for (city_id, city) in self.cities.iter().enumerate() {
+            for (building_id, owned_building) in city
+                .get_buildings()
+                .filter(|(_, owned_building)| owned_building.owner_id == Some(business_id))
+            {
+                for (
+                    recipe_id,
+                    ScaledValidRecipe {
+                        valid_recipe,
+                        scale,
+                        priority,
+                        ..
+                    },
+                ) in owned_building
+                    .production_scale
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, s)| s.scale != 0)
+                {
+                    activities.push(Activity {
+                        id: ActivityId::Recipe {
+                            city_id,
+                            building_id,
+                            recipe_id,
+                        },
+                        priority: *priority,
+                        planned: *scale,
+                        unit_flows: owned_building
+                            .get_recipe(registry, valid_recipe)
+                            .materials
+                            .into_iter()
+                            .map(|(material, quantity)| ((city_id, material), quantity))
+                            .collect(),
+                    });
+                }
+            }
+        }
+        for (shipment_id, shipment) in self.businesses[business_id].shipments.iter().enumerate() {
+            activities.push(Activity {
+                id: ActivityId::Shipment(shipment_id),
+                priority: 0,
+                planned: shipment.quantity,
+                unit_flows: vec![
+                    ((shipment.from_city_id, shipment.material.clone()), -1),
+                    ((shipment.to_city_id, shipment.material.clone()), 1),
+                    (
+                        (shipment.from_city_id, MaterialId::money()),
+                        -self.get_shipping_cost(&shipment.connection_ids, 1),
+                    ),
+                ],
+            });
+        }
+        activities.sort_by_key(|activity| -activity.priority);
+        activities
+    }
+
+    /// Throughput of every activity this round. Whenever a stock would end the round negative,
+    /// the lowest priority activity consuming it is cut back until the stock is covered.
+    pub fn resolve_throughputs(
+        &self,
+        activities: &[Activity],
+        business_id: usize,
+    ) -> (Vec<i32>, Vec<Throttle>) {
+        let business = &self.businesses[business_id];
+        let mut stock: BTreeMap<StockKey, i32> =
+            BTreeMap::from([((None, MaterialId::money()), business.money)]);
+        for (city_id, inventory) in &business.inventories {
+            for (material, quantity) in inventory {
+                *stock.entry(get_stock_key(*city_id, material)).or_insert(0) += quantity;
+            }
+        }
+        let mut throughputs: Vec<i32> =
+            activities.iter().map(|activity| activity.planned).collect();
+        let mut shortages: BTreeMap<usize, MaterialId> = BTreeMap::new();
+        loop {
+            let mut balance = stock.clone();
+            for (activity, throughput) in activities.iter().zip(&throughputs) {
+                for ((city_id, material), quantity) in &activity.unit_flows {
+                    *balance
+                        .entry(get_stock_key(*city_id, material))
+                        .or_insert(0) += quantity * throughput;
+                }
+            }
+            let cut = balance
+                .iter()
+                .filter(|(_, quantity)| **quantity < 0)
+                .find_map(|(key, quantity)| {
+                    activities
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .filter(|(activity_index, _)| throughputs[*activity_index] > 0)
+                        .find_map(|(activity_index, activity)| {
+                            let consumption: i32 = activity
+                                .unit_flows
+                                .iter()
+                                .filter(|((city_id, material), _)| {
+                                    get_stock_key(*city_id, material) == *key
+                                })
+                                .map(|(_, quantity)| -quantity)
+                                .sum();
+                            (consumption > 0).then(|| {
+                                let units = (-quantity + consumption - 1) / consumption;
+                                (activity_index, units, key.1.clone())
+                            })
+                        })
+                });
+            let Some((activity_index, units, material)) = cut else {
+                break;
+            };
+            throughputs[activity_index] -= units.min(throughputs[activity_index]);
+            shortages.entry(activity_index).or_insert(material);
+        }
+        let throttles = shortages
+            .into_iter()
+            .map(|(activity_index, shortage)| Throttle {
+                id: activities[activity_index].id,
+                planned: activities[activity_index].planned,
+                throughput: throughputs[activity_index],
+                shortage,
+            })
+            .collect();
+        (throughputs, throttles)
+    }
+}
+
+pub fn sum_flows(
+    activities: &[Activity],
+    throughputs: impl IntoIterator<Item = i32>,
+) -> BTreeMap<(usize, MaterialId), QuantityInfo> {
+    let mut flows: BTreeMap<(usize, MaterialId), QuantityInfo> = BTreeMap::new();
+    for (activity, throughput) in activities.iter().zip(throughputs) {
+        if throughput == 0 {
+            continue;
+        }
+        for (key, quantity) in &activity.unit_flows {
+            flows
+                .entry(key.clone())
+                .or_default()
+                .add_flow(quantity * throughput);
+        }
+    }
+    flows
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{registry::ValidRecipe, testing, Shipment};
+
+    fn activity(
+        shipment_id: usize,
+        priority: i32,
+        planned: i32,
+        unit_flows: &[(usize, &str, i32)],
+    ) -> Activity {
+        Activity {
+            id: ActivityId::Shipment(shipment_id),
+            priority,
+            planned,
+            unit_flows: unit_flows
+                .iter()
+                .map(|(city_id, material, quantity)| {
+                    ((*city_id, testing::material(material)), *quantity)
+                })
+                .collect(),
+        }
+    }
+
+    fn new_graph(money: i32) -> Graph {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &[]), (200.0, 0.0, &[])], &[(0, 1)]);
+        game.graph.businesses[0].money = money;
+        game.graph
+    }
+
+    #[test]
+    fn the_lowest_priority_activity_is_cut_first() {
+        let graph = new_graph(10);
+        let activities = [
+            activity(0, 1, 8, &[(0, "Money", -1)]),
+            activity(1, 0, 8, &[(0, "Money", -1)]),
+        ];
+        let (throughputs, throttles) = graph.resolve_throughputs(&activities, 0);
+        assert_eq!(throughputs, vec![8, 2]);
+        assert_eq!(throttles.len(), 1);
+        assert_eq!(throttles[0].id, ActivityId::Shipment(1));
+        assert_eq!(throttles[0].planned, 8);
+        assert_eq!(throttles[0].throughput, 2);
+        assert_eq!(throttles[0].shortage, MaterialId::money());
+    }
+
+    #[test]
+    fn production_feeds_consumption_in_the_same_round() {
+        let graph = new_graph(0);
+        let activities = [
+            activity(0, 1, 5, &[(0, "Plank", 1)]),
+            activity(1, 0, 5, &[(0, "Plank", -1)]),
+        ];
+        assert_eq!(graph.resolve_throughputs(&activities, 0).0, vec![5, 5]);
+        let activities = [
+            activity(0, 1, 5, &[(0, "Plank", 1)]),
+            activity(1, 0, 7, &[(0, "Plank", -1)]),
+            activity(2, 0, 3, &[(1, "Plank", -1)]),
+        ];
+        let (throughputs, throttles) = graph.resolve_throughputs(&activities, 0);
+        assert_eq!(throughputs, vec![5, 5, 0]);
+        assert_eq!(throttles.len(), 2);
+        assert_eq!(throttles[0].shortage, testing::material("Plank"));
+    }
+
+    #[test]
+    fn money_is_pooled_across_cities() {
+        let graph = new_graph(6);
+        let activities = [
+            activity(0, 0, 4, &[(0, "Money", -1)]),
+            activity(1, 0, 4, &[(1, "Money", -1)]),
+        ];
+        assert_eq!(graph.resolve_throughputs(&activities, 0).0, vec![4, 2]);
+    }
+
+    #[test]
+    fn shipments_come_after_recipes_of_the_same_priority() {
+        let mut game = testing::new_game_on_map(
+            &[(0.0, 0.0, &["Sawmill", "Market"]), (200.0, 0.0, &[])],
+            &[(0, 1)],
+        );
+        testing::own_building(&mut game, 0, 0, 0);
+        testing::own_building(&mut game, 0, 0, 1);
+        testing::own_connection(&mut game, 0, 0);
+        let city = &mut game.graph.cities[0];
+        city.get_building_mut(0).unwrap().production_scale[0].scale = 1;
+        let market_recipe = &mut city.get_building_mut(1).unwrap().production_scale[0];
+        market_recipe.scale = 1;
+        market_recipe.priority = -1;
+        game.graph.businesses[0].shipments.push(Shipment {
+            material: testing::material("Plank"),
+            from_city_id: 0,
+            to_city_id: 1,
+            quantity: 1,
+            connection_ids: vec![0],
+        });
+
+        let activity_ids: Vec<ActivityId> = game
+            .graph
+            .get_activities(&game.registry, 0)
+            .iter()
+            .map(|activity| activity.id)
+            .collect();
+        assert_eq!(
+            activity_ids,
+            vec![
+                ActivityId::Recipe {
+                    city_id: 0,
+                    building_id: 0,
+                    recipe_id: 0,
+                },
+                ActivityId::Shipment(0),
+                ActivityId::Recipe {
+                    city_id: 0,
+                    building_id: 1,
+                    recipe_id: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_round_delivers_what_throttled_recipes_could_afford() {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &["Market"])], &[]);
+        testing::own_building(&mut game, 0, 0, 0);
+        let log = testing::material("Log");
+        let market = game.graph.cities[0].get_building_mut(0).unwrap();
+        let recipe_id = market
+            .production_scale
+            .iter()
+            .position(|scaled_valid_recipe| {
+                scaled_valid_recipe.valid_recipe == ValidRecipe::MaterialImport(log.clone())
+            })
+            .unwrap();
+        market.production_scale[recipe_id].scale = 5;
+        let price = market.get_buy_price(&game.registry, &log);
+        let upkeep = game.graph.get_upkeep(&game.registry, 0);
+        assert!(upkeep < price);
+        game.graph.businesses[0].money = price * 2 + upkeep;
+        for _ in 0..game.graph.businesses.len() {
+            game.end_turn();
+        }
+
+        let business = &game.graph.businesses[0];
+        assert_eq!(business.inventories[&0][&log], 2);
+        assert_eq!(business.throttles.len(), 1);
+        assert_eq!(
+            business.throttles[0].id,
+            ActivityId::Recipe {
+                city_id: 0,
+                building_id: 0,
+                recipe_id,
+            }
+        );
+        assert_eq!(business.throttles[0].throughput, 2);
+    }
+}
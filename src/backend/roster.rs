@@ -0,0 +1,44 @@
+use super::ai::Controller;
+use serde::{Deserialize, Serialize};
+
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 8;
+
+/// Who plays a business, as chosen before the game starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerSetup {
+    pub name: String,
+    pub controller: Controller,
+}
+
+impl PlayerSetup {
+    pub fn new(player_id: usize, controller: Controller) -> Self {
+        Self {
+            name: get_default_name(player_id),
+            controller,
+        }
+    }
+}
+
+pub fn get_default_name(player_id: usize) -> String {
+    format!("Player {}", player_id + 1)
+}
+
+/// Saturated colors with hues spread so that neighbouring players differ the most.
+pub fn get_palette_color(player_id: usize) -> [u8; 3] {
+    let hue = (0.6 + player_id as f32 * 3.0 / MAX_PLAYERS as f32).fract();
+    let (saturation, value) = (0.8, 0.95);
+    let h = hue * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    [r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
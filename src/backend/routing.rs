@@ -0,0 +1,272 @@
+use super::{Graph, OwnedConnection};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeSet, BinaryHeap, VecDeque},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Hops,
+    Distance,
+    TransportCost,
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub city_ids: Vec<usize>,
+    pub connection_ids: Vec<usize>,
+    pub cost: f32,
+}
+
+/// Adjacency over the completed connections that pass a filter, built once per query batch.
+pub struct Router<'a> {
+    graph: &'a Graph,
+    adjacency: Vec<Vec<(usize, usize)>>,
+}
+
+impl<'a> Router<'a> {
+    pub fn new(graph: &'a Graph, filter: impl Fn(usize, &OwnedConnection) -> bool) -> Self {
+        let mut adjacency = vec![vec![]; graph.cities.len()];
+        for (connection_id, owned_connection) in graph.connections.iter().enumerate() {
+            if owned_connection.is_under_construction() || !filter(connection_id, owned_connection)
+            {
+                continue;
+            }
+            let (city_id_a, city_id_b) =
+                (owned_connection.city_ids[0], owned_connection.city_ids[1]);
+            adjacency[city_id_a].push((city_id_b, connection_id));
+            adjacency[city_id_b].push((city_id_a, connection_id));
+        }
+        Self { graph, adjacency }
+    }
+
+    pub fn all(graph: &'a Graph) -> Self {
+        Self::new(graph, |_, _| true)
+    }
+
+    /// Connections the business owns or pays a toll for.
+    pub fn usable_by(graph: &'a Graph, business_id: usize) -> Self {
+        Self::new(graph, move |connection_id, _| {
+            graph.can_use_connection(business_id, connection_id)
+        })
+    }
+
+    pub fn edge_cost(&self, connection_id: usize, metric: Metric) -> f32 {
+        match metric {
+            Metric::Hops => 1.0,
+            Metric::Distance => self.graph.get_connection_length(connection_id),
+            Metric::TransportCost => self.graph.connections[connection_id].transport_cost as f32,
+        }
+    }
+
+    pub fn path_cost(&self, connection_ids: &[usize], metric: Metric) -> f32 {
+        connection_ids
+            .iter()
+            .map(|connection_id| self.edge_cost(*connection_id, metric))
+            .sum()
+    }
+
+    pub fn find_path(
+        &self,
+        from_city_id: usize,
+        to_city_id: usize,
+        metric: Metric,
+    ) -> Option<Route> {
+        let mut best_costs = vec![f32::INFINITY; self.graph.cities.len()];
+        let mut previous: Vec<Option<(usize, usize)>> = vec![None; self.graph.cities.len()];
+        let mut queue = BinaryHeap::from([Reverse(QueueEntry {
+            cost: 0.0,
+            city_id: from_city_id,
+        })]);
+        best_costs[from_city_id] = 0.0;
+        while let Some(Reverse(QueueEntry { cost, city_id })) = queue.pop() {
+            if city_id == to_city_id {
+                let mut city_ids = vec![city_id];
+                let mut connection_ids = vec![];
+                let mut current = city_id;
+                while let Some((previous_city_id, connection_id)) = previous[current] {
+                    city_ids.push(previous_city_id);
+                    connection_ids.push(connection_id);
+                    current = previous_city_id;
+                }
+                city_ids.reverse();
+                connection_ids.reverse();
+                return Some(Route {
+                    city_ids,
+                    connection_ids,
+                    cost,
+                });
+            }
+            if cost > best_costs[city_id] {
+                continue;
+            }
+            for (next_city_id, connection_id) in self.adjacency[city_id].iter().copied() {
+                let next_cost = cost + self.edge_cost(connection_id, metric);
+                if next_cost < best_costs[next_city_id] {
+                    best_costs[next_city_id] = next_cost;
+                    previous[next_city_id] = Some((city_id, connection_id));
+                    queue.push(Reverse(QueueEntry {
+                        cost: next_cost,
+                        city_id: next_city_id,
+                    }));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn reachable_city_ids(
+        &self,
+        from_city_ids: impl IntoIterator<Item = usize>,
+    ) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        for city_id in from_city_ids {
+            if reachable.insert(city_id) {
+                queue.push_back(city_id);
+            }
+        }
+        while let Some(city_id) = queue.pop_front() {
+            for (next_city_id, _) in &self.adjacency[city_id] {
+                if reachable.insert(*next_city_id) {
+                    queue.push_back(*next_city_id);
+                }
+            }
+        }
+        reachable
+    }
+}
+
+#[derive(PartialEq)]
+struct QueueEntry {
+    cost: f32,
+    city_id: usize,
+}
+
+impl Eq for QueueEntry {}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost
+            .total_cmp(&other.cost)
+            .then(self.city_id.cmp(&other.city_id))
+    }
+}
+
+impl Graph {
+    pub fn get_connection_length(&self, connection_id: usize) -> f32 {
+        let owned_connection = &self.connections[connection_id];
+        self.get_city_distance(owned_connection.city_ids[0], owned_connection.city_ids[1])
+    }
+
+    pub fn get_city_distance(&self, city_id_a: usize, city_id_b: usize) -> f32 {
+        let city_a = &self.cities[city_id_a];
+        let city_b = &self.cities[city_id_b];
+        ((city_a.x - city_b.x).powi(2) + (city_a.y - city_b.y).powi(2)).sqrt()
+    }
+
+    /// Cities where the business owns a building or which a completed connection it owns or pays
+    /// a toll for touches.
+    pub fn get_network_city_ids(&self, business_id: usize) -> BTreeSet<usize> {
+        let mut city_ids: BTreeSet<usize> = self
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(connection_id, _)| self.can_use_connection(business_id, *connection_id))
+            .flat_map(|(_, owned_connection)| owned_connection.city_ids.iter().copied())
+            .collect();
+        city_ids.extend(
+            self.cities
+                .iter()
+                .enumerate()
+                .filter(|(_, city)| {
+                    city.owned_buildings
+                        .iter()
+                        .flatten()
+                        .any(|owned_building| owned_building.owner_id == Some(business_id))
+                })
+                .map(|(city_id, _)| city_id),
+        );
+        city_ids
+    }
+
+    /// Cities the business can reach from its network over the connections it can use.
+    pub fn get_reachable_city_ids(&self, business_id: usize) -> BTreeSet<usize> {
+        Router::usable_by(self, business_id)
+            .reachable_city_ids(self.get_network_city_ids(business_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing;
+
+    /// A direct but expensive connection from city 0 to city 2 and a cheap detour over city 1.
+    /// City 3 hangs off city 2 and city 4 is on its own.
+    fn new_graph() -> Graph {
+        let mut game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &[]),
+                (100.0, 100.0, &[]),
+                (200.0, 0.0, &[]),
+                (300.0, 0.0, &[]),
+                (0.0, 300.0, &[]),
+            ],
+            &[(0, 2), (0, 1), (1, 2), (2, 3)],
+        );
+        game.graph.connections[0].transport_cost = 5;
+        game.graph
+    }
+
+    #[test]
+    fn paths_follow_the_metric() {
+        let graph = new_graph();
+        let router = Router::all(&graph);
+        let route = router.find_path(0, 3, Metric::Hops).unwrap();
+        assert_eq!(route.city_ids, vec![0, 2, 3]);
+        assert_eq!(route.connection_ids, vec![0, 3]);
+        assert_eq!(route.cost, 2.0);
+        let route = router.find_path(0, 3, Metric::TransportCost).unwrap();
+        assert_eq!(route.connection_ids, vec![1, 2, 3]);
+        assert_eq!(route.cost, 3.0);
+        let route = router.find_path(0, 2, Metric::Distance).unwrap();
+        assert_eq!(route.connection_ids, vec![0]);
+        assert_eq!(route.cost, 200.0);
+        assert!(router.find_path(0, 4, Metric::Hops).is_none());
+    }
+
+    #[test]
+    fn connections_under_construction_are_not_routed() {
+        let mut graph = new_graph();
+        graph.connections[3].construction_rounds = 1;
+        let router = Router::all(&graph);
+        assert!(router.find_path(0, 3, Metric::Hops).is_none());
+        assert_eq!(router.reachable_city_ids([0]), BTreeSet::from([0, 1, 2]));
+    }
+
+    #[test]
+    fn businesses_reach_over_owned_and_tolled_connections() {
+        let mut graph = new_graph();
+        graph.connections[1].owner_id = Some(0);
+        graph.connections[2].owner_id = Some(1);
+        graph.connections[3].owner_id = Some(1);
+        assert_eq!(graph.get_network_city_ids(0), BTreeSet::from([0, 1]));
+        assert_eq!(graph.get_reachable_city_ids(0), BTreeSet::from([0, 1]));
+        graph.businesses[0].toll_connection_ids.insert(2);
+        assert_eq!(graph.get_network_city_ids(0), BTreeSet::from([0, 1, 2]));
+        assert_eq!(graph.get_reachable_city_ids(0), BTreeSet::from([0, 1, 2]));
+        let router = Router::usable_by(&graph, 0);
+        assert_eq!(
+            router.find_path(0, 2, Metric::Hops).unwrap().connection_ids,
+            vec![1, 2]
+        );
+        assert!(router.find_path(0, 3, Metric::Hops).is_none());
+    }
+}
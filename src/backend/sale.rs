@@ -0,0 +1,262 @@
+use super::{map::find_root, resolution::ActivityId, Graph, OwnedBuilding};
+use serde::{Deserialize, Serialize};
+
+/// Share of the acquisition cost paid out when an asset is sold.
+pub const SALE_SHARE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AssetId {
+    Building { city_id: usize, building_id: usize },
+    Connection(usize),
+}
+
+pub fn get_sale_price(acquisition_cost: i32) -> i32 {
+    (acquisition_cost as f32 * SALE_SHARE).round() as i32
+}
+
+impl OwnedBuilding {
+    /// Returns the building to the market with all of its recipes stopped.
+    pub fn release(&mut self) {
+        self.owner_id = None;
+        for scaled_valid_recipe in &mut self.production_scale {
+            scaled_valid_recipe.scale = 0;
+            scaled_valid_recipe.priority = 0;
+        }
+    }
+}
+
+impl Graph {
+    /// Empties the slot of a building, dropping its throttles and booking its ledger entries as
+    /// demolished. The other buildings keep their ids.
+    pub fn remove_building(&mut self, city_id: usize, building_id: usize) {
+        self.cities[city_id].owned_buildings[building_id] = None;
+        for business in &mut self.businesses {
+            business.ledger.remove_building(city_id, building_id);
+            business.throttles.retain(|throttle| {
+                !matches!(
+                    throttle.id,
+                    ActivityId::Recipe {
+                        city_id: throttle_city_id,
+                        building_id: throttle_building_id,
+                        ..
+                    } if throttle_city_id == city_id && throttle_building_id == building_id
+                )
+            });
+        }
+    }
+
+    /// Whether giving up the asset would leave the network of the business in more separate
+    /// pieces than it is now.
+    pub fn splits_network(&self, business_id: usize, asset_id: AssetId) -> bool {
+        self.count_network_parts(business_id, Some(asset_id))
+            > self.count_network_parts(business_id, None)
+    }
+
+    fn count_network_parts(&self, business_id: usize, skipped: Option<AssetId>) -> usize {
+        let mut roots: Vec<usize> = (0..self.cities.len()).collect();
+        let mut network = vec![false; self.cities.len()];
+        for (connection_id, owned_connection) in self.connections.iter().enumerate() {
+            if owned_connection.owner_id != Some(business_id)
+                || owned_connection.is_under_construction()
+                || skipped == Some(AssetId::Connection(connection_id))
+            {
+                continue;
+            }
+            let (city_id_a, city_id_b) =
+                (owned_connection.city_ids[0], owned_connection.city_ids[1]);
+            network[city_id_a] = true;
+            network[city_id_b] = true;
+            let root_a = find_root(&mut roots, city_id_a);
+            let root_b = find_root(&mut roots, city_id_b);
+            roots[root_a] = root_b;
+        }
+        for (city_id, city) in self.cities.iter().enumerate() {
+            if city.get_buildings().any(|(building_id, owned_building)| {
+                owned_building.owner_id == Some(business_id)
+                    && skipped
+                        != Some(AssetId::Building {
+                            city_id,
+                            building_id,
+                        })
+            }) {
+                network[city_id] = true;
+            }
+        }
+        (0..self.cities.len())
+            .filter(|city_id| network[*city_id] && find_root(&mut roots, *city_id) == *city_id)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        game::Command, ledger::Source, registry::MaterialId, resolution::Throttle, testing,
+    };
+
+    #[test]
+    fn demolishing_keeps_the_other_buildings_in_their_slots() {
+        let mut game = testing::new_game();
+        let city_id = (0..game.graph.cities.len())
+            .find(|city_id| game.graph.cities[*city_id].owned_buildings.len() >= 3)
+            .unwrap();
+        testing::give(&mut game, 0, city_id, &[(MaterialId::money(), 1000)]);
+        for building_id in 0..3 {
+            game.execute(Command::BuyBuilding {
+                city_id,
+                building_id,
+            })
+            .unwrap();
+            game.graph.businesses[0].throttles.push(Throttle {
+                id: ActivityId::Recipe {
+                    city_id,
+                    building_id,
+                    recipe_id: 0,
+                },
+                planned: 1,
+                throughput: 0,
+                shortage: MaterialId::money(),
+            });
+        }
+        let city = &game.graph.cities[city_id];
+        let kept_types = [0, 2].map(|building_id| {
+            city.get_building(building_id)
+                .unwrap()
+                .building_type
+                .clone()
+        });
+
+        game.execute(Command::DemolishBuilding {
+            city_id,
+            building_id: 1,
+        })
+        .unwrap();
+
+        let city = &game.graph.cities[city_id];
+        assert!(city.get_building(1).is_none());
+        for (building_id, building_type) in [0, 2].into_iter().zip(kept_types) {
+            let building = city.get_building(building_id).unwrap();
+            assert_eq!(building.building_type, building_type);
+            assert_eq!(building.owner_id, Some(0));
+        }
+        let business = &game.graph.businesses[0];
+        let throttled: Vec<_> = business
+            .throttles
+            .iter()
+            .map(|throttle| throttle.id)
+            .collect();
+        assert_eq!(
+            throttled,
+            [0, 2].map(|building_id| ActivityId::Recipe {
+                city_id,
+                building_id,
+                recipe_id: 0,
+            })
+        );
+        let sources: Vec<Source> = business.ledger.rounds[&game.round]
+            .iter()
+            .map(|entry| entry.source)
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                Source::Purchase(AssetId::Building {
+                    city_id,
+                    building_id: 0,
+                }),
+                Source::Demolished,
+                Source::Purchase(AssetId::Building {
+                    city_id,
+                    building_id: 2,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn construction_fills_the_demolished_slot_with_a_clean_ledger() {
+        let mut game = testing::new_game();
+        let city_id = (0..game.graph.cities.len())
+            .find(|city_id| game.graph.cities[*city_id].owned_buildings.len() >= 2)
+            .unwrap();
+        testing::give(&mut game, 0, city_id, &[(MaterialId::money(), 1000)]);
+        for building_id in 0..2 {
+            game.execute(Command::BuyBuilding {
+                city_id,
+                building_id,
+            })
+            .unwrap();
+        }
+        game.execute(Command::DemolishBuilding {
+            city_id,
+            building_id: 0,
+        })
+        .unwrap();
+        let (building_type, construction) = game
+            .registry
+            .building_types
+            .iter()
+            .find_map(|(building_type, building_type_def)| {
+                Some((
+                    building_type.clone(),
+                    building_type_def.construction.clone()?,
+                ))
+            })
+            .unwrap();
+        testing::give(&mut game, 0, city_id, &construction.materials);
+
+        game.execute(Command::ConstructBuilding {
+            city_id,
+            building_type: building_type.clone(),
+        })
+        .unwrap();
+
+        let building = game.graph.cities[city_id].get_building(0).unwrap();
+        assert_eq!(building.building_type, building_type);
+        let slot = AssetId::Building {
+            city_id,
+            building_id: 0,
+        };
+        let entries = &game.graph.businesses[0].ledger.rounds[&game.round];
+        assert!(entries.iter().all(|entry| match entry.source {
+            Source::Purchase(asset_id) => asset_id != slot,
+            Source::Construction(asset_id) => asset_id == slot,
+            _ => true,
+        }));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.source == Source::Demolished));
+    }
+
+    #[test]
+    fn selling_pays_the_seller_and_frees_the_asset() {
+        let mut game = testing::new_game_on_map(
+            &[
+                (0.0, 0.0, &["Sawmill"]),
+                (200.0, 0.0, &[]),
+                (400.0, 0.0, &["Market"]),
+            ],
+            &[(0, 1), (1, 2)],
+        );
+        testing::own_building(&mut game, 0, 0, 0);
+        testing::own_connection(&mut game, 0, 0);
+        testing::own_connection(&mut game, 0, 1);
+        testing::own_building(&mut game, 0, 2, 0);
+        assert!(game.graph.splits_network(0, AssetId::Connection(1)));
+        assert!(!game.graph.splits_network(
+            0,
+            AssetId::Building {
+                city_id: 2,
+                building_id: 0,
+            }
+        ));
+
+        let price = game.check_sell_connection(1).unwrap();
+        game.execute(Command::SellConnection { connection_id: 1 })
+            .unwrap();
+        assert_eq!(game.graph.connections[1].owner_id, None);
+        assert_eq!(game.graph.businesses[0].money, 250 + price);
+        assert!(!game.graph.splits_network(0, AssetId::Connection(0)));
+    }
+}
@@ -0,0 +1,452 @@
+use super::{
+    game::Game,
+    registry::{BuildingTypeId, Registry, RegistryError, MONEY},
+    roster,
+};
+use serde_json::Value;
+use std::{fmt, fs, io, path::PathBuf};
+
+pub const SAVE_VERSION: u32 = 9;
+pub const AUTOSAVE_SLOT: &str = "autosave";
+
+type Migration = fn(Value) -> Result<Value, SaveError>;
+
+// MIGRATIONS[n] upgrades a version n + 1 save to version n + 2. A new field gets a migration when
+// old saves need a value other than its default, and then no `#[serde(default)]` unless game data
+// may leave it out.
+const MIGRATIONS: [Migration; SAVE_VERSION as usize - 1] = [
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+    migrate_v7_to_v8,
+    migrate_v8_to_v9,
+];
+
+// Version 1 worlds were generated from an unseeded RNG and cannot be reproduced.
+fn migrate_v1_to_v2(mut save: Value) -> Result<Value, SaveError> {
+    save["game"]["seed"] = Value::from(0);
+    save["version"] = Value::from(2);
+    Ok(save)
+}
+
+// Version 2 games ran on the rules that now ship as the builtin registry, and named production
+// recipes directly instead of wrapping them in `ValidRecipe::Production`.
+fn migrate_v2_to_v3(mut save: Value) -> Result<Value, SaveError> {
+    let game = &mut save["game"];
+    if let Some(cities) = game["graph"]["cities"].as_array_mut() {
+        for city in cities {
+            if let Some(owned_buildings) = city["owned_buildings"].as_array_mut() {
+                for owned_building in owned_buildings {
+                    if let Some(production_scale) =
+                        owned_building["production_scale"].as_array_mut()
+                    {
+                        for scaled_valid_recipe in production_scale {
+                            let valid_recipe = &mut scaled_valid_recipe["valid_recipe"];
+                            if let Value::String(recipe) = valid_recipe {
+                                *valid_recipe = serde_json::json!({ "Production": recipe });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    game["registry"] = serde_json::to_value(Registry::builtin())?;
+    save["version"] = Value::from(3);
+    Ok(save)
+}
+
+// Version 3 businesses kept one global resource pool. Money becomes cash and other materials
+// are stored in the first city where the business owns a building.
+fn migrate_v3_to_v4(mut save: Value) -> Result<Value, SaveError> {
+    let graph = &mut save["game"]["graph"];
+    let home_city_ids: Vec<Option<usize>> = (0..graph["businesses"].as_array().map_or(0, Vec::len))
+        .map(|business_id| {
+            graph["cities"].as_array().and_then(|cities| {
+                cities.iter().position(|city| {
+                    city["owned_buildings"]
+                        .as_array()
+                        .is_some_and(|owned_buildings| {
+                            owned_buildings
+                                .iter()
+                                .any(|owned_building| owned_building["owner_id"] == business_id)
+                        })
+                })
+            })
+        })
+        .collect();
+    if let Some(businesses) = graph["businesses"].as_array_mut() {
+        for (business, home_city_id) in businesses.iter_mut().zip(home_city_ids) {
+            let mut resources = match business["resources"].take() {
+                Value::Object(resources) => resources,
+                _ => serde_json::Map::new(),
+            };
+            let money = resources.remove(MONEY).unwrap_or(Value::from(0));
+            let mut inventories = serde_json::Map::new();
+            if let Some(home_city_id) = home_city_id {
+                inventories.insert(home_city_id.to_string(), Value::Object(resources));
+            }
+            *business = serde_json::json!({
+                "money": money,
+                "inventories": inventories,
+                "shipments": [],
+            });
+        }
+    }
+    if let Some(connections) = graph["connections"].as_array_mut() {
+        for connection in connections {
+            connection["capacity"] = Value::from(10);
+            connection["transport_cost"] = Value::from(1);
+        }
+    }
+    save["version"] = Value::from(4);
+    Ok(save)
+}
+
+// Version 4 businesses were only known by their index.
+fn migrate_v4_to_v5(mut save: Value) -> Result<Value, SaveError> {
+    if let Some(businesses) = save["game"]["graph"]["businesses"].as_array_mut() {
+        for (business_id, business) in businesses.iter_mut().enumerate() {
+            business["name"] = Value::from(roster::get_default_name(business_id));
+            business["color"] = serde_json::to_value(roster::get_palette_color(business_id))?;
+        }
+    }
+    save["version"] = Value::from(5);
+    Ok(save)
+}
+
+// Version 5 assets were free to keep. Building types take their upkeep from the builtin rules.
+fn migrate_v5_to_v6(mut save: Value) -> Result<Value, SaveError> {
+    let game = &mut save["game"];
+    if let Some(connections) = game["graph"]["connections"].as_array_mut() {
+        for connection in connections {
+            connection["maintenance_cost"] = Value::from(1);
+        }
+    }
+    let builtin = Registry::builtin();
+    if let Some(building_types) = game["registry"]["building_types"].as_object_mut() {
+        for (building_type, building_type_def) in building_types {
+            let (upkeep, upkeep_per_scale) = builtin
+                .building_types
+                .get(&BuildingTypeId(building_type.clone()))
+                .map_or((0, 0.0), |builtin_def| {
+                    (builtin_def.upkeep, builtin_def.upkeep_per_scale)
+                });
+            building_type_def["upkeep"] = Value::from(upkeep);
+            building_type_def["upkeep_per_scale"] = Value::from(upkeep_per_scale);
+        }
+    }
+    save["version"] = Value::from(6);
+    Ok(save)
+}
+
+// Version 6 buildings could not be upgraded. Building types take their upgrades from the builtin
+// rules.
+fn migrate_v6_to_v7(mut save: Value) -> Result<Value, SaveError> {
+    let builtin = Registry::builtin();
+    if let Some(building_types) = save["game"]["registry"]["building_types"].as_object_mut() {
+        for (building_type, building_type_def) in building_types {
+            let upgrades = builtin
+                .building_types
+                .get(&BuildingTypeId(building_type.clone()))
+                .map_or(vec![], |builtin_def| builtin_def.upgrades.clone());
+            building_type_def["upgrades"] = serde_json::to_value(upgrades)?;
+        }
+    }
+    save["version"] = Value::from(7);
+    Ok(save)
+}
+
+// Version 7 cities only held the buildings the map placed. Building types take their construction
+// costs from the builtin rules.
+fn migrate_v7_to_v8(mut save: Value) -> Result<Value, SaveError> {
+    let builtin = Registry::builtin();
+    if let Some(building_types) = save["game"]["registry"]["building_types"].as_object_mut() {
+        for (building_type, building_type_def) in building_types {
+            let construction = builtin
+                .building_types
+                .get(&BuildingTypeId(building_type.clone()))
+                .and_then(|builtin_def| builtin_def.construction.clone());
+            building_type_def["construction"] = serde_json::to_value(construction)?;
+        }
+    }
+    save["version"] = Value::from(8);
+    Ok(save)
+}
+
+// Version 8 connections were all placed by the map. New ones cost what the builtin rules say.
+fn migrate_v8_to_v9(mut save: Value) -> Result<Value, SaveError> {
+    save["game"]["registry"]["connection_construction"] =
+        serde_json::to_value(Registry::builtin().connection_construction)?;
+    save["version"] = Value::from(9);
+    Ok(save)
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    MissingVersion,
+    UnsupportedVersion { found: u32, supported: u32 },
+    InvalidSlotName(String),
+    Registry(RegistryError),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not access save: {}", err),
+            Self::Format(err) => write!(f, "Corrupt save: {}", err),
+            Self::MissingVersion => write!(f, "Save has no version"),
+            Self::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Save version {} is not supported (this build reads up to version {})",
+                found, supported
+            ),
+            Self::InvalidSlotName(name) => write!(f, "Invalid save slot name {:?}", name),
+            Self::Registry(err) => write!(f, "Save contains invalid game data: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Format(err)
+    }
+}
+
+pub struct SaveStore {
+    pub dir: PathBuf,
+}
+
+impl SaveStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn save(&self, game: &Game, slot: &str) -> Result<(), SaveError> {
+        let path = self.slot_path(slot)?;
+        fs::create_dir_all(&self.dir)?;
+        let contents = serde_json::to_string(&serde_json::json!({
+            "version": SAVE_VERSION,
+            "game": game,
+        }))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(&self, slot: &str) -> Result<Game, SaveError> {
+        let contents = fs::read_to_string(self.slot_path(slot)?)?;
+        let mut save: Value = serde_json::from_str(&contents)?;
+        let found = save
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(SaveError::MissingVersion)? as u32;
+        if found == 0 || found > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion {
+                found,
+                supported: SAVE_VERSION,
+            });
+        }
+        for migration in &MIGRATIONS[found as usize - 1..] {
+            save = migration(save)?;
+        }
+        let game: Game = serde_json::from_value(save["game"].take())?;
+        game.registry.validate().map_err(SaveError::Registry)?;
+        Ok(game)
+    }
+
+    pub fn list_slots(&self) -> Result<Vec<String>, SaveError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut slots = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    slots.push(stem.to_string());
+                }
+            }
+        }
+        slots.sort();
+        Ok(slots)
+    }
+
+    fn slot_path(&self, slot: &str) -> Result<PathBuf, SaveError> {
+        let valid = !slot.is_empty()
+            && slot
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(SaveError::InvalidSlotName(slot.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.json", slot)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        registry::{RecipeId, ValidRecipe},
+        testing,
+    };
+
+    /// A store in a fresh directory of its own under the system temp dir.
+    fn new_store(name: &str) -> SaveStore {
+        let dir = std::env::temp_dir().join(format!(
+            "logistics-game-save-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        SaveStore::new(dir)
+    }
+
+    fn write_slot(store: &SaveStore, slot: &str, save: Value) {
+        fs::create_dir_all(&store.dir).unwrap();
+        fs::write(store.dir.join(format!("{}.json", slot)), save.to_string()).unwrap();
+    }
+
+    fn v1_save() -> Value {
+        serde_json::json!({
+            "version": 1,
+            "game": {
+                "graph": {
+                    "cities": [
+                        { "x": 0.0, "y": 0.0, "owned_buildings": [] },
+                        {
+                            "x": 200.0,
+                            "y": 0.0,
+                            "owned_buildings": [{
+                                "building_type": "Sawmill",
+                                "production_scale": [{
+                                    "valid_recipe": "PlankProduction",
+                                    "scale": 2,
+                                    "max_scale": 5,
+                                }],
+                                "owner_id": 0,
+                                "acquisition_cost": 80,
+                            }],
+                        },
+                    ],
+                    "connections": [{
+                        "city_ids": [0, 1],
+                        "owner_id": null,
+                        "acquisition_cost": 20,
+                    }],
+                    "businesses": [
+                        { "resources": { "Money": 120, "Plank": 7 } },
+                        { "resources": { "Money": 90 } },
+                    ],
+                },
+                "current_player_id": 1,
+            },
+        })
+    }
+
+    #[test]
+    fn version_1_saves_are_migrated_and_round_trip() {
+        let store = new_store("migration");
+        write_slot(&store, "old", v1_save());
+
+        let game = store.load("old").unwrap();
+        assert_eq!(game.seed, 0);
+        assert_eq!(game.current_player_id, 1);
+        let sawmill = game.graph.cities[1].get_building(0).unwrap();
+        assert_eq!(
+            sawmill.production_scale[0].valid_recipe,
+            ValidRecipe::Production(RecipeId("PlankProduction".to_string()))
+        );
+        assert_eq!(sawmill.production_scale[0].scale, 2);
+        let business = &game.graph.businesses[0];
+        assert_eq!(business.money, 120);
+        assert_eq!(business.inventories[&1][&testing::material("Plank")], 7);
+        assert_eq!(business.name, roster::get_default_name(0));
+        assert_eq!(game.graph.businesses[1].money, 90);
+        assert!(game.graph.businesses[1].inventories.is_empty());
+        let connection = &game.graph.connections[0];
+        assert_eq!(
+            (
+                connection.capacity,
+                connection.transport_cost,
+                connection.maintenance_cost
+            ),
+            (10, 1, 1)
+        );
+        let builtin = Registry::builtin();
+        let sawmill_type = BuildingTypeId("Sawmill".to_string());
+        let migrated_def = &game.registry.building_types[&sawmill_type];
+        let builtin_def = &builtin.building_types[&sawmill_type];
+        assert_eq!(migrated_def.upkeep, builtin_def.upkeep);
+        assert_eq!(migrated_def.upgrades.len(), builtin_def.upgrades.len());
+        assert_eq!(
+            migrated_def.construction.is_some(),
+            builtin_def.construction.is_some()
+        );
+        assert_eq!(
+            game.registry.connection_construction.cost,
+            builtin.connection_construction.cost
+        );
+
+        store.save(&game, "new").unwrap();
+        let reloaded = store.load("new").unwrap();
+        assert_eq!(
+            serde_json::to_value(&reloaded).unwrap(),
+            serde_json::to_value(&game).unwrap()
+        );
+        assert_eq!(store.list_slots().unwrap(), vec!["new", "old"]);
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn saves_from_newer_builds_are_rejected() {
+        let store = new_store("newer");
+        let mut save = v1_save();
+        save["version"] = Value::from(SAVE_VERSION + 1);
+        write_slot(&store, "future", save);
+        assert!(matches!(
+            store.load("future"),
+            Err(SaveError::UnsupportedVersion { found, supported })
+                if found == SAVE_VERSION + 1 && supported == SAVE_VERSION
+        ));
+        write_slot(&store, "unversioned", serde_json::json!({ "game": {} }));
+        assert!(matches!(
+            store.load("unversioned"),
+            Err(SaveError::MissingVersion)
+        ));
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn slot_names_stay_inside_the_save_dir() {
+        let store = new_store("slots");
+        let game = testing::new_game();
+        for slot in ["", "../escape", "a/b", "dot.json"] {
+            assert!(matches!(
+                store.save(&game, slot),
+                Err(SaveError::InvalidSlotName(_))
+            ));
+        }
+        assert!(store.list_slots().unwrap().is_empty());
+    }
+}
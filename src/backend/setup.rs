@@ -0,0 +1,26 @@
+use super::{ai::Controller, map::MapSize, roster::PlayerSetup, victory::WinCondition};
+use serde::{Deserialize, Serialize};
+
+/// Everything chosen before a new game starts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameSetup {
+    pub seed: u64,
+    pub map_size: MapSize,
+    pub starting_money: i32,
+    pub win_condition: WinCondition,
+    pub players: Vec<PlayerSetup>,
+}
+
+impl Default for GameSetup {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            map_size: MapSize::default(),
+            starting_money: 250,
+            win_condition: WinCondition::default(),
+            players: (0..2)
+                .map(|player_id| PlayerSetup::new(player_id, Controller::Human))
+                .collect(),
+        }
+    }
+}
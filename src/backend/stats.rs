@@ -0,0 +1,129 @@
+use super::{
+    game::Game,
+    ledger::Source,
+    registry::{MaterialId, Registry},
+    Graph,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// State of a business at the end of a round.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub round: u32,
+    pub cash: i32,
+    pub net_worth: i32,
+    /// Stock of every material summed over all cities.
+    pub stocks: BTreeMap<MaterialId, i32>,
+    /// Materials output by recipes in the round, market imports included.
+    pub production: BTreeMap<MaterialId, i32>,
+    pub buildings: usize,
+    pub connections: usize,
+}
+
+/// A time series that can be read from snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Metric {
+    Cash,
+    NetWorth,
+    Stock(MaterialId),
+    Production(MaterialId),
+    Buildings,
+    Connections,
+}
+
+impl Metric {
+    /// Every metric, with stocks and production for every material but money.
+    pub fn all(registry: &Registry) -> Vec<Metric> {
+        let materials = registry
+            .materials
+            .keys()
+            .filter(|material| !material.is_money());
+        [
+            Self::Cash,
+            Self::NetWorth,
+            Self::Buildings,
+            Self::Connections,
+        ]
+        .into_iter()
+        .chain(materials.clone().cloned().map(Self::Stock))
+        .chain(materials.cloned().map(Self::Production))
+        .collect()
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cash => write!(f, "Cash"),
+            Self::NetWorth => write!(f, "Net worth"),
+            Self::Stock(material) => write!(f, "{} stock", material),
+            Self::Production(material) => write!(f, "{} production", material),
+            Self::Buildings => write!(f, "Buildings"),
+            Self::Connections => write!(f, "Connections"),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn get(&self, metric: &Metric) -> i32 {
+        match metric {
+            Metric::Cash => self.cash,
+            Metric::NetWorth => self.net_worth,
+            Metric::Stock(material) => self.stocks.get(material).copied().unwrap_or(0),
+            Metric::Production(material) => self.production.get(material).copied().unwrap_or(0),
+            Metric::Buildings => self.buildings as i32,
+            Metric::Connections => self.connections as i32,
+        }
+    }
+}
+
+impl Graph {
+    pub fn get_snapshot(&self, registry: &Registry, business_id: usize, round: u32) -> Snapshot {
+        let business = &self.businesses[business_id];
+        let mut stocks: BTreeMap<MaterialId, i32> = BTreeMap::new();
+        for (material, quantity) in business.inventories.values().flatten() {
+            *stocks.entry(material.clone()).or_insert(0) += quantity;
+        }
+        let mut production: BTreeMap<MaterialId, i32> = BTreeMap::new();
+        for entry in business.ledger.rounds.get(&round).into_iter().flatten() {
+            if matches!(entry.source, Source::Production { .. })
+                && !entry.material.is_money()
+                && entry.quantity > 0
+            {
+                *production.entry(entry.material.clone()).or_insert(0) += entry.quantity;
+            }
+        }
+        Snapshot {
+            round,
+            cash: business.money,
+            net_worth: self.get_score(registry, business_id, round).get_net_worth(),
+            stocks,
+            production,
+            buildings: self
+                .cities
+                .iter()
+                .flat_map(|city| city.owned_buildings.iter().flatten())
+                .filter(|owned_building| owned_building.owner_id == Some(business_id))
+                .count(),
+            connections: self
+                .connections
+                .iter()
+                .filter(|owned_connection| owned_connection.owner_id == Some(business_id))
+                .count(),
+        }
+    }
+}
+
+/// Adds a snapshot of every business still in the game to its history.
+pub fn record_history(game: &mut Game) {
+    for business_id in 0..game.graph.businesses.len() {
+        if game.graph.businesses[business_id].eliminated {
+            continue;
+        }
+        let snapshot = game
+            .graph
+            .get_snapshot(&game.registry, business_id, game.round);
+        game.graph.businesses[business_id].history.push(snapshot);
+    }
+}
@@ -0,0 +1,75 @@
+//! Fixtures shared by the unit tests.
+
+use super::{
+    game::Game,
+    registry::{BuildingTypeId, MaterialId, Registry},
+    setup::GameSetup,
+    City, Graph, OwnedBuilding, OwnedConnection,
+};
+
+/// A game on the default map with the built-in registry, player 0 to move.
+pub fn new_game() -> Game {
+    Game::new(&GameSetup::default(), Registry::builtin())
+}
+
+/// A game with the two default businesses on a hand-made map. Cities are placed at `(x, y)` with
+/// the listed building types, connections join pairs of cities and nothing is owned.
+pub fn new_game_on_map(cities: &[(f32, f32, &[&str])], connections: &[(usize, usize)]) -> Game {
+    let mut game = new_game();
+    let businesses = std::mem::take(&mut game.graph.businesses);
+    game.graph = Graph {
+        cities: cities
+            .iter()
+            .map(|(x, y, building_types)| City {
+                x: *x,
+                y: *y,
+                owned_buildings: building_types
+                    .iter()
+                    .map(|building_type| {
+                        let building_type = BuildingTypeId(building_type.to_string());
+                        Some(OwnedBuilding::new(&game.registry, building_type))
+                    })
+                    .collect(),
+            })
+            .collect(),
+        connections: connections
+            .iter()
+            .map(|(city_id_a, city_id_b)| OwnedConnection::new(*city_id_a, *city_id_b))
+            .collect(),
+        businesses,
+        ..Default::default()
+    };
+    game
+}
+
+pub fn material(id: &str) -> MaterialId {
+    MaterialId(id.to_string())
+}
+
+/// Puts goods in the stock of a business in a city, money into its pooled funds.
+pub fn give(game: &mut Game, business_id: usize, city_id: usize, goods: &[(MaterialId, i32)]) {
+    let business = &mut game.graph.businesses[business_id];
+    for (material, quantity) in goods {
+        if material.is_money() {
+            business.money += quantity;
+            continue;
+        }
+        *business
+            .inventories
+            .entry(city_id)
+            .or_default()
+            .entry(material.clone())
+            .or_insert(0) += quantity;
+    }
+}
+
+pub fn own_building(game: &mut Game, business_id: usize, city_id: usize, building_id: usize) {
+    let building = game.graph.cities[city_id]
+        .get_building_mut(building_id)
+        .unwrap();
+    building.owner_id = Some(business_id);
+}
+
+pub fn own_connection(game: &mut Game, business_id: usize, connection_id: usize) {
+    game.graph.connections[connection_id].owner_id = Some(business_id);
+}
@@ -0,0 +1,226 @@
+use super::{game::Game, ledger::Source, Graph};
+
+/// Highest toll an owner can charge per round.
+pub const MAX_TOLL: i32 = 1000;
+
+impl Graph {
+    /// Whether the business can route over the connection, as its owner or by paying its toll.
+    pub fn can_use_connection(&self, business_id: usize, connection_id: usize) -> bool {
+        let owned_connection = &self.connections[connection_id];
+        !owned_connection.is_under_construction()
+            && (owned_connection.owner_id == Some(business_id)
+                || self.businesses[business_id]
+                    .toll_connection_ids
+                    .contains(&connection_id))
+    }
+
+    /// Businesses paying the toll of a connection.
+    pub fn get_toll_payer_ids(&self, connection_id: usize) -> Vec<usize> {
+        (0..self.businesses.len())
+            .filter(|business_id| {
+                self.businesses[*business_id]
+                    .toll_connection_ids
+                    .contains(&connection_id)
+            })
+            .collect()
+    }
+
+    /// Tolls the business receives minus the tolls it pays, per round.
+    pub fn get_toll_balance(&self, business_id: usize) -> i32 {
+        let income = self
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(_, owned_connection)| owned_connection.owner_id == Some(business_id))
+            .map(|(connection_id, owned_connection)| {
+                let payer_count = self.get_toll_payer_ids(connection_id).len() as i32;
+                owned_connection
+                    .toll
+                    .unwrap_or(0)
+                    .saturating_mul(payer_count)
+            })
+            .fold(0, i32::saturating_add);
+        let payments = self.businesses[business_id]
+            .toll_connection_ids
+            .iter()
+            .map(|connection_id| self.connections[*connection_id].toll.unwrap_or(0))
+            .fold(0, i32::saturating_add);
+        income.saturating_sub(payments)
+    }
+
+    /// Returns the connection to the market, closed to tolls.
+    pub fn release_connection(&mut self, connection_id: usize) {
+        let owned_connection = &mut self.connections[connection_id];
+        owned_connection.owner_id = None;
+        owned_connection.toll = None;
+        self.revoke_tolls(connection_id);
+    }
+
+    /// Ends every toll paid for the connection and drops the shipments that relied on it.
+    pub fn revoke_tolls(&mut self, connection_id: usize) {
+        for business_id in 0..self.businesses.len() {
+            if !self.businesses[business_id]
+                .toll_connection_ids
+                .remove(&connection_id)
+            {
+                continue;
+            }
+            let shipment_ids: Vec<usize> = self.businesses[business_id]
+                .shipments
+                .iter()
+                .enumerate()
+                .filter(|(_, shipment)| shipment.connection_ids.contains(&connection_id))
+                .map(|(shipment_id, _)| shipment_id)
+                .collect();
+            for shipment_id in shipment_ids.into_iter().rev() {
+                self.remove_shipment(business_id, shipment_id);
+            }
+        }
+    }
+}
+
+/// Moves the toll of every connection from the businesses using it to its owner.
+pub fn pay_tolls(game: &mut Game) {
+    for business_id in 0..game.graph.businesses.len() {
+        if game.graph.businesses[business_id].eliminated {
+            continue;
+        }
+        let connection_ids: Vec<usize> = game.graph.businesses[business_id]
+            .toll_connection_ids
+            .iter()
+            .copied()
+            .collect();
+        for connection_id in connection_ids {
+            let owned_connection = &game.graph.connections[connection_id];
+            let (Some(owner_id), Some(toll)) = (owned_connection.owner_id, owned_connection.toll)
+            else {
+                continue;
+            };
+            let source = Source::Toll(connection_id);
+            game.graph.businesses[business_id].add_money(game.round, source, -toll);
+            game.graph.businesses[owner_id].add_money(game.round, source, toll);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        game::{Command, GameError},
+        logistics::Shipment,
+        resolution::{ActivityId, Throttle},
+        testing,
+    };
+
+    /// Player 0 owns the connection from city 0 to city 1, player 1 the one from city 1 to city 2
+    /// with a toll of 3. Neither costs upkeep.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(
+            &[(0.0, 0.0, &[]), (200.0, 0.0, &[]), (400.0, 0.0, &[])],
+            &[(0, 1), (1, 2)],
+        );
+        testing::own_connection(&mut game, 0, 0);
+        testing::own_connection(&mut game, 1, 1);
+        for owned_connection in &mut game.graph.connections {
+            owned_connection.maintenance_cost = 0;
+        }
+        game.graph.connections[1].toll = Some(3);
+        game
+    }
+
+    fn shipment(connection_ids: Vec<usize>) -> Shipment {
+        Shipment {
+            material: testing::material("Log"),
+            from_city_id: 0,
+            to_city_id: connection_ids.len(),
+            quantity: 1,
+            connection_ids,
+        }
+    }
+
+    #[test]
+    fn rounds_move_tolls_from_payers_to_the_owner() {
+        let mut game = new_game();
+        game.execute(Command::StartPayingToll { connection_id: 1 })
+            .unwrap();
+        assert!(game.graph.can_use_connection(0, 1));
+        assert_eq!(game.graph.get_toll_balance(0), -3);
+        assert_eq!(game.graph.get_toll_balance(1), 3);
+        let round = game.round;
+        for _ in 0..game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+
+        for (business_id, toll) in [(0, -3), (1, 3)] {
+            let business = &game.graph.businesses[business_id];
+            assert_eq!(business.money, 250 + toll);
+            let entry = &business.ledger.rounds[&round][0];
+            assert_eq!((entry.source, entry.quantity), (Source::Toll(1), toll));
+        }
+    }
+
+    #[test]
+    fn raising_or_closing_a_toll_drops_its_payers_and_their_shipments() {
+        let mut game = new_game();
+        game.execute(Command::StartPayingToll { connection_id: 1 })
+            .unwrap();
+        let business = &mut game.graph.businesses[0];
+        business.shipments = vec![shipment(vec![0, 1]), shipment(vec![0])];
+        business.throttles.push(Throttle {
+            id: ActivityId::Shipment(1),
+            planned: 1,
+            throughput: 0,
+            shortage: testing::material("Log"),
+        });
+        game.execute(Command::EndTurn).unwrap();
+
+        game.execute(Command::SetToll {
+            connection_id: 1,
+            toll: Some(2),
+        })
+        .unwrap();
+        assert_eq!(game.graph.get_toll_payer_ids(1), vec![0]);
+        game.execute(Command::SetToll {
+            connection_id: 1,
+            toll: Some(5),
+        })
+        .unwrap();
+
+        let business = &game.graph.businesses[0];
+        assert!(game.graph.get_toll_payer_ids(1).is_empty());
+        assert_eq!(business.shipments.len(), 1);
+        assert_eq!(business.shipments[0].connection_ids, vec![0]);
+        assert_eq!(business.throttles[0].id, ActivityId::Shipment(0));
+        assert_eq!(
+            game.execute(Command::SetToll {
+                connection_id: 1,
+                toll: Some(MAX_TOLL + 1),
+            }),
+            Err(GameError::InvalidQuantity(MAX_TOLL + 1))
+        );
+
+        game.graph.businesses[0].toll_connection_ids.insert(1);
+        game.execute(Command::SetToll {
+            connection_id: 1,
+            toll: None,
+        })
+        .unwrap();
+        assert!(game.graph.get_toll_payer_ids(1).is_empty());
+    }
+
+    #[test]
+    fn toll_balances_saturate() {
+        let mut game = new_game();
+        let payer = game.graph.businesses[0].clone();
+        game.graph.businesses.push(payer);
+        for business_id in [0, 2] {
+            game.graph.businesses[business_id]
+                .toll_connection_ids
+                .insert(1);
+        }
+        game.graph.connections[1].toll = Some(i32::MAX);
+        assert_eq!(game.graph.get_toll_balance(1), i32::MAX);
+        assert_eq!(game.graph.get_toll_balance(0), -i32::MAX);
+    }
+}
@@ -0,0 +1,330 @@
+use super::{game::Game, ledger::Source, registry::MaterialId, Graph};
+use serde::{Deserialize, Serialize};
+
+/// What two businesses exchange in a city. Money is pooled, so only materials have to be there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Terms {
+    pub city_id: usize,
+    /// From the proposer to the other business.
+    pub gives: Vec<(MaterialId, i32)>,
+    /// From the other business to the proposer.
+    pub takes: Vec<(MaterialId, i32)>,
+    /// Zero for a one-off trade, otherwise how many rounds the exchange repeats as a contract.
+    pub rounds: u32,
+    /// Money a party pays the other for every round it fails to deliver.
+    pub penalty: i32,
+}
+
+impl Terms {
+    pub fn is_contract(&self) -> bool {
+        self.rounds > 0
+    }
+
+    /// The same exchange seen from the other business.
+    pub fn reversed(&self) -> Self {
+        Self {
+            gives: self.takes.clone(),
+            takes: self.gives.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Terms proposed by one business to another, waiting for an answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Offer {
+    pub from_id: usize,
+    pub to_id: usize,
+    pub terms: Terms,
+}
+
+/// Accepted terms delivered every round until they run out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contract {
+    /// The proposer first. It delivers `terms.gives` and the other party `terms.takes`.
+    pub party_ids: [usize; 2],
+    pub terms: Terms,
+    pub rounds_left: u32,
+    /// Rounds each party failed to deliver so far.
+    pub missed: [u32; 2],
+}
+
+impl Contract {
+    pub fn new(offer: Offer) -> Self {
+        Self {
+            party_ids: [offer.from_id, offer.to_id],
+            rounds_left: offer.terms.rounds,
+            terms: offer.terms,
+            missed: [0, 0],
+        }
+    }
+
+    /// Which of the two parties the business is.
+    pub fn get_side(&self, business_id: usize) -> Option<usize> {
+        self.party_ids.iter().position(|id| *id == business_id)
+    }
+
+    /// Terms as seen from one side.
+    pub fn get_terms(&self, side: usize) -> Terms {
+        match side {
+            0 => self.terms.clone(),
+            _ => self.terms.reversed(),
+        }
+    }
+}
+
+/// Goods as "5 Plank + 6 Money".
+pub fn format_goods(goods: &[(MaterialId, i32)]) -> String {
+    if goods.is_empty() {
+        return "nothing".to_string();
+    }
+    goods
+        .iter()
+        .map(|(material, quantity)| format!("{} {}", quantity, material))
+        .collect::<Vec<String>>()
+        .join(" + ")
+}
+
+impl Graph {
+    /// Offers the business made or received.
+    pub fn get_offer_ids(&self, business_id: usize) -> Vec<usize> {
+        (0..self.offers.len())
+            .filter(|offer_id| {
+                let offer = &self.offers[*offer_id];
+                offer.from_id == business_id || offer.to_id == business_id
+            })
+            .collect()
+    }
+
+    /// Contracts the business is a party to.
+    pub fn get_contract_ids(&self, business_id: usize) -> Vec<usize> {
+        (0..self.contracts.len())
+            .filter(|contract_id| self.contracts[*contract_id].get_side(business_id).is_some())
+            .collect()
+    }
+
+    /// Whether the business holds the goods right now, ignoring this round's flows.
+    pub fn holds(&self, business_id: usize, city_id: usize, goods: &[(MaterialId, i32)]) -> bool {
+        let business = &self.businesses[business_id];
+        goods.iter().all(|(material, quantity)| {
+            let held = if material.is_money() {
+                business.money
+            } else {
+                business
+                    .inventories
+                    .get(&city_id)
+                    .and_then(|inventory| inventory.get(material))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            held >= *quantity
+        })
+    }
+
+    /// Moves goods between two businesses, booked for each against the other by `source`.
+    pub fn transfer(
+        &mut self,
+        round: u32,
+        (from_id, to_id): (usize, usize),
+        city_id: usize,
+        goods: &[(MaterialId, i32)],
+        source: fn(usize) -> Source,
+    ) {
+        let taken: Vec<(MaterialId, i32)> = goods
+            .iter()
+            .map(|(material, quantity)| (material.clone(), -quantity))
+            .collect();
+        self.businesses[from_id].add_goods(round, source(to_id), city_id, &taken);
+        self.businesses[to_id].add_goods(round, source(from_id), city_id, goods);
+    }
+
+    /// Drops the offers and contracts of a business that left the game.
+    pub fn cancel_trades(&mut self, business_id: usize) {
+        self.offers
+            .retain(|offer| offer.from_id != business_id && offer.to_id != business_id);
+        self.contracts
+            .retain(|contract| contract.get_side(business_id).is_none());
+    }
+}
+
+/// Delivers every contract for the round. Whether each party can deliver all of its goods is
+/// decided before anything moves, so neither pays with what the other just delivered. A party
+/// that can't delivers nothing and pays the penalty instead. Contracts in a city either party no
+/// longer reaches are cancelled.
+pub fn settle_contracts(game: &mut Game) {
+    let round = game.round;
+    let graph = &mut game.graph;
+    for contract_id in 0..graph.contracts.len() {
+        let Contract {
+            party_ids, terms, ..
+        } = graph.contracts[contract_id].clone();
+        if party_ids.iter().any(|party_id| {
+            !graph
+                .get_network_city_ids(*party_id)
+                .contains(&terms.city_id)
+        }) {
+            graph.contracts[contract_id].rounds_left = 0;
+            continue;
+        }
+        let goods = [&terms.gives, &terms.takes];
+        let delivers = [0, 1].map(|side| graph.holds(party_ids[side], terms.city_id, goods[side]));
+        for side in 0..2 {
+            let (from_id, to_id) = (party_ids[side], party_ids[1 - side]);
+            if delivers[side] {
+                graph.transfer(
+                    round,
+                    (from_id, to_id),
+                    terms.city_id,
+                    goods[side],
+                    Source::Contract,
+                );
+            } else {
+                graph.transfer(
+                    round,
+                    (from_id, to_id),
+                    terms.city_id,
+                    &[(MaterialId::money(), terms.penalty)],
+                    Source::Penalty,
+                );
+                graph.contracts[contract_id].missed[side] += 1;
+            }
+        }
+        graph.contracts[contract_id].rounds_left -= 1;
+    }
+    graph.contracts.retain(|contract| contract.rounds_left > 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Command, testing};
+
+    /// Player 0 owns one market in city 0 and player 1 the other. Neither costs upkeep.
+    fn new_game() -> Game {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &["Market", "Market"])], &[]);
+        testing::own_building(&mut game, 0, 0, 0);
+        testing::own_building(&mut game, 1, 0, 1);
+        game
+    }
+
+    fn log() -> MaterialId {
+        testing::material("Log")
+    }
+
+    /// Player 0 delivers `logs` logs a round for 5 money, or pays a penalty of 7.
+    fn sign(game: &mut Game, logs: i32, rounds: u32) {
+        game.graph.contracts.push(Contract::new(Offer {
+            from_id: 0,
+            to_id: 1,
+            terms: Terms {
+                city_id: 0,
+                gives: vec![(log(), logs)],
+                takes: vec![(MaterialId::money(), 5)],
+                rounds,
+                penalty: 7,
+            },
+        }));
+    }
+
+    fn end_round(game: &mut Game) {
+        for _ in 0..game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+    }
+
+    fn get_logs(game: &Game, business_id: usize) -> i32 {
+        game.graph.businesses[business_id].inventories[&0][&log()]
+    }
+
+    #[test]
+    fn accepted_one_off_trades_swap_goods_at_once() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(log(), 2)]);
+        let terms = Terms {
+            city_id: 0,
+            gives: vec![(log(), 2)],
+            takes: vec![(MaterialId::money(), 5)],
+            rounds: 0,
+            penalty: 0,
+        };
+        game.execute(Command::ProposeTrade { to_id: 1, terms })
+            .unwrap();
+        game.execute(Command::EndTurn).unwrap();
+        game.execute(Command::AcceptOffer { offer_id: 0 }).unwrap();
+
+        assert!(game.graph.offers.is_empty());
+        assert!(game.graph.contracts.is_empty());
+        assert_eq!((get_logs(&game, 0), get_logs(&game, 1)), (0, 2));
+        assert_eq!(game.graph.businesses[0].money, 255);
+        assert_eq!(game.graph.businesses[1].money, 245);
+    }
+
+    #[test]
+    fn contracts_deliver_both_sides_every_round_until_they_run_out() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(log(), 4)]);
+        sign(&mut game, 2, 2);
+        end_round(&mut game);
+        assert_eq!(game.graph.contracts[0].rounds_left, 1);
+        end_round(&mut game);
+
+        assert!(game.graph.contracts.is_empty());
+        assert_eq!((get_logs(&game, 0), get_logs(&game, 1)), (0, 4));
+        assert_eq!(game.graph.businesses[0].money, 260);
+        assert_eq!(game.graph.businesses[1].money, 240);
+    }
+
+    #[test]
+    fn a_party_that_cannot_deliver_pays_the_penalty() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(log(), 1)]);
+        sign(&mut game, 2, 2);
+        end_round(&mut game);
+
+        assert_eq!(game.graph.contracts[0].missed, [1, 0]);
+        assert_eq!(get_logs(&game, 0), 1);
+        assert_eq!(game.graph.businesses[0].money, 250 + 5 - 7);
+        assert_eq!(game.graph.businesses[1].money, 250 - 5 + 7);
+        let sources: Vec<Source> = game.graph.businesses[0].ledger.rounds[&1]
+            .iter()
+            .map(|entry| entry.source)
+            .collect();
+        assert_eq!(sources, [Source::Penalty(1), Source::Contract(1)]);
+    }
+
+    #[test]
+    fn goods_delivered_in_a_round_cannot_pay_for_it() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(log(), 2)]);
+        game.graph.contracts.push(Contract::new(Offer {
+            from_id: 0,
+            to_id: 1,
+            terms: Terms {
+                city_id: 0,
+                gives: vec![(log(), 2)],
+                takes: vec![(log(), 1)],
+                rounds: 1,
+                penalty: 7,
+            },
+        }));
+        end_round(&mut game);
+
+        assert_eq!((get_logs(&game, 0), get_logs(&game, 1)), (0, 2));
+        assert_eq!(game.graph.businesses[0].money, 257);
+        assert_eq!(game.graph.businesses[1].money, 243);
+    }
+
+    #[test]
+    fn contracts_are_cancelled_once_a_party_leaves_the_city() {
+        let mut game = new_game();
+        testing::give(&mut game, 0, 0, &[(log(), 2)]);
+        sign(&mut game, 2, 3);
+        game.graph.cities[0].get_building_mut(1).unwrap().release();
+        end_round(&mut game);
+
+        assert!(game.graph.contracts.is_empty());
+        assert_eq!(get_logs(&game, 0), 2);
+        assert_eq!(game.graph.businesses[0].money, 250);
+        assert_eq!(game.graph.businesses[1].money, 250);
+    }
+}
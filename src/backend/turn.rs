@@ -0,0 +1,79 @@
+use super::{construction, game::Game, stats, toll, trade, upkeep, victory};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Every round each player plans in turn, then all businesses resolve together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Phase {
+    Planning,
+    Production,
+    Trade,
+    Upkeep,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A system hooked into a phase. It runs once per phase for all businesses.
+pub type System = fn(&mut Game);
+
+impl Phase {
+    pub const RESOLUTION: [Phase; 3] = [Phase::Production, Phase::Trade, Phase::Upkeep];
+
+    /// Systems in the order they run. Planning systems run whenever a player starts planning.
+    pub fn get_systems(self) -> &'static [System] {
+        match self {
+            Phase::Planning => &[],
+            Phase::Production => &[construction::advance_construction, produce],
+            Phase::Trade => &[update_market_prices, trade::settle_contracts],
+            Phase::Upkeep => &[
+                toll::pay_tolls,
+                upkeep::pay_upkeep,
+                stats::record_history,
+                victory::eliminate_bankrupt,
+                victory::check_win_condition,
+            ],
+        }
+    }
+}
+
+fn produce(game: &mut Game) {
+    for business_id in 0..game.graph.businesses.len() {
+        game.graph
+            .update_business_resources(&game.registry, business_id, game.round);
+    }
+}
+
+fn update_market_prices(game: &mut Game) {
+    game.graph.update_market_prices(&game.registry);
+}
+
+impl Game {
+    /// Hands planning to the next player still in the game, resolving the round after the last
+    /// one.
+    pub fn end_turn(&mut self) {
+        loop {
+            self.current_player_id += 1;
+            if self.current_player_id >= self.graph.businesses.len() {
+                self.current_player_id = 0;
+                for phase in Phase::RESOLUTION {
+                    self.run_phase(phase);
+                }
+                self.round += 1;
+            }
+            if self.outcome.is_some() || !self.graph.businesses[self.current_player_id].eliminated {
+                break;
+            }
+        }
+        self.run_phase(Phase::Planning);
+    }
+
+    fn run_phase(&mut self, phase: Phase) {
+        for system in phase.get_systems() {
+            system(self);
+        }
+    }
+}
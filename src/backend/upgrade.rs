@@ -0,0 +1,42 @@
+use super::{
+    registry::{Registry, UpgradeDef, ValidRecipe},
+    OwnedBuilding, ScaledValidRecipe,
+};
+
+impl OwnedBuilding {
+    /// The level this building can be upgraded to next, if any.
+    pub fn get_next_upgrade<'a>(&self, registry: &'a Registry) -> Option<&'a UpgradeDef> {
+        registry.building_types[&self.building_type]
+            .upgrades
+            .get(self.level)
+    }
+
+    /// Raises the max scale of every recipe and adds the recipes the new level unlocks. What was
+    /// paid counts toward the value of the building.
+    pub fn upgrade(&mut self, registry: &Registry) {
+        let Some(upgrade) = self.get_next_upgrade(registry) else {
+            return;
+        };
+        let upgrade = upgrade.clone();
+        for scaled_valid_recipe in &mut self.production_scale {
+            scaled_valid_recipe.max_scale = upgrade.max_scale;
+        }
+        for recipe in upgrade.recipes {
+            let valid_recipe = ValidRecipe::Production(recipe);
+            if self
+                .production_scale
+                .iter()
+                .all(|scaled_valid_recipe| scaled_valid_recipe.valid_recipe != valid_recipe)
+            {
+                self.production_scale.push(ScaledValidRecipe {
+                    valid_recipe,
+                    scale: 0,
+                    max_scale: upgrade.max_scale,
+                    priority: 0,
+                });
+            }
+        }
+        self.acquisition_cost += upgrade.cost;
+        self.level += 1;
+    }
+}
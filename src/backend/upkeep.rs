@@ -0,0 +1,139 @@
+use super::{game::Game, ledger::Source, registry::Registry, sale::AssetId, Graph, OwnedBuilding};
+
+impl OwnedBuilding {
+    /// Construction sites cost nothing until they are completed.
+    pub fn get_upkeep(&self, registry: &Registry) -> i32 {
+        if self.is_under_construction() {
+            return 0;
+        }
+        let building_type_def = &registry.building_types[&self.building_type];
+        let scale: i32 = self
+            .production_scale
+            .iter()
+            .map(|scaled_valid_recipe| scaled_valid_recipe.scale)
+            .sum();
+        (building_type_def.upkeep as f32 + building_type_def.upkeep_per_scale * scale as f32)
+            .round() as i32
+    }
+}
+
+impl Graph {
+    /// Upkeep a business pays per round for each of its buildings and connections.
+    pub fn get_upkeep_costs(&self, registry: &Registry, business_id: usize) -> Vec<(AssetId, i32)> {
+        let mut upkeep_costs = vec![];
+        for (city_id, city) in self.cities.iter().enumerate() {
+            for (building_id, owned_building) in city.get_buildings() {
+                if owned_building.owner_id == Some(business_id) {
+                    upkeep_costs.push((
+                        AssetId::Building {
+                            city_id,
+                            building_id,
+                        },
+                        owned_building.get_upkeep(registry),
+                    ));
+                }
+            }
+        }
+        for (connection_id, owned_connection) in self.connections.iter().enumerate() {
+            if owned_connection.owner_id == Some(business_id)
+                && !owned_connection.is_under_construction()
+            {
+                upkeep_costs.push((
+                    AssetId::Connection(connection_id),
+                    owned_connection.maintenance_cost,
+                ));
+            }
+        }
+        upkeep_costs
+    }
+
+    /// Total upkeep a business pays per round for its buildings and connections.
+    pub fn get_upkeep(&self, registry: &Registry, business_id: usize) -> i32 {
+        self.get_upkeep_costs(registry, business_id)
+            .into_iter()
+            .map(|(_, upkeep)| upkeep)
+            .sum()
+    }
+}
+
+/// Charges every business still in the game for its assets. Cash may go negative.
+pub fn pay_upkeep(game: &mut Game) {
+    for business_id in 0..game.graph.businesses.len() {
+        if game.graph.businesses[business_id].eliminated {
+            continue;
+        }
+        for (asset_id, upkeep) in game.graph.get_upkeep_costs(&game.registry, business_id) {
+            game.graph.businesses[business_id].add_money(
+                game.round,
+                Source::Upkeep(asset_id),
+                -upkeep,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Command, testing};
+
+    /// Player 0 owns the sawmill in city 0 and the connection to city 1.
+    fn new_game() -> Game {
+        let mut game =
+            testing::new_game_on_map(&[(0.0, 0.0, &["Sawmill"]), (200.0, 0.0, &[])], &[(0, 1)]);
+        testing::own_building(&mut game, 0, 0, 0);
+        testing::own_connection(&mut game, 0, 0);
+        game
+    }
+
+    #[test]
+    fn building_upkeep_grows_with_scale() {
+        let mut game = new_game();
+        let building = game.graph.cities[0].get_building_mut(0).unwrap();
+        assert_eq!(building.get_upkeep(&game.registry), 1);
+        building.production_scale[0].scale = 5;
+        assert_eq!(building.get_upkeep(&game.registry), 2);
+        building.construction_rounds = 1;
+        assert_eq!(building.get_upkeep(&game.registry), 0);
+    }
+
+    #[test]
+    fn rounds_charge_upkeep_for_every_asset() {
+        let mut game = new_game();
+        game.graph.connections[0].maintenance_cost = 3;
+        let round = game.round;
+        for _ in 0..game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+
+        assert_eq!(game.graph.businesses[0].money, 250 - 4);
+        assert_eq!(game.graph.businesses[1].money, 250);
+        let upkeep: Vec<(Source, i32)> = game.graph.businesses[0].ledger.rounds[&round]
+            .iter()
+            .map(|entry| (entry.source, entry.quantity))
+            .collect();
+        assert_eq!(
+            upkeep,
+            vec![
+                (
+                    Source::Upkeep(AssetId::Building {
+                        city_id: 0,
+                        building_id: 0,
+                    }),
+                    -1
+                ),
+                (Source::Upkeep(AssetId::Connection(0)), -3),
+            ]
+        );
+    }
+
+    #[test]
+    fn connections_under_construction_and_eliminated_businesses_pay_nothing() {
+        let mut game = new_game();
+        game.graph.connections[0].construction_rounds = 2;
+        assert_eq!(game.graph.get_upkeep(&game.registry, 0), 1);
+        game.graph.businesses[0].eliminated = true;
+        pay_upkeep(&mut game);
+        assert_eq!(game.graph.businesses[0].money, 250);
+    }
+}
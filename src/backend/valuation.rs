@@ -0,0 +1,275 @@
+use super::{
+    registry::{MarketPrice, MaterialId, Registry, ValidRecipe},
+    sale::{self, AssetId},
+    Graph,
+};
+use std::collections::BTreeMap;
+
+/// Share of the acquisition cost an asset loses every round it is owned, down to its sale price.
+pub const DEPRECIATION_PER_ROUND: f32 = 0.01;
+/// Rounds of ledger history averaged into the returns of an asset.
+pub const RETURN_ROUNDS: u32 = 10;
+
+/// Acquisition cost written down every round since the asset was acquired.
+pub fn get_book_value(acquisition_cost: i32, acquired_round: u32, round: u32) -> i32 {
+    let age = round.saturating_sub(acquired_round) as f32;
+    let share = (1.0 - DEPRECIATION_PER_ROUND * age).max(sale::SALE_SHARE);
+    (acquisition_cost as f32 * share).round() as i32
+}
+
+/// How an owned asset has been paying off lately.
+#[derive(Debug, Clone, Copy)]
+pub struct AssetReturn {
+    pub asset_id: AssetId,
+    pub acquisition_cost: i32,
+    pub book_value: i32,
+    /// Value earned running the asset minus its upkeep, averaged over recent rounds. Materials
+    /// count at their current market value.
+    pub profit_per_round: f32,
+}
+
+impl AssetReturn {
+    /// Profit per round as a share of the acquisition cost.
+    pub fn get_roi(&self) -> f32 {
+        self.profit_per_round / self.acquisition_cost.max(1) as f32
+    }
+
+    /// Rounds of current profit it takes to earn back the acquisition cost. `None` when the
+    /// asset does not make a profit.
+    pub fn get_payback_rounds(&self) -> Option<f32> {
+        (self.profit_per_round > 0.0).then(|| self.acquisition_cost as f32 / self.profit_per_round)
+    }
+}
+
+impl Graph {
+    /// Mid price of a unit averaged over every market trading the material, or its base price
+    /// when none does.
+    pub fn get_market_value(&self, registry: &Registry, material: &MaterialId) -> f32 {
+        if material.is_money() {
+            return 1.0;
+        }
+        let MarketPrice { quantity: lot, .. } = registry.get_market_price(material);
+        let mid_prices: Vec<f32> = self
+            .cities
+            .iter()
+            .flat_map(|city| city.owned_buildings.iter().flatten())
+            .filter(|owned_building| {
+                owned_building
+                    .production_scale
+                    .iter()
+                    .any(
+                        |scaled_valid_recipe| match &scaled_valid_recipe.valid_recipe {
+                            ValidRecipe::MaterialImport(traded)
+                            | ValidRecipe::MaterialExport(traded) => traded == material,
+                            ValidRecipe::Production(_) => false,
+                        },
+                    )
+            })
+            .map(|owned_building| owned_building.get_mid_price(registry, material))
+            .collect();
+        let mid_price = if mid_prices.is_empty() {
+            registry.get_market_price(material).price as f32
+        } else {
+            mid_prices.iter().sum::<f32>() / mid_prices.len() as f32
+        };
+        mid_price / lot as f32
+    }
+
+    /// Market value of a unit of every material, money included.
+    pub fn get_market_values(&self, registry: &Registry) -> BTreeMap<MaterialId, f32> {
+        registry
+            .materials
+            .keys()
+            .map(|material| (material.clone(), self.get_market_value(registry, material)))
+            .collect()
+    }
+
+    pub fn get_inventory_value(&self, registry: &Registry, business_id: usize) -> i32 {
+        self.businesses[business_id]
+            .inventories
+            .values()
+            .flatten()
+            .map(|(material, quantity)| {
+                *quantity as f32 * self.get_market_value(registry, material)
+            })
+            .sum::<f32>()
+            .round() as i32
+    }
+
+    /// What the business stands to lose on its contracts over their remaining rounds, counting
+    /// only contracts where it delivers more than it receives at market value.
+    pub fn get_liabilities(&self, registry: &Registry, business_id: usize) -> i32 {
+        let get_value = |goods: &[(MaterialId, i32)]| -> f32 {
+            goods
+                .iter()
+                .map(|(material, quantity)| {
+                    *quantity as f32 * self.get_market_value(registry, material)
+                })
+                .sum()
+        };
+        self.get_contract_ids(business_id)
+            .into_iter()
+            .map(|contract_id| {
+                let contract = &self.contracts[contract_id];
+                let terms = contract.get_terms(contract.get_side(business_id).unwrap());
+                let net_cost = get_value(&terms.gives) - get_value(&terms.takes);
+                net_cost.max(0.0) * contract.rounds_left as f32
+            })
+            .sum::<f32>()
+            .round() as i32
+    }
+
+    /// Returns of an owned asset from the ledger rounds completed since it was acquired, at
+    /// most `RETURN_ROUNDS` of them.
+    pub fn get_asset_return(
+        &self,
+        registry: &Registry,
+        business_id: usize,
+        asset_id: AssetId,
+        round: u32,
+    ) -> AssetReturn {
+        let (acquisition_cost, acquired_round) = match asset_id {
+            AssetId::Building {
+                city_id,
+                building_id,
+            } => {
+                let owned_building = self.cities[city_id].get_building(building_id).unwrap();
+                (
+                    owned_building.acquisition_cost,
+                    owned_building.acquired_round,
+                )
+            }
+            AssetId::Connection(connection_id) => {
+                let owned_connection = &self.connections[connection_id];
+                (
+                    owned_connection.acquisition_cost,
+                    owned_connection.acquired_round,
+                )
+            }
+        };
+        let first_round = acquired_round.max(round.saturating_sub(RETURN_ROUNDS));
+        let ledger = &self.businesses[business_id].ledger;
+        let market_values = self.get_market_values(registry);
+        let profit: i32 = (first_round..round)
+            .filter_map(|past_round| {
+                ledger
+                    .get_income_statement(past_round, &market_values)
+                    .assets
+                    .get(&asset_id)
+                    .map(|quantity_info| quantity_info.net_in())
+            })
+            .sum();
+        AssetReturn {
+            asset_id,
+            acquisition_cost,
+            book_value: get_book_value(acquisition_cost, acquired_round, round),
+            profit_per_round: profit as f32 / round.saturating_sub(first_round).max(1) as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        ledger::Source,
+        testing,
+        trade::{Contract, Offer, Terms},
+    };
+
+    fn log() -> MaterialId {
+        testing::material("Log")
+    }
+
+    #[test]
+    fn book_values_depreciate_down_to_the_sale_price() {
+        assert_eq!(get_book_value(100, 3, 3), 100);
+        assert_eq!(get_book_value(100, 3, 13), 90);
+        assert_eq!(get_book_value(100, 3, 1000), 50);
+        assert_eq!(get_book_value(100, 3, 0), 100);
+    }
+
+    #[test]
+    fn market_values_average_the_markets_trading_a_material() {
+        let mut game =
+            testing::new_game_on_map(&[(0.0, 0.0, &["Market"]), (200.0, 0.0, &["Market"])], &[]);
+        let registry = game.registry.clone();
+        assert_eq!(game.graph.get_market_value(&registry, &log()), 2.0);
+        for (city_id, price) in [(0, 3.0), (1, 6.0)] {
+            game.graph.cities[city_id]
+                .get_building_mut(0)
+                .unwrap()
+                .market_prices
+                .insert(log(), price);
+        }
+        assert_eq!(game.graph.get_market_value(&registry, &log()), 4.5);
+        assert_eq!(
+            game.graph.get_market_value(&registry, &MaterialId::money()),
+            1.0
+        );
+        let unknown = testing::material("Unobtainium");
+        assert_eq!(registry.get_market_price(&unknown).price, 0);
+        assert_eq!(game.graph.get_market_value(&registry, &unknown), 0.0);
+
+        testing::give(&mut game, 0, 0, &[(log(), 2), (unknown, 5)]);
+        testing::give(&mut game, 0, 1, &[(log(), 1)]);
+        assert_eq!(game.graph.get_inventory_value(&registry, 0), 14);
+    }
+
+    #[test]
+    fn contracts_that_cost_more_than_they_bring_are_liabilities() {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &[])], &[]);
+        game.graph.contracts.push(Contract::new(Offer {
+            from_id: 0,
+            to_id: 1,
+            terms: Terms {
+                city_id: 0,
+                gives: vec![(log(), 3)],
+                takes: vec![(MaterialId::money(), 1)],
+                rounds: 4,
+                penalty: 0,
+            },
+        }));
+        assert_eq!(game.graph.get_liabilities(&game.registry, 0), (6 - 1) * 4);
+        assert_eq!(game.graph.get_liabilities(&game.registry, 1), 0);
+    }
+
+    #[test]
+    fn asset_returns_average_recent_profits_with_materials_at_market_value() {
+        let mut game = testing::new_game_on_map(&[(0.0, 0.0, &["Sawmill"])], &[]);
+        testing::own_building(&mut game, 0, 0, 0);
+        let sawmill = game.graph.cities[0].get_building_mut(0).unwrap();
+        sawmill.acquisition_cost = 80;
+        sawmill.acquired_round = 2;
+        let asset_id = AssetId::Building {
+            city_id: 0,
+            building_id: 0,
+        };
+        let ledger = &mut game.graph.businesses[0].ledger;
+        for round in 1..5 {
+            ledger.record(round, Source::Upkeep(asset_id), MaterialId::money(), -1);
+            ledger.record(
+                round,
+                Source::Production {
+                    city_id: 0,
+                    building_id: 0,
+                    recipe_id: 0,
+                },
+                log(),
+                3,
+            );
+        }
+
+        let asset_return = game.graph.get_asset_return(&game.registry, 0, asset_id, 5);
+        assert_eq!(asset_return.profit_per_round, 5.0);
+        assert_eq!(asset_return.book_value, 78);
+        assert_eq!(asset_return.get_roi(), 5.0 / 80.0);
+        assert_eq!(asset_return.get_payback_rounds(), Some(16.0));
+
+        let ledger = &mut game.graph.businesses[0].ledger;
+        ledger.record(5, Source::Upkeep(asset_id), MaterialId::money(), -40);
+        let asset_return = game.graph.get_asset_return(&game.registry, 0, asset_id, 6);
+        assert_eq!(asset_return.profit_per_round, (5.0 * 3.0 - 40.0) / 4.0);
+        assert_eq!(asset_return.get_payback_rounds(), None);
+    }
+}
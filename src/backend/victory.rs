@@ -0,0 +1,348 @@
+use super::{game::Game, registry::Registry, valuation, Graph};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WinCondition {
+    /// Play on without a winner.
+    #[default]
+    Sandbox,
+    NetWorth {
+        target: i32,
+    },
+    HighestNetWorth {
+        rounds: u32,
+    },
+    /// Own at least `percent` of all buildings and connections.
+    MapShare {
+        percent: u32,
+    },
+}
+
+impl WinCondition {
+    /// Every kind of win condition with its default goal.
+    pub const ALL: [WinCondition; 4] = [
+        WinCondition::Sandbox,
+        WinCondition::NetWorth { target: 2000 },
+        WinCondition::HighestNetWorth { rounds: 50 },
+        WinCondition::MapShare { percent: 50 },
+    ];
+
+    /// The same kind of condition with its goal moved by `steps` increments.
+    pub fn adjust(self, steps: i32) -> Self {
+        match self {
+            Self::Sandbox => Self::Sandbox,
+            Self::NetWorth { target } => Self::NetWorth {
+                target: (target + 250 * steps).max(250),
+            },
+            Self::HighestNetWorth { rounds } => Self::HighestNetWorth {
+                rounds: rounds.saturating_add_signed(10 * steps).max(10),
+            },
+            Self::MapShare { percent } => Self::MapShare {
+                percent: percent.saturating_add_signed(5 * steps).clamp(5, 100),
+            },
+        }
+    }
+}
+
+impl fmt::Display for WinCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sandbox => write!(f, "No win condition"),
+            Self::NetWorth { target } => write!(f, "Reach {}$ net worth", target),
+            Self::HighestNetWorth { rounds } => {
+                write!(f, "Highest net worth after {} rounds", rounds)
+            }
+            Self::MapShare { percent } => write!(f, "Own {}% of the map", percent),
+        }
+    }
+}
+
+/// How the game ended. There is no winner when every business went bankrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub winner_id: Option<usize>,
+    pub round: u32,
+}
+
+/// Net worth of a business split by what it is held in.
+#[derive(Debug, Clone, Copy)]
+pub struct Score {
+    pub business_id: usize,
+    pub cash: i32,
+    pub buildings_value: i32,
+    pub connections_value: i32,
+    pub inventory_value: i32,
+    pub liabilities: i32,
+}
+
+impl Score {
+    pub fn get_net_worth(&self) -> i32 {
+        self.cash + self.buildings_value + self.connections_value + self.inventory_value
+            - self.liabilities
+    }
+}
+
+impl Graph {
+    /// Values buildings and connections at their depreciated book value, inventory at current
+    /// market prices and contracts that cost more than they bring as liabilities.
+    pub fn get_score(&self, registry: &Registry, business_id: usize, round: u32) -> Score {
+        let buildings_value = self
+            .cities
+            .iter()
+            .flat_map(|city| city.owned_buildings.iter().flatten())
+            .filter(|owned_building| owned_building.owner_id == Some(business_id))
+            .map(|owned_building| {
+                valuation::get_book_value(
+                    owned_building.acquisition_cost,
+                    owned_building.acquired_round,
+                    round,
+                )
+            })
+            .sum();
+        let connections_value = self
+            .connections
+            .iter()
+            .filter(|owned_connection| owned_connection.owner_id == Some(business_id))
+            .map(|owned_connection| {
+                valuation::get_book_value(
+                    owned_connection.acquisition_cost,
+                    owned_connection.acquired_round,
+                    round,
+                )
+            })
+            .sum();
+        Score {
+            business_id,
+            cash: self.businesses[business_id].money,
+            buildings_value,
+            connections_value,
+            inventory_value: self.get_inventory_value(registry, business_id),
+            liabilities: self.get_liabilities(registry, business_id),
+        }
+    }
+
+    /// Scores of all businesses, highest net worth first.
+    pub fn get_scoreboard(&self, registry: &Registry, round: u32) -> Vec<Score> {
+        let mut scores: Vec<Score> = (0..self.businesses.len())
+            .map(|business_id| self.get_score(registry, business_id, round))
+            .collect();
+        scores.sort_by_key(|score| -score.get_net_worth());
+        scores
+    }
+
+    /// Share of all buildings and connections owned by the business, in percent.
+    pub fn get_map_share(&self, business_id: usize) -> f32 {
+        let owner_ids: Vec<Option<usize>> = self
+            .cities
+            .iter()
+            .flat_map(|city| city.owned_buildings.iter().flatten())
+            .map(|owned_building| owned_building.owner_id)
+            .chain(
+                self.connections
+                    .iter()
+                    .map(|owned_connection| owned_connection.owner_id),
+            )
+            .collect();
+        let owned = owner_ids
+            .iter()
+            .filter(|owner_id| **owner_id == Some(business_id))
+            .count();
+        100.0 * owned as f32 / owner_ids.len().max(1) as f32
+    }
+
+    /// Gives up every asset of the business and stops all of its activities.
+    fn liquidate(&mut self, business_id: usize) {
+        for owned_building in self
+            .cities
+            .iter_mut()
+            .flat_map(|city| city.owned_buildings.iter_mut().flatten())
+            .filter(|owned_building| owned_building.owner_id == Some(business_id))
+        {
+            owned_building.release();
+        }
+        for connection_id in 0..self.connections.len() {
+            if self.connections[connection_id].owner_id == Some(business_id) {
+                self.release_connection(connection_id);
+            }
+        }
+        self.cancel_trades(business_id);
+        let business = &mut self.businesses[business_id];
+        business.toll_connection_ids.clear();
+        business.shipments.clear();
+        business.inventories.clear();
+        business.throttles.clear();
+    }
+}
+
+/// Eliminates businesses that end a round with negative cash.
+pub fn eliminate_bankrupt(game: &mut Game) {
+    for business_id in 0..game.graph.businesses.len() {
+        let business = &mut game.graph.businesses[business_id];
+        if !business.eliminated && business.money < 0 {
+            business.eliminated = true;
+            game.graph.liquidate(business_id);
+        }
+    }
+}
+
+/// Ends the game once a business meets the win condition or is the last one standing.
+pub fn check_win_condition(game: &mut Game) {
+    if game.outcome.is_some() {
+        return;
+    }
+    let scores: Vec<Score> = game
+        .graph
+        .get_scoreboard(&game.registry, game.round)
+        .into_iter()
+        .filter(|score| !game.graph.businesses[score.business_id].eliminated)
+        .collect();
+    let leader_id_opt = scores.first().map(|score| score.business_id);
+    let game_over = scores.len() <= 1
+        || match game.win_condition {
+            WinCondition::Sandbox => false,
+            WinCondition::NetWorth { target } => scores[0].get_net_worth() >= target,
+            WinCondition::HighestNetWorth { rounds } => game.round >= rounds,
+            WinCondition::MapShare { percent } => scores
+                .iter()
+                .any(|score| game.graph.get_map_share(score.business_id) >= percent as f32),
+        };
+    if !game_over {
+        return;
+    }
+    let winner_id = match game.win_condition {
+        WinCondition::MapShare { .. } if scores.len() > 1 => {
+            scores.iter().map(|score| score.business_id).max_by(|a, b| {
+                game.graph
+                    .get_map_share(*a)
+                    .total_cmp(&game.graph.get_map_share(*b))
+            })
+        }
+        _ => leader_id_opt,
+    };
+    game.outcome = Some(Outcome {
+        winner_id,
+        round: game.round,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{game::Command, game::GameError, testing};
+
+    /// Player 0 can own the market and sawmill in city 0 and the connection to city 1.
+    fn new_game(win_condition: WinCondition) -> Game {
+        let mut game = testing::new_game_on_map(
+            &[(0.0, 0.0, &["Market", "Sawmill"]), (200.0, 0.0, &[])],
+            &[(0, 1)],
+        );
+        game.win_condition = win_condition;
+        game
+    }
+
+    fn end_round(game: &mut Game) {
+        for _ in 0..game.graph.businesses.len() {
+            game.execute(Command::EndTurn).unwrap();
+        }
+    }
+
+    #[test]
+    fn businesses_in_debt_are_eliminated_and_liquidated() {
+        let mut game = new_game(WinCondition::Sandbox);
+        testing::own_building(&mut game, 0, 0, 1);
+        testing::own_connection(&mut game, 0, 0);
+        testing::give(&mut game, 0, 0, &[(testing::material("Log"), 3)]);
+        game.graph.businesses[0].money = 0;
+        end_round(&mut game);
+
+        let business = &game.graph.businesses[0];
+        assert!(business.eliminated);
+        assert!(business.inventories.is_empty());
+        assert_eq!(game.graph.cities[0].get_building(1).unwrap().owner_id, None);
+        assert_eq!(game.graph.connections[0].owner_id, None);
+        assert_eq!(
+            game.outcome,
+            Some(Outcome {
+                winner_id: Some(1),
+                round: 1,
+            })
+        );
+        assert_eq!(game.execute(Command::EndTurn), Err(GameError::GameOver));
+    }
+
+    #[test]
+    fn reaching_the_net_worth_target_wins() {
+        let mut game = new_game(WinCondition::NetWorth { target: 300 });
+        end_round(&mut game);
+        assert_eq!(game.outcome, None);
+        game.graph.businesses[1].money = 300;
+        end_round(&mut game);
+        assert_eq!(
+            game.outcome,
+            Some(Outcome {
+                winner_id: Some(1),
+                round: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn the_richest_business_wins_after_the_last_round() {
+        let mut game = new_game(WinCondition::HighestNetWorth { rounds: 2 });
+        game.graph.businesses[0].money = 260;
+        end_round(&mut game);
+        assert_eq!(game.outcome, None);
+        end_round(&mut game);
+        assert_eq!(
+            game.outcome,
+            Some(Outcome {
+                winner_id: Some(0),
+                round: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn owning_the_map_share_wins_over_net_worth() {
+        let mut game = new_game(WinCondition::MapShare { percent: 60 });
+        testing::own_building(&mut game, 1, 0, 0);
+        end_round(&mut game);
+        assert_eq!(game.outcome, None);
+        testing::own_connection(&mut game, 1, 0);
+        game.graph.businesses[0].money = 1000;
+        end_round(&mut game);
+        assert_eq!(
+            game.outcome,
+            Some(Outcome {
+                winner_id: Some(1),
+                round: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn scores_count_assets_at_book_value() {
+        let mut game = new_game(WinCondition::Sandbox);
+        game.execute(Command::BuyBuilding {
+            city_id: 0,
+            building_id: 1,
+        })
+        .unwrap();
+        let cost = game.graph.cities[0]
+            .get_building(1)
+            .unwrap()
+            .acquisition_cost;
+        let score = game.graph.get_score(&game.registry, 0, game.round);
+        assert_eq!(score.cash, 250 - cost);
+        assert_eq!(score.buildings_value, cost);
+        assert_eq!(score.get_net_worth(), 250);
+
+        let later = game.graph.get_score(&game.registry, 0, game.round + 10);
+        assert_eq!(
+            later.buildings_value,
+            valuation::get_book_value(cost, game.round, game.round + 10)
+        );
+        assert!(later.buildings_value < cost);
+    }
+}
@@ -1,130 +1,146 @@
 use macroquad::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-mod backend;
+mod menu;
 mod ui;
-use backend::{BuildingType, Business, City, Graph, Material, OwnedConnection};
+use logistics_game::backend::{
+    ai::{self, Controller},
+    game::{Command, GameError},
+    planner::Target,
+    registry::{BuildingTypeDef, MaterialId, Registry},
+    resolution::ActivityId,
+    roster::{PlayerSetup, MAX_PLAYERS, MIN_PLAYERS},
+    sale::AssetId,
+    save::{SaveStore, AUTOSAVE_SLOT},
+    setup::GameSetup,
+    stats::Metric,
+    victory::Outcome,
+    Graph,
+};
 use ui::*;
-extern crate rand;
-use rand::Rng;
 
 enum Asset {
     Building((usize, usize)),
     Connection(usize),
+    City(usize),
 }
 
+/// Panels drawn over the middle of the map, one at a time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Overlay {
+    Trades,
+    Ledger,
+    Stats,
+    Planner,
+}
+
+enum SlotMenu {
+    /// The name being typed and the existing saves.
+    Save(String, Vec<String>),
+    Load(Vec<String>),
+}
+
+const MAX_SLOT_NAME_LENGTH: usize = 24;
+const MAP_ORIGIN: Vec2 = Vec2::new(200.0, 60.0);
+
 struct AssetUI {
     pub asset: Asset,
     pub position: Vec2,
     pub size: Option<Vec2>,
 }
 
-fn get_player_color(player_id: usize) -> Color {
-    match player_id {
-        0 => BLUE,
-        1 => GREEN,
-        2 => YELLOW,
-        _ => todo!(),
+/// Textures of every material in the registry. Missing textures are left out and drawn as the
+/// placeholder.
+async fn load_textures(registry: &Registry) -> HashMap<String, Texture2D> {
+    let mut textures: HashMap<String, Texture2D> = HashMap::from([(
+        PLACEHOLDER_TEXTURE.to_string(),
+        Texture2D::from_rgba8(1, 1, &[255, 0, 255, 255]),
+    )]);
+    let texture_ids = registry
+        .materials
+        .values()
+        .map(|material| material.texture.clone())
+        .chain(["right_arrow".to_string()]);
+    for texture_id in texture_ids {
+        if let Ok(texture) =
+            load_texture(format!("assets/textures/{}.png", texture_id).as_str()).await
+        {
+            textures.insert(texture_id, texture);
+        }
     }
+    textures
+}
+
+fn get_player_color(graph: &Graph, player_id: usize) -> Color {
+    let [r, g, b] = graph.businesses[player_id].color;
+    Color::from_rgba(r, g, b, 255)
 }
 
 #[macroquad::main("logistics-game")]
 async fn main() {
-    let mut rng = rand::thread_rng();
-    let mut graph = Graph {
-        cities: vec![
-            City::new_with_random_buildings(&mut rng, 200.0, 60.0),
-            City::new_with_random_buildings(&mut rng, 400.0, 60.0),
-            City::new_with_random_buildings(&mut rng, 600.0, 60.0),
-            City::new_with_random_buildings(&mut rng, 800.0, 60.0),
-            City::new_with_random_buildings(&mut rng, 1000.0, 60.0),
-            City::new_with_random_buildings(&mut rng, 300.0, 220.0),
-            City::new_with_random_buildings(&mut rng, 500.0, 220.0),
-            City::new_with_random_buildings(&mut rng, 700.0, 220.0),
-            City::new_with_random_buildings(&mut rng, 900.0, 220.0),
-            City::new_with_random_buildings(&mut rng, 1100.0, 220.0),
-            City::new_with_random_buildings(&mut rng, 200.0, 380.0),
-            City::new_with_random_buildings(&mut rng, 400.0, 380.0),
-            City::new_with_random_buildings(&mut rng, 600.0, 380.0),
-            City::new_with_random_buildings(&mut rng, 800.0, 380.0),
-            City::new_with_random_buildings(&mut rng, 1000.0, 380.0),
-            City::new_with_random_buildings(&mut rng, 300.0, 540.0),
-            City::new_with_random_buildings(&mut rng, 500.0, 540.0),
-            City::new_with_random_buildings(&mut rng, 700.0, 540.0),
-            City::new_with_random_buildings(&mut rng, 900.0, 540.0),
-            City::new_with_random_buildings(&mut rng, 1100.0, 540.0),
-        ],
-        connections: vec![
-            OwnedConnection::new(0, 1),
-            OwnedConnection::new(0, 5),
-            OwnedConnection::new(0, 10),
-            OwnedConnection::new(1, 2),
-            OwnedConnection::new(1, 6),
-            OwnedConnection::new(3, 4),
-            OwnedConnection::new(3, 7),
-            OwnedConnection::new(4, 8),
-            OwnedConnection::new(4, 9),
-            OwnedConnection::new(5, 11),
-            OwnedConnection::new(6, 7),
-            OwnedConnection::new(6, 12),
-            OwnedConnection::new(6, 16),
-            OwnedConnection::new(7, 8),
-            OwnedConnection::new(7, 12),
-            OwnedConnection::new(7, 13),
-            OwnedConnection::new(8, 9),
-            OwnedConnection::new(8, 13),
-            OwnedConnection::new(9, 19),
-            OwnedConnection::new(10, 15),
-            OwnedConnection::new(11, 16),
-            OwnedConnection::new(12, 16),
-            OwnedConnection::new(12, 17),
-            OwnedConnection::new(13, 14),
-            OwnedConnection::new(13, 17),
-            OwnedConnection::new(14, 18),
-            OwnedConnection::new(14, 19),
-            OwnedConnection::new(15, 16),
-            OwnedConnection::new(17, 18),
-            OwnedConnection::new(18, 19),
-        ],
-        businesses: vec![Business::new(), Business::new()],
+    let registry =
+        Registry::load("assets/data/registry.toml").unwrap_or_else(|err| panic!("{}", err));
+    let mut setup = GameSetup {
+        seed: ::rand::random(),
+        ..Default::default()
     };
+    if let Some(seed) = std::env::args()
+        .skip_while(|arg| arg != "--seed")
+        .nth(1)
+        .and_then(|seed| seed.parse().ok())
+    {
+        setup.seed = seed;
+    }
+    if let Some(players) = std::env::args().skip_while(|arg| arg != "--players").nth(1) {
+        setup.players = players
+            .split(',')
+            .take(MAX_PLAYERS)
+            .enumerate()
+            .map(|(player_id, controller)| {
+                PlayerSetup::new(
+                    player_id,
+                    controller.parse().unwrap_or_else(|err| panic!("{}", err)),
+                )
+            })
+            .collect();
+        while setup.players.len() < MIN_PLAYERS {
+            setup
+                .players
+                .push(PlayerSetup::new(setup.players.len(), Controller::Human));
+        }
+    }
+    let save_store = SaveStore::new("saves");
 
-    let mut current_player_id = 0;
+    request_new_screen_size(1920.0, 1080.0);
+    let mut game = menu::run_start_menu(setup, &registry, &save_store).await;
 
     let mut open_asset_ui_opt: Option<AssetUI> = None;
+    let mut slot_menu_opt: Option<SlotMenu> = None;
+    let mut status_message_opt: Option<String> = None;
+    let mut shipping_opt: Option<(MaterialId, usize)> = None;
+    let mut connecting_opt: Option<usize> = None;
+    let mut show_scoreboard = false;
+    let mut overlay_opt: Option<Overlay> = None;
+    let mut ledger_round_opt: Option<u32> = None;
+    let mut stats_metric_index = 0;
+    let mut plan_target_opt: Option<Target> = None;
+    let mut trade_draft_opt: Option<TradeDraft> = None;
+    let mut overlay_rect_opt: Option<Rect> = None;
+    let mut construction_type_index = 0;
 
-    let mut textures: HashMap<String, Texture2D> = HashMap::new();
-    for texture_id in vec![
-        "chip",
-        "gold",
-        "wire",
-        "computer",
-        "logs",
-        "planks",
-        "chair",
-        "right_arrow",
-        "money",
-        "rocks",
-        "sand",
-        "energy",
-        "raw_oil",
-        "oil",
-        "glass",
-        "plastic",
-    ] {
-        textures.insert(
-            texture_id.to_string(),
-            load_texture(format!("assets/textures/{}.png", texture_id).as_str())
-                .await
-                .unwrap(),
-        );
-    }
-
-    request_new_screen_size(1920.0, 1080.0);
+    let mut textures = load_textures(&game.registry).await;
 
     loop {
         clear_background(BLACK);
 
+        let game_over = game.outcome.is_some();
+        if let (Controller::Ai(difficulty), false) = (game.get_current_controller(), game_over) {
+            ai::play_turn(&mut game, difficulty);
+            if let Err(err) = save_store.save(&game, AUTOSAVE_SLOT) {
+                status_message_opt = Some(err.to_string());
+            }
+        }
+
         let mouse_button_pressed = is_mouse_button_pressed(MouseButton::Left);
         let (mouse_x, mouse_y) = mouse_position();
         let mouse_pos = Vec2::new(mouse_x, mouse_y);
@@ -133,32 +149,22 @@ async fn main() {
             relative_mouse_pos.cmpgt(Vec2::ZERO).all()
                 && relative_mouse_pos.cmplt(ui.size.unwrap()).all()
         });
+        let cursor_inside_overlay =
+            overlay_opt.is_some() && overlay_rect_opt.is_some_and(|rect| rect.contains(mouse_pos));
 
-        let owns_nothing = graph
-            .connections
-            .iter()
-            .all(|owned_connection| owned_connection.owner_id != Some(current_player_id))
-            && graph
-                .cities
-                .iter()
-                .flat_map(|city| city.owned_buildings.iter())
-                .all(|owned_building| owned_building.owner_id != Some(current_player_id));
+        let current_player_id = game.current_player_id;
+        let mut command_opt: Option<Command> = None;
+        let mut clicked_city_opt: Option<(usize, Vec2)> = None;
 
         let mut city_positions = HashMap::<usize, (f32, f32)>::new();
-        for (city_id, city) in graph.cities.iter().enumerate() {
-            city_positions.insert(city_id, (city.x, city.y));
+        for (city_id, city) in game.graph.cities.iter().enumerate() {
+            city_positions.insert(city_id, (city.x + MAP_ORIGIN.x, city.y + MAP_ORIGIN.y));
         }
-        for (connection_id, owned_connection) in graph.connections.iter().enumerate() {
+        for (connection_id, owned_connection) in game.graph.connections.iter().enumerate() {
             let connection_width = 10.0;
             assert!(owned_connection.city_ids.len() == 2);
-            let (start_x, start_y) = city_positions
-                .get(owned_connection.city_ids.get(0).unwrap())
-                .unwrap()
-                .clone();
-            let (end_x, end_y) = city_positions
-                .get(owned_connection.city_ids.get(1).unwrap())
-                .unwrap()
-                .clone();
+            let (start_x, start_y) = city_positions[&owned_connection.city_ids[0]];
+            let (end_x, end_y) = city_positions[&owned_connection.city_ids[1]];
             let v = Vec2::new(end_x - start_x, end_y - start_y);
             let angle = v.angle_between(Vec2::new(0.0, 1.0));
             draw_rectangle_ex(
@@ -169,14 +175,21 @@ async fn main() {
                 DrawRectangleParams {
                     rotation: -angle,
                     offset: Vec2::new(0.5, 0.0),
-                    color: owned_connection.owner_id.map_or(GRAY, get_player_color),
-                    ..Default::default()
+                    color: owned_connection.owner_id.map_or(GRAY, |owner_id| {
+                        let color = get_player_color(&game.graph, owner_id);
+                        if owned_connection.is_under_construction() {
+                            Color { a: 0.4, ..color }
+                        } else {
+                            color
+                        }
+                    }),
                 },
             );
 
             let local_mouse_pos =
                 Vec2::from_angle(angle).rotate(mouse_pos - Vec2::new(start_x, start_y));
             if !cursor_inside_asset_ui
+                && !cursor_inside_overlay
                 && local_mouse_pos.x.abs() < connection_width / 2.0
                 && local_mouse_pos.y > 0.0
                 && local_mouse_pos.y < v.length()
@@ -189,13 +202,17 @@ async fn main() {
             }
         }
 
-        for (city_id, city) in graph.cities.iter().enumerate() {
+        for (city_id, city) in game.graph.cities.iter().enumerate() {
             let city_radius = 50.0;
             let building_radius = 10.0;
+            let city_pos = Vec2::new(city.x, city.y) + MAP_ORIGIN;
+            if (mouse_pos - city_pos).length() < city_radius {
+                clicked_city_opt = Some((city_id, city_pos + Vec2::new(city_radius, -city_radius)));
+            }
 
             draw_hexagon(
-                city.x,
-                city.y,
+                city_pos.x,
+                city_pos.y,
                 city_radius,
                 0.0,
                 true,
@@ -208,37 +225,39 @@ async fn main() {
                 },
             );
 
-            for (building_id, owned_building) in city.owned_buildings.iter().enumerate() {
+            for (building_id, owned_building) in city.get_buildings() {
                 let rad = 2.0 * std::f32::consts::PI * (building_id as f32 / 6.0);
                 let building_pos = Vec2::new(
-                    0.5 * city_radius * f32::sin(rad) + city.x,
-                    0.5 * city_radius * f32::cos(rad) + city.y,
+                    0.5 * city_radius * f32::sin(rad) + city_pos.x,
+                    0.5 * city_radius * f32::cos(rad) + city_pos.y,
                 );
                 draw_circle(
                     building_pos.x,
                     building_pos.y,
                     building_radius,
-                    owned_building.owner_id.map_or(GRAY, get_player_color),
+                    owned_building
+                        .owner_id
+                        .map_or(GRAY, |owner_id| get_player_color(&game.graph, owner_id)),
                 );
-                let building_marker =
-                    if owned_building
-                        .production_scale
-                        .iter()
-                        .any(|scaled_valid_recipe| {
-                            scaled_valid_recipe
-                                .valid_recipe
-                                .get_recipe()
-                                .materials
-                                .iter()
-                                .any(|(material, _)| material == &Material::Money)
-                        })
-                    {
-                        "M"
-                    } else {
-                        ""
-                    };
+                let building_marker = if owned_building.is_under_construction() {
+                    owned_building.construction_rounds.to_string()
+                } else if owned_building
+                    .production_scale
+                    .iter()
+                    .any(|scaled_valid_recipe| {
+                        owned_building
+                            .get_recipe(&game.registry, &scaled_valid_recipe.valid_recipe)
+                            .materials
+                            .iter()
+                            .any(|(material, _)| material.is_money())
+                    })
+                {
+                    "M".to_string()
+                } else {
+                    String::new()
+                };
                 draw_text(
-                    building_marker,
+                    building_marker.as_str(),
                     building_pos.x - 0.5 * building_radius,
                     building_pos.y + 0.5 * building_radius,
                     24.0,
@@ -246,6 +265,7 @@ async fn main() {
                 );
                 if !cursor_inside_asset_ui && (mouse_pos - building_pos).length() < building_radius
                 {
+                    clicked_city_opt = None;
                     open_asset_ui_opt = Some(AssetUI {
                         asset: Asset::Building((city_id, building_id)),
                         position: building_pos,
@@ -257,11 +277,34 @@ async fn main() {
 
         let x_ = MARGIN;
         let mut y_ = MARGIN;
-        draw_button(x_, y_, 100.0, MARGIN, get_player_color(current_player_id));
+        draw_button(
+            x_,
+            y_,
+            100.0,
+            MARGIN,
+            get_player_color(&game.graph, current_player_id),
+        );
+        draw_text(
+            format!(
+                "{} ({})",
+                game.graph.businesses[current_player_id].name,
+                game.get_current_controller()
+            )
+            .as_str(),
+            x_ + 100.0 + MARGIN,
+            y_ + MARGIN,
+            24.0,
+            WHITE,
+        );
         y_ += MARGIN + MARGIN;
-        for (material, quantity_info) in graph.get_resource_stock(current_player_id) {
+        let fixed_costs = game.graph.get_upkeep(&game.registry, current_player_id)
+            - game.graph.get_toll_balance(current_player_id);
+        for (material, quantity_info) in
+            game.graph
+                .get_resource_stock(&game.registry, current_player_id, None)
+        {
             draw_texture_ex(
-                textures.get(&material.get_texture_id()).unwrap(),
+                get_texture(&textures, game.registry.get_texture_id(&material)),
                 x_,
                 y_,
                 WHITE,
@@ -271,13 +314,13 @@ async fn main() {
                 },
             );
 
+            let net_in = if material.is_money() {
+                quantity_info.net_in() - fixed_costs
+            } else {
+                quantity_info.net_in()
+            };
             draw_text(
-                format!(
-                    "{}({:+})",
-                    quantity_info.quantity,
-                    quantity_info.gross_in - quantity_info.gross_out,
-                )
-                .as_str(),
+                format!("{}({:+})", quantity_info.quantity, net_in).as_str(),
                 x_ + ICON_SIZE + MARGIN,
                 y_ + ICON_SIZE / 2.0,
                 24.0,
@@ -285,174 +328,809 @@ async fn main() {
             );
             y_ += ICON_SIZE + MARGIN;
         }
-        if ButtonState::Pressed == draw_next_turn_button(screen_width() - 200.0, MARGIN).0 {
-            current_player_id += 1;
-            if graph.businesses.get(current_player_id).is_none() {
-                current_player_id = 0;
+        draw_throttle_report(x_, y_, &game.graph, current_player_id);
+        let x_ = screen_width() - 200.0;
+        let (next_turn_state, size) = draw_next_turn_button(x_, MARGIN);
+        if next_turn_state == ButtonState::Pressed {
+            command_opt = Some(Command::EndTurn);
+        }
+        let mut y_ = MARGIN + size.y;
+        let (save_state, size) = draw_text_button(x_, y_, "Save");
+        y_ += size.y;
+        let (load_state, size) = draw_text_button(x_, y_, "Load");
+        y_ += size.y;
+        let (scores_state, size) = draw_text_button(x_, y_, "Scores");
+        y_ += size.y;
+        let received_offers = game
+            .graph
+            .offers
+            .iter()
+            .filter(|offer| offer.to_id == current_player_id)
+            .count();
+        let trades_label = match received_offers {
+            0 => "Trades".to_string(),
+            count => format!("Trades ({})", count),
+        };
+        let (trades_state, size) = draw_text_button(x_, y_, trades_label.as_str());
+        y_ += size.y;
+        let (ledger_state, size) = draw_text_button(x_, y_, "Ledger");
+        y_ += size.y;
+        let (stats_state, size) = draw_text_button(x_, y_, "Stats");
+        y_ += size.y;
+        let (planner_state, size) = draw_text_button(x_, y_, "Planner");
+        y_ += size.y + MARGIN;
+        if scores_state == ButtonState::Pressed {
+            show_scoreboard = !show_scoreboard;
+        }
+        for (state, overlay) in [
+            (trades_state, Overlay::Trades),
+            (ledger_state, Overlay::Ledger),
+            (stats_state, Overlay::Stats),
+            (planner_state, Overlay::Planner),
+        ] {
+            if state == ButtonState::Pressed {
+                overlay_opt = (overlay_opt != Some(overlay)).then_some(overlay);
+            }
+        }
+        if save_state == ButtonState::Pressed {
+            while get_char_pressed().is_some() {}
+            slot_menu_opt = match save_store.list_slots() {
+                Ok(slots) => Some(SlotMenu::Save(String::new(), slots)),
+                Err(err) => {
+                    status_message_opt = Some(err.to_string());
+                    None
+                }
+            };
+        }
+        if load_state == ButtonState::Pressed {
+            slot_menu_opt = match save_store.list_slots() {
+                Ok(slots) => Some(SlotMenu::Load(slots)),
+                Err(err) => {
+                    status_message_opt = Some(err.to_string());
+                    None
+                }
+            };
+        }
+        match &mut slot_menu_opt {
+            Some(SlotMenu::Save(name, slots)) => {
+                while let Some(character) = get_char_pressed() {
+                    if (character.is_ascii_alphanumeric() || character == '_' || character == '-')
+                        && name.len() < MAX_SLOT_NAME_LENGTH
+                    {
+                        name.push(character);
+                    }
+                }
+                if is_key_pressed(KeyCode::Backspace) {
+                    name.pop();
+                }
+                let (confirmed, _) = draw_save_menu(x_, y_, name, slots);
+                if confirmed || is_key_pressed(KeyCode::Enter) {
+                    status_message_opt = Some(match save_store.save(&game, name) {
+                        Ok(()) => format!("Saved to {}", name),
+                        Err(err) => err.to_string(),
+                    });
+                    slot_menu_opt = None;
+                }
+            }
+            Some(SlotMenu::Load(slots)) => {
+                if let (Some(slot_index), _) = draw_slot_menu(x_, y_, slots) {
+                    status_message_opt = Some(match save_store.load(&slots[slot_index]) {
+                        Ok(loaded_game) => {
+                            game = loaded_game;
+                            textures = load_textures(&game.registry).await;
+                            open_asset_ui_opt = None;
+                            format!("Loaded {}", slots[slot_index])
+                        }
+                        Err(err) => err.to_string(),
+                    });
+                    slot_menu_opt = None;
+                }
+            }
+            None => {}
+        }
+        draw_text(
+            game.win_condition.to_string().as_str(),
+            screen_width() - 400.0,
+            screen_height() - 2.0 * MARGIN - 64.0,
+            32.0,
+            WHITE,
+        );
+        draw_text(
+            format!("Round: {}", game.round).as_str(),
+            screen_width() - 400.0,
+            screen_height() - 2.0 * MARGIN - 32.0,
+            32.0,
+            WHITE,
+        );
+        draw_text(
+            format!("Seed: {}", game.seed).as_str(),
+            screen_width() - 400.0,
+            screen_height() - 2.0 * MARGIN,
+            32.0,
+            WHITE,
+        );
+        if let Some(status_message) = &status_message_opt {
+            draw_message_box_ui(MARGIN, screen_height() - 60.0, status_message);
+        }
+        if show_scoreboard || game_over {
+            let title = match game.outcome {
+                Some(Outcome {
+                    winner_id: Some(winner_id),
+                    round,
+                }) => format!(
+                    "{} wins in round {}",
+                    game.graph.businesses[winner_id].name, round
+                ),
+                Some(Outcome {
+                    winner_id: None,
+                    round,
+                }) => format!("Everyone went bankrupt by round {}", round),
+                None => format!("Round {}", game.round),
+            };
+            let scores = game.graph.get_scoreboard(&game.registry, game.round);
+            draw_scoreboard(
+                screen_width() / 2.0 - 460.0,
+                screen_height() / 2.0 - 200.0,
+                title.as_str(),
+                &game.graph,
+                &scores,
+            );
+        }
+        let overlay_position = Vec2::new(screen_width() / 2.0 - 500.0, MAP_ORIGIN.y);
+        if overlay_opt == Some(Overlay::Ledger) {
+            let rounds: Vec<u32> = game.graph.businesses[current_player_id]
+                .ledger
+                .rounds
+                .keys()
+                .copied()
+                .collect();
+            let round_index_opt = ledger_round_opt
+                .and_then(|round| rounds.iter().position(|r| *r == round))
+                .or(rounds.len().checked_sub(1));
+            let (step, size) = draw_ledger_panel(
+                overlay_position.x,
+                overlay_position.y,
+                &game.graph,
+                &game.registry,
+                current_player_id,
+                round_index_opt.map(|round_index| rounds[round_index]),
+            );
+            overlay_rect_opt = Some(Rect::new(
+                overlay_position.x,
+                overlay_position.y,
+                size.x,
+                size.y,
+            ));
+            if let (Some(round_index), true) = (round_index_opt, step != 0) {
+                let round_index = round_index.saturating_add_signed(step as isize);
+                ledger_round_opt = rounds.get(round_index).copied().or(ledger_round_opt);
+            }
+        }
+        if overlay_opt == Some(Overlay::Stats) {
+            let metrics = Metric::all(&game.registry);
+            let (step, size) = draw_stats_panel(
+                overlay_position.x,
+                overlay_position.y,
+                &game.graph,
+                &metrics[stats_metric_index],
+            );
+            overlay_rect_opt = Some(Rect::new(
+                overlay_position.x,
+                overlay_position.y,
+                size.x,
+                size.y,
+            ));
+            stats_metric_index =
+                (stats_metric_index as i32 + step).rem_euclid(metrics.len() as i32) as usize;
+        }
+        if overlay_opt == Some(Overlay::Planner) {
+            let target = plan_target_opt.get_or_insert_with(|| Target::new(&game.registry));
+            let plan = game
+                .graph
+                .plan_production(&game.registry, current_player_id, target);
+            let size = draw_planner_panel(
+                overlay_position.x,
+                overlay_position.y,
+                &game.registry,
+                target,
+                &plan,
+            );
+            overlay_rect_opt = Some(Rect::new(
+                overlay_position.x,
+                overlay_position.y,
+                size.x,
+                size.y,
+            ));
+        }
+        if overlay_opt == Some(Overlay::Trades) && !game_over {
+            let (action_opt, size) = draw_trade_panel(
+                overlay_position.x,
+                overlay_position.y,
+                &game.graph,
+                &game.registry,
+                current_player_id,
+                trade_draft_opt.as_mut(),
+            );
+            overlay_rect_opt = Some(Rect::new(
+                overlay_position.x,
+                overlay_position.y,
+                size.x,
+                size.y,
+            ));
+            match action_opt {
+                Some(TradePanelAction::Accept(offer_id)) => {
+                    // Offer ids shift once this one is gone.
+                    trade_draft_opt = trade_draft_opt.filter(|draft| draft.offer_id.is_none());
+                    command_opt = Some(Command::AcceptOffer { offer_id });
+                }
+                Some(TradePanelAction::Counter(offer_id)) => {
+                    let offer = &game.graph.offers[offer_id];
+                    trade_draft_opt = Some(TradeDraft::new(
+                        Some(offer_id),
+                        offer.from_id,
+                        &offer.terms.reversed(),
+                    ));
+                }
+                Some(TradePanelAction::Reject(offer_id)) => {
+                    trade_draft_opt = trade_draft_opt.filter(|draft| draft.offer_id.is_none());
+                    command_opt = Some(Command::RejectOffer { offer_id });
+                }
+                Some(TradePanelAction::NewOffer) => {
+                    let to_id = (0..game.graph.businesses.len())
+                        .find(|id| {
+                            *id != current_player_id && !game.graph.businesses[*id].eliminated
+                        })
+                        .unwrap_or(current_player_id);
+                    let city_id = game
+                        .graph
+                        .get_network_city_ids(current_player_id)
+                        .into_iter()
+                        .next()
+                        .unwrap_or(0);
+                    trade_draft_opt = Some(TradeDraft {
+                        offer_id: None,
+                        to_id,
+                        city_id,
+                        give: (MaterialId::money(), 0),
+                        take: (MaterialId::money(), 0),
+                        rounds: 0,
+                        penalty: 0,
+                    });
+                }
+                Some(TradePanelAction::Send) => {
+                    let draft = trade_draft_opt.as_ref().unwrap();
+                    let terms = draft.get_terms();
+                    command_opt = Some(match draft.offer_id {
+                        Some(offer_id) => Command::CounterOffer { offer_id, terms },
+                        None => Command::ProposeTrade {
+                            to_id: draft.to_id,
+                            terms,
+                        },
+                    });
+                }
+                Some(TradePanelAction::Cancel) => trade_draft_opt = None,
+                None => {}
             }
-            graph.update_business_resources(current_player_id);
         }
 
-        let resource_stock = graph.get_resource_stock(current_player_id);
         if let Some(open_asset_ui) = open_asset_ui_opt.as_mut() {
+            let x = open_asset_ui.position.x;
+            let y = open_asset_ui.position.y;
             open_asset_ui.size = Some(match open_asset_ui.asset {
                 Asset::Building((city_id, building_id)) => {
-                    let owns_building_in_city = graph
-                        .cities
-                        .get(city_id)
-                        .unwrap()
-                        .owned_buildings
-                        .iter()
-                        .any(|owned_building| owned_building.owner_id == Some(current_player_id));
-                    let building = graph
-                        .cities
-                        .get_mut(city_id)
-                        .unwrap()
-                        .owned_buildings
-                        .get_mut(building_id)
+                    let building = game.graph.cities[city_id]
+                        .get_building(building_id)
                         .unwrap();
-
-                    let x = open_asset_ui.position.x;
-                    let y = open_asset_ui.position.y;
-
                     match building.owner_id {
-                        Some(id) if id == current_player_id => {
-                            draw_recipes_panel(x, y, building, &resource_stock, &textures, true)
+                        Some(id) if id == current_player_id && building.is_under_construction() => {
+                            let size = draw_message_box_ui(
+                                x,
+                                y,
+                                format!(
+                                    "Under construction: {} rounds left",
+                                    building.construction_rounds
+                                )
+                                .as_str(),
+                            );
+                            let (sale_action_opt, sale_size) = draw_sale_ui(
+                                x,
+                                y + size.y,
+                                None,
+                                true,
+                                game.graph.splits_network(
+                                    current_player_id,
+                                    AssetId::Building {
+                                        city_id,
+                                        building_id,
+                                    },
+                                ),
+                            );
+                            if let Some(SaleAction::Demolish) = sale_action_opt {
+                                command_opt = Some(Command::DemolishBuilding {
+                                    city_id,
+                                    building_id,
+                                });
+                            }
+                            Vec2::new(size.x.max(sale_size.x), size.y + sale_size.y)
                         }
-                        Some(other_id) => {
-                            draw_message_box_ui(x, y, format!("Owned by {}", other_id).as_str())
+                        Some(id) if id == current_player_id => {
+                            let throttles = game.graph.businesses[current_player_id]
+                                .throttles
+                                .iter()
+                                .filter_map(|throttle| match throttle.id {
+                                    ActivityId::Recipe {
+                                        city_id: throttle_city_id,
+                                        building_id: throttle_building_id,
+                                        recipe_id,
+                                    } if (throttle_city_id, throttle_building_id)
+                                        == (city_id, building_id) =>
+                                    {
+                                        Some((recipe_id, throttle))
+                                    }
+                                    _ => None,
+                                })
+                                .collect();
+                            let upkeep_size = draw_message_box_ui(
+                                x,
+                                y,
+                                format!(
+                                    "Upkeep: {}$/round\n{}",
+                                    building.get_upkeep(&game.registry),
+                                    format_asset_return(&game.graph.get_asset_return(
+                                        &game.registry,
+                                        current_player_id,
+                                        AssetId::Building {
+                                            city_id,
+                                            building_id,
+                                        },
+                                        game.round,
+                                    ))
+                                )
+                                .as_str(),
+                            );
+                            let (upgrade_ui_state, upgrade_size) = draw_upgrade_ui(
+                                x,
+                                y + upkeep_size.y,
+                                building.level,
+                                building.get_next_upgrade(&game.registry),
+                            );
+                            if upgrade_ui_state == ButtonState::Pressed {
+                                command_opt = Some(Command::UpgradeBuilding {
+                                    city_id,
+                                    building_id,
+                                });
+                            }
+                            let (sale_action_opt, sale_size) = draw_sale_ui(
+                                x,
+                                y + upkeep_size.y + upgrade_size.y,
+                                game.check_sell_building(city_id, building_id).ok(),
+                                true,
+                                game.graph.splits_network(
+                                    current_player_id,
+                                    AssetId::Building {
+                                        city_id,
+                                        building_id,
+                                    },
+                                ),
+                            );
+                            match sale_action_opt {
+                                Some(SaleAction::Sell) => {
+                                    command_opt = Some(Command::SellBuilding {
+                                        city_id,
+                                        building_id,
+                                    });
+                                }
+                                Some(SaleAction::Demolish) => {
+                                    command_opt = Some(Command::DemolishBuilding {
+                                        city_id,
+                                        building_id,
+                                    });
+                                }
+                                None => {}
+                            }
+                            let header_height = upkeep_size.y + upgrade_size.y + sale_size.y;
+                            let (size, action_opt) = draw_recipes_panel(
+                                x,
+                                y + header_height,
+                                building,
+                                &throttles,
+                                &game.registry,
+                                &textures,
+                                true,
+                            );
+                            match action_opt {
+                                Some(RecipesPanelAction::SetScale { recipe_id, scale }) => {
+                                    command_opt = Some(Command::SetRecipeScale {
+                                        city_id,
+                                        building_id,
+                                        recipe_id,
+                                        scale,
+                                    });
+                                }
+                                Some(RecipesPanelAction::SetPriority {
+                                    recipe_id,
+                                    priority,
+                                }) => {
+                                    command_opt = Some(Command::SetRecipePriority {
+                                        city_id,
+                                        building_id,
+                                        recipe_id,
+                                        priority,
+                                    });
+                                }
+                                None => {}
+                            }
+                            size + Vec2::new(0.0, header_height)
                         }
+                        Some(other_id) => draw_message_box_ui(
+                            x,
+                            y,
+                            format!("Owned by {}", game.graph.businesses[other_id].name).as_str(),
+                        ),
                         None => {
-                            let can_buy = owns_nothing
-                                || graph.connections.iter().any(|owned_connection| {
-                                    owned_connection.owner_id == Some(current_player_id)
-                                        && owned_connection.city_ids.contains(&city_id)
-                                })
-                                || owns_building_in_city;
-
-                            match can_buy {
-                                true => {
+                            let size = match game.check_buy_building(city_id, building_id) {
+                                Err(GameError::NotConnected) => {
+                                    draw_message_box_ui(x, y, "Not connected\nto your network")
+                                }
+                                _ => {
                                     let (buy_ui_state, size) =
                                         draw_buy_ui(x, y, building.acquisition_cost);
-                                    if resource_stock.get(&Material::Money).unwrap().quantity
-                                        >= building.acquisition_cost
-                                        && buy_ui_state == ButtonState::Pressed
-                                    {
-                                        building.owner_id = Some(current_player_id);
-                                        *graph
-                                            .businesses
-                                            .get_mut(current_player_id)
-                                            .unwrap()
-                                            .resources
-                                            .get_mut(&backend::Material::Money)
-                                            .unwrap() -= building.acquisition_cost;
+                                    if buy_ui_state == ButtonState::Pressed {
+                                        command_opt = Some(Command::BuyBuilding {
+                                            city_id,
+                                            building_id,
+                                        });
                                     }
-                                    draw_recipes_panel(
-                                        x,
-                                        y + size.y,
-                                        building,
-                                        &resource_stock,
-                                        &textures,
-                                        false,
-                                    ) + Vec2::new(0.0, size.y)
+                                    let building_type_def =
+                                        &game.registry.building_types[&building.building_type];
+                                    size + Vec2::new(
+                                        0.0,
+                                        draw_message_box_ui(
+                                            x,
+                                            y + size.y,
+                                            format!(
+                                                "Upkeep: {}$/round + {}$ per scale",
+                                                building_type_def.upkeep,
+                                                building_type_def.upkeep_per_scale
+                                            )
+                                            .as_str(),
+                                        )
+                                        .y,
+                                    )
                                 }
-                                false => {
-                                    let size =
-                                        draw_message_box_ui(x, y, "Not connected\nto your network");
-
-                                    draw_recipes_panel(
-                                        x,
-                                        y + size.y,
-                                        building,
-                                        &resource_stock,
-                                        &textures,
-                                        false,
-                                    ) + Vec2::new(0.0, size.y)
-                                }
-                            }
+                            };
+                            draw_recipes_panel(
+                                x,
+                                y + size.y,
+                                building,
+                                &BTreeMap::new(),
+                                &game.registry,
+                                &textures,
+                                false,
+                            )
+                            .0 + Vec2::new(0.0, size.y)
                         }
                     }
                 }
                 Asset::Connection(connection_id) => {
-                    let owned_connection = graph.connections.get(connection_id).unwrap();
-
-                    let (start_x, start_y) = city_positions
-                        .get(owned_connection.city_ids.get(0).unwrap())
-                        .unwrap()
-                        .clone();
-                    let (end_x, end_y) = city_positions
-                        .get(owned_connection.city_ids.get(1).unwrap())
-                        .unwrap()
-                        .clone();
-                    let x = (start_x + end_x) / 2.0;
-                    let y = (start_y + end_y) / 2.0;
-
+                    let owned_connection = &game.graph.connections[connection_id];
                     match owned_connection.owner_id {
+                        Some(id)
+                            if id == current_player_id
+                                && owned_connection.is_under_construction() =>
+                        {
+                            draw_message_box_ui(
+                                x,
+                                y,
+                                format!(
+                                    "Under construction: {} rounds left",
+                                    owned_connection.construction_rounds
+                                )
+                                .as_str(),
+                            )
+                        }
                         Some(id) if id == current_player_id => {
-                            draw_message_box_ui(x, y, "Maintenance cost: 0")
+                            let size = draw_message_box_ui(
+                                x,
+                                y,
+                                format!(
+                                    "Maintenance cost: {}$/round\nLoad: {}/{}\nTransport cost: {}$/unit\n{}",
+                                    owned_connection.maintenance_cost,
+                                    game.graph.get_connection_load(connection_id),
+                                    owned_connection.capacity,
+                                    owned_connection.transport_cost,
+                                    format_asset_return(&game.graph.get_asset_return(
+                                        &game.registry,
+                                        current_player_id,
+                                        AssetId::Connection(connection_id),
+                                        game.round,
+                                    ))
+                                )
+                                .as_str(),
+                            );
+                            let (sale_action_opt, sale_size) = draw_sale_ui(
+                                x,
+                                y + size.y,
+                                game.check_sell_connection(connection_id).ok(),
+                                false,
+                                game.graph.splits_network(
+                                    current_player_id,
+                                    AssetId::Connection(connection_id),
+                                ),
+                            );
+                            if let Some(SaleAction::Sell) = sale_action_opt {
+                                command_opt = Some(Command::SellConnection { connection_id });
+                            }
+                            let (toll_step, toll_size) = draw_toll_ui(
+                                x,
+                                y + size.y + sale_size.y,
+                                owned_connection.toll,
+                                game.graph.get_toll_payer_ids(connection_id).len(),
+                            );
+                            if toll_step != 0 {
+                                let toll = match (owned_connection.toll, toll_step) {
+                                    (None, 1) => Some(0),
+                                    (Some(0), -1) | (None, _) => None,
+                                    (Some(toll), step) => Some(toll + step),
+                                };
+                                command_opt = Some(Command::SetToll {
+                                    connection_id,
+                                    toll,
+                                });
+                            }
+                            Vec2::new(size.x.max(sale_size.x), size.y + sale_size.y + toll_size.y)
                         }
                         Some(other_id) => {
-                            draw_message_box_ui(x, y, format!("Owned by {}", other_id).as_str())
-                        }
-                        None => {
-                            let can_buy =
-                                graph.connections.iter().any(|owned_connection| {
-                                    owned_connection.owner_id == Some(current_player_id)
-                                        && owned_connection.city_ids.iter().any(|city_id| {
-                                            graph
-                                                .connections
-                                                .get(connection_id)
-                                                .unwrap()
-                                                .city_ids
-                                                .contains(city_id)
-                                        })
-                                }) || graph.cities.iter().enumerate().any(|(city_it_id, city)| {
-                                    city.owned_buildings.iter().any(|owned_building| {
-                                        owned_building.owner_id == Some(current_player_id)
-                                            && owned_connection.city_ids.iter().any(|city_id| {
-                                                graph
-                                                    .connections
-                                                    .get(connection_id)
-                                                    .unwrap()
-                                                    .city_ids
-                                                    .contains(&city_it_id)
-                                            })
-                                    })
-                                });
-
-                            match can_buy {
-                                true => {
-                                    let (clicked, size) =
-                                        draw_buy_ui(x, y, owned_connection.acquisition_cost);
-                                    open_asset_ui.size = Some(size);
-                                    if resource_stock.get(&Material::Money).unwrap().quantity
-                                        >= owned_connection.acquisition_cost
-                                        && clicked == ButtonState::Pressed
-                                    {
-                                        let owned_connection =
-                                            graph.connections.get_mut(connection_id).unwrap();
-                                        owned_connection.owner_id = Some(current_player_id);
-                                        *graph
-                                            .businesses
-                                            .get_mut(current_player_id)
-                                            .unwrap()
-                                            .resources
-                                            .get_mut(&backend::Material::Money)
-                                            .unwrap() -= owned_connection.acquisition_cost;
-                                    }
-                                    size
+                            let toll_info = match owned_connection.toll {
+                                Some(toll) => format!("Toll: {}$/round", toll),
+                                None => "Closed to other businesses".to_string(),
+                            };
+                            let size = draw_message_box_ui(
+                                x,
+                                y,
+                                format!(
+                                    "Owned by {}\n{}",
+                                    game.graph.businesses[other_id].name, toll_info
+                                )
+                                .as_str(),
+                            );
+                            let paying = game.graph.businesses[current_player_id]
+                                .toll_connection_ids
+                                .contains(&connection_id);
+                            if !paying && owned_connection.toll.is_none() {
+                                size
+                            } else {
+                                let (toll_state, toll_size) = draw_text_button(
+                                    x,
+                                    y + size.y,
+                                    if paying { "Stop toll" } else { "Pay toll" },
+                                );
+                                if toll_state == ButtonState::Pressed {
+                                    command_opt = Some(if paying {
+                                        Command::StopPayingToll { connection_id }
+                                    } else {
+                                        Command::StartPayingToll { connection_id }
+                                    });
                                 }
-                                false => {
-                                    draw_message_box_ui(x, y, "Not connected\nto your network")
+                                Vec2::new(size.x.max(toll_size.x), size.y + toll_size.y)
+                            }
+                        }
+                        None => match game.check_buy_connection(connection_id) {
+                            Err(GameError::NotConnected) => {
+                                draw_message_box_ui(x, y, "Not connected\nto your network")
+                            }
+                            _ => {
+                                let (clicked, size) =
+                                    draw_buy_ui(x, y, owned_connection.acquisition_cost);
+                                if clicked == ButtonState::Pressed {
+                                    command_opt = Some(Command::BuyConnection { connection_id });
                                 }
+                                size + Vec2::new(
+                                    0.0,
+                                    draw_message_box_ui(
+                                        x,
+                                        y + size.y,
+                                        format!(
+                                            "Maintenance cost: {}$/round",
+                                            owned_connection.maintenance_cost
+                                        )
+                                        .as_str(),
+                                    )
+                                    .y,
+                                )
+                            }
+                        },
+                    }
+                }
+                Asset::City(city_id) => {
+                    let resource_stock = game.graph.get_resource_stock(
+                        &game.registry,
+                        current_player_id,
+                        Some(city_id),
+                    );
+                    let shipments: Vec<_> = game.graph.businesses[current_player_id]
+                        .shipments
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, shipment)| {
+                            shipment.from_city_id == city_id || shipment.to_city_id == city_id
+                        })
+                        .collect();
+                    let (size, action_opt) = draw_city_panel(
+                        x,
+                        y,
+                        city_id,
+                        &resource_stock,
+                        &shipments,
+                        &game.registry,
+                        &textures,
+                    );
+                    match action_opt {
+                        Some(CityPanelAction::Ship(material)) => {
+                            shipping_opt = Some((material, city_id));
+                        }
+                        Some(CityPanelAction::SetShipmentQuantity {
+                            shipment_id,
+                            quantity,
+                        }) => {
+                            command_opt = Some(Command::SetShipmentQuantity {
+                                shipment_id,
+                                quantity,
+                            });
+                        }
+                        None => {}
+                    }
+                    let constructible: Vec<&BuildingTypeDef> = game
+                        .registry
+                        .building_types
+                        .values()
+                        .filter(|building_type_def| building_type_def.construction.is_some())
+                        .collect();
+                    let construction = (!constructible.is_empty()
+                        && game.graph.cities[city_id].has_free_slot())
+                    .then(|| constructible[construction_type_index % constructible.len()]);
+                    let mut panel_size = size;
+                    if let Some(building_type_def) = construction {
+                        let (construction_size, construction_action_opt) = draw_construction_panel(
+                            x,
+                            y + panel_size.y,
+                            building_type_def,
+                            &game.registry,
+                            &textures,
+                        );
+                        match construction_action_opt {
+                            Some(ConstructionPanelAction::SelectBuildingType(step)) => {
+                                construction_type_index = (construction_type_index as i32 + step)
+                                    .rem_euclid(constructible.len() as i32)
+                                    as usize;
+                            }
+                            Some(ConstructionPanelAction::Construct) => {
+                                command_opt = Some(Command::ConstructBuilding {
+                                    city_id,
+                                    building_type: building_type_def.id.clone(),
+                                });
                             }
+                            None => {}
                         }
+                        panel_size.y += construction_size.y;
                     }
+                    let (connect_state, connect_size) =
+                        draw_text_button(x, y + panel_size.y, "Connect");
+                    if connect_state == ButtonState::Pressed {
+                        connecting_opt = Some(city_id);
+                    }
+                    panel_size + Vec2::new(0.0, connect_size.y)
                 }
             })
         }
-        if mouse_button_pressed && !cursor_inside_asset_ui {
+        if let Some((material, from_city_id)) = &shipping_opt {
+            let route_info = match clicked_city_opt {
+                Some((to_city_id, _)) if to_city_id != *from_city_id => {
+                    match game
+                        .graph
+                        .find_route(current_player_id, *from_city_id, to_city_id, 1)
+                    {
+                        Some(connection_ids) => format!(
+                            "\nTo city {}: {} connections, {}$/unit",
+                            to_city_id,
+                            connection_ids.len(),
+                            game.graph.get_shipping_cost(&connection_ids, 1)
+                        ),
+                        None => format!("\nNo route to city {}", to_city_id),
+                    }
+                }
+                _ => String::new(),
+            };
+            draw_message_box_ui(
+                screen_width() / 2.0 - 200.0,
+                MARGIN,
+                format!("Select a destination for {}{}", material, route_info).as_str(),
+            );
+        }
+        if let Some(from_city_id) = connecting_opt {
+            let construction_info = match clicked_city_opt {
+                Some((to_city_id, _)) if to_city_id != from_city_id => {
+                    match game.check_construct_connection(from_city_id, to_city_id) {
+                        Ok((cost, construction)) => format!(
+                            "\nTo city {}: {}$ {}, {} rounds",
+                            to_city_id,
+                            cost,
+                            construction
+                                .materials
+                                .iter()
+                                .map(|(material, quantity)| format!("+ {} {}", quantity, material))
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                            construction.rounds
+                        ),
+                        Err(err) => format!("\nTo city {}: {}", to_city_id, err),
+                    }
+                }
+                _ => String::new(),
+            };
+            draw_message_box_ui(
+                screen_width() / 2.0 - 200.0,
+                MARGIN,
+                format!(
+                    "Select a city to connect to city {}{}",
+                    from_city_id, construction_info
+                )
+                .as_str(),
+            );
+        }
+        if let Some(command) = command_opt {
+            let end_turn = command == Command::EndTurn;
+            let demolish = matches!(command, Command::DemolishBuilding { .. });
+            let trade_sent = matches!(
+                command,
+                Command::ProposeTrade { .. } | Command::CounterOffer { .. }
+            );
+            match game.execute(command) {
+                Ok(()) if end_turn => {
+                    trade_draft_opt = None;
+                    if let Err(err) = save_store.save(&game, AUTOSAVE_SLOT) {
+                        status_message_opt = Some(err.to_string());
+                    }
+                }
+                // The panel points at a building that no longer exists.
+                Ok(()) if demolish => open_asset_ui_opt = None,
+                Ok(()) if trade_sent => trade_draft_opt = None,
+                Ok(()) => {}
+                Err(err) => status_message_opt = Some(err.to_string()),
+            }
+        }
+        if mouse_button_pressed && !cursor_inside_asset_ui && !cursor_inside_overlay {
             open_asset_ui_opt = None;
+            match (shipping_opt.take(), connecting_opt.take(), clicked_city_opt) {
+                (Some((material, from_city_id)), _, Some((to_city_id, _))) => {
+                    let command = Command::AddShipment {
+                        material,
+                        from_city_id,
+                        to_city_id,
+                        quantity: 1,
+                    };
+                    if let Err(err) = game.execute(command) {
+                        status_message_opt = Some(err.to_string());
+                    }
+                }
+                (None, Some(from_city_id), Some((to_city_id, _))) => {
+                    let command = Command::ConstructConnection {
+                        from_city_id,
+                        to_city_id,
+                    };
+                    if let Err(err) = game.execute(command) {
+                        status_message_opt = Some(err.to_string());
+                    }
+                }
+                (None, None, Some((city_id, position))) => {
+                    open_asset_ui_opt = Some(AssetUI {
+                        asset: Asset::City(city_id),
+                        position,
+                        size: None,
+                    });
+                }
+                _ => {}
+            }
         }
         next_frame().await
     }
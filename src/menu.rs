@@ -0,0 +1,221 @@
+use crate::ui::*;
+use logistics_game::backend::{
+    ai::{Controller, Difficulty},
+    game::Game,
+    map::MapSize,
+    registry::Registry,
+    roster::{self, PlayerSetup, MAX_PLAYERS, MIN_PLAYERS},
+    save::{SaveStore, AUTOSAVE_SLOT},
+    setup::GameSetup,
+    victory::WinCondition,
+};
+use macroquad::prelude::*;
+use std::mem;
+
+const CONTROLLERS: [Controller; 4] = [
+    Controller::Human,
+    Controller::Ai(Difficulty::Easy),
+    Controller::Ai(Difficulty::Normal),
+    Controller::Ai(Difficulty::Hard),
+];
+const MAX_NAME_LENGTH: usize = 16;
+const MAX_SEED_LENGTH: usize = 20;
+const STARTING_MONEY_STEP: i32 = 50;
+const ROW_HEIGHT: f32 = 50.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Name(usize),
+    Seed,
+}
+
+fn get_next<T: Copy>(options: &[T], current: T, same: impl Fn(&T, &T) -> bool) -> T {
+    let index = options
+        .iter()
+        .position(|option| same(option, &current))
+        .map_or(0, |index| index + 1);
+    options[index % options.len()]
+}
+
+/// Shows the start menu until a new game is set up or a saved game is loaded.
+pub async fn run_start_menu(
+    mut setup: GameSetup,
+    registry: &Registry,
+    save_store: &SaveStore,
+) -> Game {
+    let mut seed_text = setup.seed.to_string();
+    let mut focused_field_opt: Option<TextField> = None;
+    let mut load_slots_opt: Option<Vec<String>> = None;
+    let mut status_message_opt: Option<String> = None;
+
+    loop {
+        clear_background(BLACK);
+
+        if let Some(focused_field) = focused_field_opt {
+            let (text, max_length) = match focused_field {
+                TextField::Name(player_id) => (&mut setup.players[player_id].name, MAX_NAME_LENGTH),
+                TextField::Seed => (&mut seed_text, MAX_SEED_LENGTH),
+            };
+            while let Some(character) = get_char_pressed() {
+                let accepted = match focused_field {
+                    TextField::Name(_) => !character.is_control(),
+                    TextField::Seed => character.is_ascii_digit(),
+                };
+                if accepted && text.chars().count() < max_length {
+                    text.push(character);
+                }
+            }
+            if is_key_pressed(KeyCode::Backspace) {
+                text.pop();
+            }
+        } else {
+            while get_char_pressed().is_some() {}
+        }
+        if is_mouse_button_pressed(MouseButton::Left) {
+            focused_field_opt = None;
+        }
+
+        let x = 200.0;
+        let mut y = 100.0;
+        draw_text("New game", x, y, 48.0, WHITE);
+        y += ROW_HEIGHT;
+
+        draw_text("Players", x, y + 30.0, 32.0, WHITE);
+        let (step, _) = draw_stepper(x + 250.0, y, setup.players.len().to_string().as_str());
+        match step {
+            1 if setup.players.len() < MAX_PLAYERS => {
+                let player_id = setup.players.len();
+                setup.players.push(PlayerSetup::new(
+                    player_id,
+                    Controller::Ai(Difficulty::Normal),
+                ));
+            }
+            -1 if setup.players.len() > MIN_PLAYERS => {
+                setup.players.pop();
+            }
+            _ => {}
+        }
+        y += ROW_HEIGHT;
+
+        for (player_id, player_setup) in setup.players.iter_mut().enumerate() {
+            let [r, g, b] = roster::get_palette_color(player_id);
+            draw_rectangle(x, y, 40.0, 40.0, Color::from_rgba(r, g, b, 255));
+            let focused = focused_field_opt == Some(TextField::Name(player_id));
+            let (state, _) =
+                draw_text_field(x + 50.0, y, 300.0, player_setup.name.as_str(), focused);
+            if state == ButtonState::Pressed {
+                focused_field_opt = Some(TextField::Name(player_id));
+            }
+            let controller_label = player_setup.controller.to_string();
+            if draw_labeled_button(x + 370.0, y, 200.0, 40.0, controller_label.as_str())
+                == ButtonState::Pressed
+            {
+                player_setup.controller =
+                    get_next(&CONTROLLERS, player_setup.controller, |a, b| a == b);
+            }
+            y += ROW_HEIGHT;
+        }
+
+        draw_text("Seed", x, y + 30.0, 32.0, WHITE);
+        let focused = focused_field_opt == Some(TextField::Seed);
+        let (state, _) = draw_text_field(x + 250.0, y, 300.0, seed_text.as_str(), focused);
+        if state == ButtonState::Pressed {
+            focused_field_opt = Some(TextField::Seed);
+        }
+        if draw_labeled_button(x + 570.0, y, 120.0, 40.0, "Random") == ButtonState::Pressed {
+            seed_text = ::rand::random::<u64>().to_string();
+        }
+        y += ROW_HEIGHT;
+
+        draw_text("Map size", x, y + 30.0, 32.0, WHITE);
+        let map_size_label = setup.map_size.to_string();
+        if draw_labeled_button(x + 250.0, y, 220.0, 40.0, map_size_label.as_str())
+            == ButtonState::Pressed
+        {
+            setup.map_size = get_next(&MapSize::ALL, setup.map_size, |a, b| a == b);
+        }
+        y += ROW_HEIGHT;
+
+        draw_text("Starting money", x, y + 30.0, 32.0, WHITE);
+        let (step, _) = draw_stepper(x + 250.0, y, format!("{}$", setup.starting_money).as_str());
+        setup.starting_money =
+            (setup.starting_money + step * STARTING_MONEY_STEP).max(STARTING_MONEY_STEP);
+        y += ROW_HEIGHT;
+
+        draw_text("Win condition", x, y + 30.0, 32.0, WHITE);
+        let win_condition_label = setup.win_condition.to_string();
+        if draw_labeled_button(x + 250.0, y, 440.0, 40.0, win_condition_label.as_str())
+            == ButtonState::Pressed
+        {
+            setup.win_condition = get_next(&WinCondition::ALL, setup.win_condition, |a, b| {
+                mem::discriminant(a) == mem::discriminant(b)
+            });
+        }
+        if setup.win_condition != WinCondition::Sandbox {
+            for (offset, label, steps) in [(710.0, " -", -1), (760.0, " +", 1)] {
+                if draw_labeled_button(x + offset, y, 40.0, 40.0, label) == ButtonState::Pressed {
+                    setup.win_condition = setup.win_condition.adjust(steps);
+                }
+            }
+        }
+        y += ROW_HEIGHT + MARGIN;
+
+        let (start_state, _) = draw_text_button(x, y, "Start");
+        if start_state == ButtonState::Pressed {
+            // An empty seed plays a random map, anything else has to reproduce the one typed.
+            let seed = match seed_text.as_str() {
+                "" => Ok(::rand::random()),
+                text => text.parse::<u64>(),
+            };
+            match seed {
+                Ok(seed) => {
+                    setup.seed = seed;
+                    return Game::new(&setup, registry.clone());
+                }
+                Err(_) => {
+                    status_message_opt = Some(format!(
+                        "Invalid seed {}: use a number up to {}",
+                        seed_text,
+                        u64::MAX
+                    ))
+                }
+            }
+        }
+
+        let x = screen_width() - 600.0;
+        let mut y = 100.0;
+        let (continue_state, size) = draw_text_button(x, y, "Continue");
+        y += size.y;
+        let (load_state, size) = draw_text_button(x, y, "Load");
+        y += size.y + MARGIN;
+        let mut load_slot_opt = None;
+        if continue_state == ButtonState::Pressed {
+            load_slot_opt = Some(AUTOSAVE_SLOT.to_string());
+        }
+        if load_state == ButtonState::Pressed {
+            load_slots_opt = match save_store.list_slots() {
+                Ok(slots) => Some(slots),
+                Err(err) => {
+                    status_message_opt = Some(err.to_string());
+                    None
+                }
+            };
+        }
+        if let Some(slots) = &load_slots_opt {
+            if let (Some(slot_index), _) = draw_slot_menu(x, y, slots) {
+                load_slot_opt = Some(slots[slot_index].clone());
+            }
+        }
+        if let Some(slot) = load_slot_opt {
+            match save_store.load(&slot) {
+                Ok(game) => return game,
+                Err(err) => status_message_opt = Some(err.to_string()),
+            }
+        }
+
+        if let Some(status_message) = &status_message_opt {
+            draw_message_box_ui(MARGIN, screen_height() - 60.0, status_message);
+        }
+        next_frame().await
+    }
+}
@@ -1,4 +1,15 @@
-use crate::backend::{Material, OwnedBuilding, QuantityInfo, ScaledValidRecipe};
+use logistics_game::backend::{
+    logistics::Shipment,
+    planner::{Plan, Target},
+    registry::{BuildingTypeDef, MaterialId, Registry, UpgradeDef, ValidRecipe},
+    resolution::{ActivityId, Throttle},
+    sale::AssetId,
+    stats::Metric,
+    trade::{self, Terms},
+    valuation::AssetReturn,
+    victory::Score,
+    Graph, OwnedBuilding, QuantityInfo, ScaledValidRecipe,
+};
 use macroquad::prelude::*;
 use std::collections::{BTreeMap, HashMap};
 
@@ -11,6 +22,8 @@ pub const UI_BACKGROUND_COLOR: macroquad::color::Color = Color {
 pub const TEXTURE_SIZE: f32 = 80.0;
 pub const ICON_SIZE: f32 = 50.0;
 pub const MARGIN: f32 = 10.0;
+/// Key of the texture drawn in place of textures that could not be loaded.
+pub const PLACEHOLDER_TEXTURE: &str = "placeholder";
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ButtonState {
@@ -52,6 +65,118 @@ pub fn draw_buy_ui(x: f32, y: f32, amount: i32) -> (ButtonState, Vec2) {
     (clicked, Vec2::new(w, h))
 }
 
+/// Shows the building level, with a button to buy the next upgrade if there is one.
+pub fn draw_upgrade_ui(
+    x: f32,
+    y: f32,
+    level: usize,
+    upgrade: Option<&UpgradeDef>,
+) -> (ButtonState, Vec2) {
+    let w = 350.0 + 2.0 * MARGIN;
+    let h = 40.0 + 2.0 * MARGIN;
+    let x_ = x + MARGIN;
+    let y_ = y + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let Some(upgrade) = upgrade else {
+        draw_text(
+            format!("Level {} | Max level", level + 1).as_str(),
+            x_ + MARGIN,
+            y + h / 2.0,
+            32.0,
+            WHITE,
+        );
+        return (ButtonState::None, Vec2::new(w, h));
+    };
+    let (clicked, _button_size) = draw_button(x_, y_, w - 2.0 * MARGIN, h - 2.0 * MARGIN, RED);
+    draw_text(
+        format!(
+            "Level {} | Upgrade {}$ (x{})",
+            level + 1,
+            upgrade.cost,
+            upgrade.max_scale
+        )
+        .as_str(),
+        x_ + MARGIN,
+        y + h / 2.0,
+        32.0,
+        WHITE,
+    );
+    (clicked, Vec2::new(w, h))
+}
+
+pub enum SaleAction {
+    Sell,
+    Demolish,
+}
+
+/// Sell and demolish buttons of an owned asset. `sale_price` is `None` when it cannot be sold.
+pub fn draw_sale_ui(
+    x: f32,
+    y: f32,
+    sale_price: Option<i32>,
+    demolishable: bool,
+    splits_network: bool,
+) -> (Option<SaleAction>, Vec2) {
+    let w = 350.0 + 2.0 * MARGIN;
+    let row_h = 30.0 + MARGIN;
+    let h = row_h * if splits_network { 2.0 } else { 1.0 } + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let mut action = None;
+    if let Some(sale_price) = sale_price {
+        if draw_labeled_button(
+            x + MARGIN,
+            y + MARGIN,
+            160.0,
+            30.0,
+            format!("Sell | {}$", sale_price).as_str(),
+        ) == ButtonState::Pressed
+        {
+            action = Some(SaleAction::Sell);
+        }
+    }
+    if demolishable
+        && draw_labeled_button(
+            x + 2.0 * MARGIN + 160.0,
+            y + MARGIN,
+            160.0,
+            30.0,
+            "Demolish",
+        ) == ButtonState::Pressed
+    {
+        action = Some(SaleAction::Demolish);
+    }
+    if splits_network {
+        draw_text(
+            "Giving this up splits your network",
+            x + MARGIN,
+            y + row_h + 30.0,
+            24.0,
+            ORANGE,
+        );
+    }
+    (action, Vec2::new(w, h))
+}
+
+/// Toll stepper of an owned connection. Stepping below a free toll closes it to others.
+pub fn draw_toll_ui(x: f32, y: f32, toll: Option<i32>, payer_count: usize) -> (i32, Vec2) {
+    let w = 350.0 + 2.0 * MARGIN;
+    let h = 40.0 + 2.0 * MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let value = match toll {
+        Some(toll) => format!("Toll {}$", toll),
+        None => "Closed".to_string(),
+    };
+    let (step, stepper_size) = draw_stepper(x + MARGIN, y + MARGIN, value.as_str());
+    draw_text(
+        format!("{} paying", payer_count).as_str(),
+        x + 2.0 * MARGIN + stepper_size.x,
+        y + h - 2.0 * MARGIN,
+        24.0,
+        WHITE,
+    );
+    (step, Vec2::new(w, h))
+}
+
 pub fn draw_next_turn_button(x: f32, y: f32) -> (ButtonState, Vec2) {
     let w = 150.0 + 2.0 * MARGIN;
     let h = 50.0 + 2.0 * MARGIN;
@@ -63,6 +188,93 @@ pub fn draw_next_turn_button(x: f32, y: f32) -> (ButtonState, Vec2) {
     (clicked, Vec2::new(w, h))
 }
 
+pub fn draw_text_button(x: f32, y: f32, text: &str) -> (ButtonState, Vec2) {
+    let w = 150.0 + 2.0 * MARGIN;
+    let h = 40.0 + 2.0 * MARGIN;
+    let x_ = x + MARGIN;
+    let y_ = y + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let (clicked, _button_size) = draw_button(x_, y_, w - 2.0 * MARGIN, h - 2.0 * MARGIN, RED);
+    draw_text(text, x_ + MARGIN, y + h / 2.0, 32.0, WHITE);
+    (clicked, Vec2::new(w, h))
+}
+
+/// The loaded texture, or the placeholder when there is none.
+pub fn get_texture<'a>(
+    textures: &'a HashMap<String, Texture2D>,
+    texture_id: &str,
+) -> &'a Texture2D {
+    textures
+        .get(texture_id)
+        .unwrap_or_else(|| &textures[PLACEHOLDER_TEXTURE])
+}
+
+/// Name field and save button above the existing saves. Picking a save fills in its name, to
+/// overwrite it. Returns whether the save button was pressed.
+pub fn draw_save_menu(x: f32, y: f32, name: &mut String, slots: &[String]) -> (bool, Vec2) {
+    let (_, field_size) = draw_text_field(x, y, 170.0, name.as_str(), true);
+    let (state, button_size) = draw_text_button(x, y + field_size.y, "Save");
+    let (slot_index_opt, slots_size) = draw_slot_menu(x, y + field_size.y + button_size.y, slots);
+    if let Some(slot_index) = slot_index_opt {
+        *name = slots[slot_index].clone();
+    }
+    (
+        state == ButtonState::Pressed,
+        Vec2::new(
+            button_size.x.max(slots_size.x),
+            field_size.y + button_size.y + slots_size.y,
+        ),
+    )
+}
+
+pub fn draw_slot_menu(x: f32, y: f32, slots: &[String]) -> (Option<usize>, Vec2) {
+    let mut y_ = y;
+    let mut selected = None;
+    let mut w: f32 = 0.0;
+    for (slot_index, slot) in slots.iter().enumerate() {
+        let (clicked, size) = draw_text_button(x, y_, slot);
+        if clicked == ButtonState::Pressed {
+            selected = Some(slot_index);
+        }
+        w = w.max(size.x);
+        y_ += size.y;
+    }
+    if slots.is_empty() {
+        let size = draw_message_box_ui(x, y, "No saves");
+        return (None, size);
+    }
+    (selected, Vec2::new(w, y_ - y))
+}
+
+pub fn draw_text_field(x: f32, y: f32, w: f32, text: &str, focused: bool) -> (ButtonState, Vec2) {
+    let h = 40.0;
+    let (state, _size) = draw_button(x, y, w, h, if focused { DARKGRAY } else { BLACK });
+    let cursor = if focused { "|" } else { "" };
+    draw_text(
+        format!("{}{}", text, cursor).as_str(),
+        x + MARGIN / 2.0,
+        y + h - MARGIN,
+        28.0,
+        WHITE,
+    );
+    (state, Vec2::new(w, h))
+}
+
+/// A value between a decrement and an increment button. Returns the requested step.
+pub fn draw_stepper(x: f32, y: f32, value: &str) -> (i32, Vec2) {
+    let h = 40.0;
+    let value_w = 140.0;
+    let mut step = 0;
+    if draw_labeled_button(x, y, h, h, " -") == ButtonState::Pressed {
+        step = -1;
+    }
+    draw_text(value, x + h + MARGIN, y + h - MARGIN, 28.0, WHITE);
+    if draw_labeled_button(x + h + value_w, y, h, h, " +") == ButtonState::Pressed {
+        step = 1;
+    }
+    (step, Vec2::new(2.0 * h + value_w, h))
+}
+
 pub fn draw_message_box_ui(x: f32, y: f32, text: &str) -> Vec2 {
     let (font_size, font_scale, font_aspect) = camera_font_scale(30.0);
     let params = TextParams {
@@ -79,77 +291,113 @@ pub fn draw_message_box_ui(x: f32, y: f32, text: &str) -> Vec2 {
     Vec2::new(w, h)
 }
 
+pub enum RecipesPanelAction {
+    SetScale { recipe_id: usize, scale: i32 },
+    SetPriority { recipe_id: usize, priority: i32 },
+}
+
+/// `throttles` are the throttled recipes of this building keyed by recipe id.
 pub fn draw_recipes_panel(
     x: f32,
     y: f32,
-    building: &mut OwnedBuilding,
-    resource_stock: &BTreeMap<Material, QuantityInfo>,
+    building: &OwnedBuilding,
+    throttles: &BTreeMap<usize, &Throttle>,
+    registry: &Registry,
     textures: &HashMap<String, Texture2D>,
     editable: bool,
-) -> Vec2 {
+) -> (Vec2, Option<RecipesPanelAction>) {
     let mut x_ = x + MARGIN;
     let mut y_ = y;
 
-    let w = 5 as f32 * (TEXTURE_SIZE + MARGIN) + 2.0 * MARGIN + 50.0;
+    let w = 5.0 * (TEXTURE_SIZE + MARGIN) + 3.0 * MARGIN + 100.0;
     let h = (TEXTURE_SIZE + MARGIN) * building.production_scale.len() as f32 + MARGIN;
     draw_rectangle(x, y_, w, h, UI_BACKGROUND_COLOR);
     y_ += MARGIN;
-    for ScaledValidRecipe {
-        valid_recipe,
-        scale,
-        max_scale,
-    } in building.production_scale.iter_mut()
+    let mut action = None;
+    for (
+        recipe_id,
+        ScaledValidRecipe {
+            valid_recipe,
+            scale,
+            max_scale,
+            priority,
+        },
+    ) in building.production_scale.iter().enumerate()
     {
-        let mut texture_ids: Vec<(String, i32)> = vec![("right_arrow".to_string(), 1)];
+        let mut texture_ids: Vec<(&str, i32)> = vec![("right_arrow", 1)];
 
-        for (material, quantity) in valid_recipe.get_recipe().materials.iter() {
+        for (material, quantity) in building.get_recipe(registry, valid_recipe).materials.iter() {
             let index = if *quantity > 0 { texture_ids.len() } else { 0 };
-            texture_ids.insert(index, (material.get_texture_id(), quantity.abs()));
+            texture_ids.insert(index, (registry.get_texture_id(material), quantity.abs()));
         }
 
         let click_up = editable && ButtonState::Pressed == draw_button(x_, y_, 50.0, 25.0, BLACK).0;
         let click_down = editable
             && ButtonState::Pressed
                 == draw_button(x_, y_ + TEXTURE_SIZE - MARGIN - 25.0, 50.0, 25.0, BLACK).0;
-        let requested_increment = match (click_up, click_down, *scale == 0, *scale == *max_scale) {
-            (true, false, _, false) => 1,
-            (false, true, false, _) => -1,
-            _ => 0,
-        };
-        let can_increment = requested_increment != 0
-            && valid_recipe
-                .get_recipe()
-                .materials
-                .iter()
-                .all(|(material, quantity)| {
-                    requested_increment * quantity > 0
-                        || match resource_stock.get(material) {
-                            Some(quantity_info) => {
-                                quantity_info.quantity
-                                    + requested_increment * quantity
-                                    + quantity_info.gross_in
-                                    - quantity_info.gross_out
-                                    >= 0
-                            }
-                            None => requested_increment * *quantity >= 0,
-                        }
-                });
-        if can_increment {
-            *scale += requested_increment;
+        match (click_up, click_down) {
+            (true, false) => {
+                action = Some(RecipesPanelAction::SetScale {
+                    recipe_id,
+                    scale: scale + 1,
+                })
+            }
+            (false, true) => {
+                action = Some(RecipesPanelAction::SetScale {
+                    recipe_id,
+                    scale: scale - 1,
+                })
+            }
+            _ => {}
         }
+        let throttle_opt = throttles.get(&recipe_id);
         draw_text(
             format!("{}/{}", scale, max_scale).as_str(),
             x_,
             y_ + TEXTURE_SIZE / 2.0,
             32.0,
+            if throttle_opt.is_some() {
+                ORANGE
+            } else {
+                WHITE
+            },
+        );
+        if let Some(throttle) = throttle_opt {
+            draw_text(
+                format!("ran {}", throttle.throughput).as_str(),
+                x_,
+                y_ + TEXTURE_SIZE / 2.0 + 16.0,
+                18.0,
+                ORANGE,
+            );
+        }
+        x_ += 50.0 + MARGIN;
+
+        if editable {
+            for (offset, label, increment) in
+                [(0.0, "+", 1), (TEXTURE_SIZE - MARGIN - 25.0, "-", -1)]
+            {
+                if draw_labeled_button(x_, y_ + offset, 25.0, 25.0, label) == ButtonState::Pressed {
+                    action = Some(RecipesPanelAction::SetPriority {
+                        recipe_id,
+                        priority: priority + increment,
+                    });
+                }
+            }
+        }
+        draw_text(
+            format!("P{}", priority).as_str(),
+            x_,
+            y_ + TEXTURE_SIZE / 2.0,
+            24.0,
             WHITE,
         );
         x_ += 50.0 + MARGIN;
 
         for (texture_id, quantity) in texture_ids {
-            let texture = textures.get(&texture_id).unwrap();
+            let texture = get_texture(textures, texture_id);
             draw_texture_ex(
-                &texture,
+                texture,
                 x_,
                 y_,
                 WHITE,
@@ -169,8 +417,857 @@ pub fn draw_recipes_panel(
             }
             x_ += TEXTURE_SIZE + MARGIN;
         }
+        if let ValidRecipe::MaterialImport(material) | ValidRecipe::MaterialExport(material) =
+            valid_recipe
+        {
+            draw_text(
+                format!("Buy {}$", building.get_buy_price(registry, material)).as_str(),
+                x_,
+                y_ + TEXTURE_SIZE / 3.0,
+                24.0,
+                WHITE,
+            );
+            draw_text(
+                format!("Sell {}$", building.get_sell_price(registry, material)).as_str(),
+                x_,
+                y_ + 2.0 * TEXTURE_SIZE / 3.0,
+                24.0,
+                WHITE,
+            );
+            draw_text(
+                format!("Base {}$", registry.get_market_price(material).price).as_str(),
+                x_,
+                y_ + TEXTURE_SIZE,
+                24.0,
+                WHITE,
+            );
+        }
         x_ = x + MARGIN;
         y_ += TEXTURE_SIZE + MARGIN;
     }
+    (Vec2::new(w, h), action)
+}
+
+/// Lists the activities that ran below plan last round and what they ran short of.
+pub fn draw_throttle_report(x: f32, y: f32, graph: &Graph, business_id: usize) -> Vec2 {
+    let business = &graph.businesses[business_id];
+    if business.throttles.is_empty() {
+        return Vec2::ZERO;
+    }
+    let lines: Vec<String> = std::iter::once("Throttled last round:".to_string())
+        .chain(business.throttles.iter().map(|throttle| {
+            let activity = match throttle.id {
+                ActivityId::Recipe {
+                    city_id,
+                    building_id,
+                    recipe_id,
+                } => {
+                    let building = graph.cities[city_id].get_building(building_id).unwrap();
+                    format!(
+                        "City {} {}: {}",
+                        city_id,
+                        building.building_type,
+                        building.production_scale[recipe_id].valid_recipe
+                    )
+                }
+                ActivityId::Shipment(shipment_id) => match business.shipments.get(shipment_id) {
+                    Some(shipment) => format!(
+                        "Shipment {} {}->{}",
+                        shipment.material, shipment.from_city_id, shipment.to_city_id
+                    ),
+                    None => format!("Shipment {}", shipment_id),
+                },
+            };
+            format!(
+                "{} ran {}/{}, short of {}",
+                activity, throttle.throughput, throttle.planned, throttle.shortage
+            )
+        }))
+        .collect();
+    let line_height = 24.0;
+    let w = lines
+        .iter()
+        .map(|line| measure_text(line, None, 20, 1.0).width)
+        .fold(0.0, f32::max)
+        + 2.0 * MARGIN;
+    let h = line_height * lines.len() as f32 + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    for (line_index, line) in lines.iter().enumerate() {
+        draw_text(
+            line,
+            x + MARGIN,
+            y + line_height * (line_index + 1) as f32,
+            20.0,
+            WHITE,
+        );
+    }
+    Vec2::new(w, h)
+}
+
+pub enum CityPanelAction {
+    Ship(MaterialId),
+    SetShipmentQuantity { shipment_id: usize, quantity: i32 },
+}
+
+pub fn draw_labeled_button(x: f32, y: f32, w: f32, h: f32, label: &str) -> ButtonState {
+    let (state, _size) = draw_button(x, y, w, h, BLACK);
+    draw_text(label, x + MARGIN / 2.0, y + h - MARGIN / 2.0, 24.0, WHITE);
+    state
+}
+
+pub fn draw_city_panel(
+    x: f32,
+    y: f32,
+    city_id: usize,
+    resource_stock: &BTreeMap<MaterialId, QuantityInfo>,
+    shipments: &[(usize, &Shipment)],
+    registry: &Registry,
+    textures: &HashMap<String, Texture2D>,
+) -> (Vec2, Option<CityPanelAction>) {
+    let materials: Vec<(&MaterialId, &QuantityInfo)> = resource_stock
+        .iter()
+        .filter(|(material, _)| !material.is_money())
+        .collect();
+    let row_count = 1 + materials.len() + shipments.len();
+    let w = 350.0 + 2.0 * MARGIN;
+    let h = (ICON_SIZE + MARGIN) * row_count as f32 + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+
+    let x_ = x + MARGIN;
+    let mut y_ = y + MARGIN;
+    let mut action = None;
+    draw_text(
+        format!("City {}", city_id).as_str(),
+        x_,
+        y_ + ICON_SIZE / 2.0,
+        32.0,
+        WHITE,
+    );
+    y_ += ICON_SIZE + MARGIN;
+
+    for (material, quantity_info) in materials {
+        draw_texture_ex(
+            get_texture(textures, registry.get_texture_id(material)),
+            x_,
+            y_,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(Vec2::splat(ICON_SIZE)),
+                ..Default::default()
+            },
+        );
+        draw_text(
+            format!("{}({:+})", quantity_info.quantity, quantity_info.net_in()).as_str(),
+            x_ + ICON_SIZE + MARGIN,
+            y_ + ICON_SIZE / 2.0,
+            24.0,
+            WHITE,
+        );
+        if draw_labeled_button(x + w - 80.0, y_ + MARGIN, 70.0, 30.0, "Ship")
+            == ButtonState::Pressed
+        {
+            action = Some(CityPanelAction::Ship(material.clone()));
+        }
+        y_ += ICON_SIZE + MARGIN;
+    }
+
+    for (shipment_id, shipment) in shipments {
+        let label = if shipment.from_city_id == city_id {
+            format!("to {}", shipment.to_city_id)
+        } else {
+            format!("from {}", shipment.from_city_id)
+        };
+        draw_text(label.as_str(), x_, y_ + ICON_SIZE / 2.0, 24.0, WHITE);
+        draw_texture_ex(
+            get_texture(textures, registry.get_texture_id(&shipment.material)),
+            x_ + 100.0,
+            y_,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(Vec2::splat(ICON_SIZE)),
+                ..Default::default()
+            },
+        );
+        draw_text(
+            format!("x{}", shipment.quantity).as_str(),
+            x_ + 100.0 + ICON_SIZE + MARGIN,
+            y_ + ICON_SIZE / 2.0,
+            24.0,
+            WHITE,
+        );
+        for (offset, label, increment) in [(120.0, "-", -1), (80.0, "+", 1)] {
+            if draw_labeled_button(x + w - offset, y_ + MARGIN, 30.0, 30.0, label)
+                == ButtonState::Pressed
+            {
+                action = Some(CityPanelAction::SetShipmentQuantity {
+                    shipment_id: *shipment_id,
+                    quantity: shipment.quantity + increment,
+                });
+            }
+        }
+        y_ += ICON_SIZE + MARGIN;
+    }
+
+    (Vec2::new(w, h), action)
+}
+
+pub enum ConstructionPanelAction {
+    SelectBuildingType(i32),
+    Construct,
+}
+
+/// Lets the player pick a building type to construct in a free slot and shows what it costs.
+pub fn draw_construction_panel(
+    x: f32,
+    y: f32,
+    building_type_def: &BuildingTypeDef,
+    registry: &Registry,
+    textures: &HashMap<String, Texture2D>,
+) -> (Vec2, Option<ConstructionPanelAction>) {
+    let construction_def = building_type_def.construction.as_ref().unwrap();
+    let w = 350.0 + 2.0 * MARGIN;
+    let h = (ICON_SIZE + MARGIN) * 3.0 + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+
+    let x_ = x + MARGIN;
+    let mut y_ = y + MARGIN;
+    let mut action = None;
+    for (label, offset, step) in [("<", 0.0, -1), (">", 40.0, 1)] {
+        if draw_labeled_button(x_ + offset, y_ + MARGIN, 30.0, 30.0, label) == ButtonState::Pressed
+        {
+            action = Some(ConstructionPanelAction::SelectBuildingType(step));
+        }
+    }
+    draw_text(
+        building_type_def.id.0.as_str(),
+        x_ + 80.0,
+        y_ + ICON_SIZE / 2.0,
+        24.0,
+        WHITE,
+    );
+    y_ += ICON_SIZE + MARGIN;
+
+    let costs = std::iter::once((MaterialId::money(), building_type_def.acquisition_cost))
+        .chain(construction_def.materials.iter().cloned());
+    for (cost_id, (material, quantity)) in costs.enumerate() {
+        let cost_x = x_ + cost_id as f32 * (ICON_SIZE + 40.0);
+        draw_texture_ex(
+            get_texture(textures, registry.get_texture_id(&material)),
+            cost_x,
+            y_,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(Vec2::splat(ICON_SIZE)),
+                ..Default::default()
+            },
+        );
+        draw_text(
+            format!("x{}", quantity).as_str(),
+            cost_x + ICON_SIZE,
+            y_ + ICON_SIZE / 2.0,
+            24.0,
+            WHITE,
+        );
+    }
+    y_ += ICON_SIZE + MARGIN;
+
+    draw_text(
+        format!("Takes {} rounds", construction_def.rounds).as_str(),
+        x_,
+        y_ + ICON_SIZE / 2.0,
+        24.0,
+        WHITE,
+    );
+    if draw_labeled_button(x + w - 80.0, y_ + MARGIN, 70.0, 30.0, "Build") == ButtonState::Pressed {
+        action = Some(ConstructionPanelAction::Construct);
+    }
+    (Vec2::new(w, h), action)
+}
+
+pub fn draw_scoreboard(x: f32, y: f32, title: &str, graph: &Graph, scores: &[Score]) -> Vec2 {
+    let columns = [
+        ("Business", 220.0),
+        ("Cash", 120.0),
+        ("Buildings", 140.0),
+        ("Connections", 160.0),
+        ("Inventory", 140.0),
+        ("Liabilities", 140.0),
+        ("Net worth", 140.0),
+    ];
+    let row_h = 40.0;
+    let w = columns.iter().map(|(_, column_w)| column_w).sum::<f32>() + 2.0 * MARGIN;
+    let h = row_h * (scores.len() + 2) as f32 + MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    draw_text(title, x + MARGIN, y + row_h - MARGIN, 32.0, WHITE);
+    let mut rows: Vec<(Vec<String>, Color)> = vec![(
+        columns.iter().map(|(label, _)| label.to_string()).collect(),
+        WHITE,
+    )];
+    for score in scores {
+        let business = &graph.businesses[score.business_id];
+        let [r, g, b] = business.color;
+        let name = if business.eliminated {
+            format!("{} (bankrupt)", business.name)
+        } else {
+            business.name.clone()
+        };
+        rows.push((
+            vec![
+                name,
+                format!("{}$", score.cash),
+                format!("{}$", score.buildings_value),
+                format!("{}$", score.connections_value),
+                format!("{}$", score.inventory_value),
+                format!("{}$", score.liabilities),
+                format!("{}$", score.get_net_worth()),
+            ],
+            Color::from_rgba(r, g, b, 255),
+        ));
+    }
+    let mut y_ = y + row_h;
+    for (cells, color) in rows {
+        let mut x_ = x + MARGIN;
+        for (cell, (_, column_w)) in cells.iter().zip(columns) {
+            draw_text(cell, x_, y_ + row_h - MARGIN, 24.0, color);
+            x_ += column_w;
+        }
+        y_ += row_h;
+    }
+    Vec2::new(w, h)
+}
+
+/// A trade being put together in the trade panel, one material on each side.
+pub struct TradeDraft {
+    /// The offer this answers with a counter offer.
+    pub offer_id: Option<usize>,
+    pub to_id: usize,
+    pub city_id: usize,
+    pub give: (MaterialId, i32),
+    pub take: (MaterialId, i32),
+    pub rounds: u32,
+    pub penalty: i32,
+}
+
+impl TradeDraft {
+    pub fn new(offer_id: Option<usize>, to_id: usize, terms: &Terms) -> Self {
+        let first = |goods: &[(MaterialId, i32)]| {
+            goods.first().cloned().unwrap_or((MaterialId::money(), 0))
+        };
+        Self {
+            offer_id,
+            to_id,
+            city_id: terms.city_id,
+            give: first(&terms.gives),
+            take: first(&terms.takes),
+            rounds: terms.rounds,
+            penalty: terms.penalty,
+        }
+    }
+
+    pub fn get_terms(&self) -> Terms {
+        let goods = |(material, quantity): &(MaterialId, i32)| {
+            if *quantity > 0 {
+                vec![(material.clone(), *quantity)]
+            } else {
+                vec![]
+            }
+        };
+        Terms {
+            city_id: self.city_id,
+            gives: goods(&self.give),
+            takes: goods(&self.take),
+            rounds: self.rounds,
+            penalty: self.penalty,
+        }
+    }
+}
+
+pub enum TradePanelAction {
+    Accept(usize),
+    Counter(usize),
+    Reject(usize),
+    NewOffer,
+    Send,
+    Cancel,
+}
+
+fn describe_terms(terms: &Terms) -> String {
+    let mut description = format!(
+        "give {} for {} in city {}",
+        trade::format_goods(&terms.gives),
+        trade::format_goods(&terms.takes),
+        terms.city_id
+    );
+    if terms.is_contract() {
+        description += format!(
+            ", every round for {} rounds, {}$ penalty",
+            terms.rounds, terms.penalty
+        )
+        .as_str();
+    }
+    description
+}
+
+/// Cycles through options with a pair of arrow buttons.
+fn draw_selector(x: f32, y: f32, value: &str) -> i32 {
+    let mut step = 0;
+    for (label, offset, button_step) in [("<", 0.0, -1), (">", 40.0, 1)] {
+        if draw_labeled_button(x + offset, y, 30.0, 30.0, label) == ButtonState::Pressed {
+            step = button_step;
+        }
+    }
+    draw_text(value, x + 80.0, y + 24.0, 24.0, WHITE);
+    step
+}
+
+fn cycle<T: PartialEq + Clone>(options: &[T], current: &T, step: i32) -> T {
+    let index = options
+        .iter()
+        .position(|option| option == current)
+        .unwrap_or(0);
+    options[(index as i32 + step).rem_euclid(options.len() as i32) as usize].clone()
+}
+
+/// Offers and contracts of a business, and the draft of a new offer when there is one.
+pub fn draw_trade_panel(
+    x: f32,
+    y: f32,
+    graph: &Graph,
+    registry: &Registry,
+    business_id: usize,
+    draft_opt: Option<&mut TradeDraft>,
+) -> (Option<TradePanelAction>, Vec2) {
+    let w = 1000.0;
+    let row_h = 40.0;
+    let offer_ids = graph.get_offer_ids(business_id);
+    let contract_ids = graph.get_contract_ids(business_id);
+    let draft_rows = if draft_opt.is_some() { 5 } else { 1 };
+    let h = row_h * (offer_ids.len() + contract_ids.len() + draft_rows + 1) as f32 + 2.0 * MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let x_ = x + MARGIN;
+    let mut y_ = y + MARGIN;
+    let mut action = None;
+    draw_text("Trades", x_, y_ + row_h - MARGIN, 32.0, WHITE);
+    y_ += row_h;
+
+    for offer_id in offer_ids {
+        let offer = &graph.offers[offer_id];
+        let (text, buttons) = if offer.to_id == business_id {
+            (
+                format!(
+                    "{} offers: {}",
+                    graph.businesses[offer.from_id].name,
+                    describe_terms(&offer.terms.reversed())
+                ),
+                vec![
+                    ("Accept", TradePanelAction::Accept(offer_id)),
+                    ("Counter", TradePanelAction::Counter(offer_id)),
+                    ("Reject", TradePanelAction::Reject(offer_id)),
+                ],
+            )
+        } else {
+            (
+                format!(
+                    "To {}: {}",
+                    graph.businesses[offer.to_id].name,
+                    describe_terms(&offer.terms)
+                ),
+                vec![("Withdraw", TradePanelAction::Reject(offer_id))],
+            )
+        };
+        draw_text(text.as_str(), x_, y_ + row_h - MARGIN, 20.0, WHITE);
+        let mut button_x = x + w - MARGIN;
+        for (label, button_action) in buttons.into_iter().rev() {
+            button_x -= 90.0;
+            if draw_labeled_button(button_x, y_ + 5.0, 85.0, 30.0, label) == ButtonState::Pressed {
+                action = Some(button_action);
+            }
+        }
+        y_ += row_h;
+    }
+
+    for contract_id in contract_ids {
+        let contract = &graph.contracts[contract_id];
+        let side = contract.get_side(business_id).unwrap();
+        let partner_id = contract.party_ids[1 - side];
+        draw_text(
+            format!(
+                "Contract with {}: {}, {} rounds left, missed {} by you and {} by them",
+                graph.businesses[partner_id].name,
+                describe_terms(&contract.get_terms(side)),
+                contract.rounds_left,
+                contract.missed[side],
+                contract.missed[1 - side],
+            )
+            .as_str(),
+            x_,
+            y_ + row_h - MARGIN,
+            20.0,
+            WHITE,
+        );
+        y_ += row_h;
+    }
+
+    let Some(draft) = draft_opt else {
+        if draw_labeled_button(x_, y_ + 5.0, 120.0, 30.0, "New offer") == ButtonState::Pressed {
+            action = Some(TradePanelAction::NewOffer);
+        }
+        return (action, Vec2::new(w, h));
+    };
+    let partner_ids: Vec<usize> = (0..graph.businesses.len())
+        .filter(|id| *id != business_id && !graph.businesses[*id].eliminated)
+        .collect();
+    let city_ids: Vec<usize> = (0..graph.cities.len()).collect();
+    let materials: Vec<MaterialId> = registry.materials.keys().cloned().collect();
+
+    draw_text("To", x_, y_ + row_h - MARGIN, 24.0, WHITE);
+    let partner_name = graph.businesses[draft.to_id].name.as_str();
+    let step = draw_selector(x_ + 120.0, y_ + 5.0, partner_name);
+    if step != 0 && !partner_ids.is_empty() {
+        draft.to_id = cycle(&partner_ids, &draft.to_id, step);
+    }
+    draw_text("In city", x_ + 500.0, y_ + row_h - MARGIN, 24.0, WHITE);
+    let step = draw_selector(x_ + 620.0, y_ + 5.0, draft.city_id.to_string().as_str());
+    if step != 0 {
+        draft.city_id = cycle(&city_ids, &draft.city_id, step);
+    }
+    y_ += row_h;
+
+    for (label, (material, quantity)) in
+        [("You give", &mut draft.give), ("You get", &mut draft.take)]
+    {
+        draw_text(label, x_, y_ + row_h - MARGIN, 24.0, WHITE);
+        let step = draw_selector(x_ + 120.0, y_ + 5.0, material.0.as_str());
+        if step != 0 {
+            *material = cycle(&materials, material, step);
+        }
+        let (step, _) = draw_stepper(x_ + 500.0, y_, quantity.to_string().as_str());
+        *quantity = (*quantity + step).max(0);
+        y_ += row_h;
+    }
+
+    let rounds = match draft.rounds {
+        0 => "Once".to_string(),
+        rounds => format!("{} rounds", rounds),
+    };
+    let (step, _) = draw_stepper(x_, y_, rounds.as_str());
+    draft.rounds = draft.rounds.saturating_add_signed(step);
+    if draft.rounds > 0 {
+        draw_text("Penalty", x_ + 380.0, y_ + row_h - MARGIN, 24.0, WHITE);
+        let (step, _) = draw_stepper(x_ + 500.0, y_, format!("{}$", draft.penalty).as_str());
+        draft.penalty = (draft.penalty + step).max(0);
+    }
+    y_ += row_h;
+
+    let send_label = if draft.offer_id.is_some() {
+        "Counter"
+    } else {
+        "Send"
+    };
+    if draw_labeled_button(x_, y_ + 5.0, 120.0, 30.0, send_label) == ButtonState::Pressed {
+        action = Some(TradePanelAction::Send);
+    }
+    if draw_labeled_button(x_ + 130.0, y_ + 5.0, 120.0, 30.0, "Cancel") == ButtonState::Pressed {
+        action = Some(TradePanelAction::Cancel);
+    }
+    (action, Vec2::new(w, h))
+}
+
+/// Book value, return and payback period of an owned asset, one per line.
+pub fn format_asset_return(asset_return: &AssetReturn) -> String {
+    let payback = match asset_return.get_payback_rounds() {
+        Some(rounds) => format!("{:.0} rounds", rounds.ceil()),
+        None => "never".to_string(),
+    };
+    format!(
+        "Value: {}$ (paid {}$)\nReturn: {:.1}%/round\nPayback: {}",
+        asset_return.book_value,
+        asset_return.acquisition_cost,
+        asset_return.get_roi() * 100.0,
+        payback
+    )
+}
+
+fn get_asset_label(graph: &Graph, asset_id: AssetId) -> String {
+    match asset_id {
+        AssetId::Building {
+            city_id,
+            building_id,
+        } => match graph.cities[city_id].get_building(building_id) {
+            Some(building) => format!("{} in city {}", building.building_type, city_id),
+            None => format!("Building {} in city {}", building_id, city_id),
+        },
+        AssetId::Connection(connection_id) => format!("Connection {}", connection_id),
+    }
+}
+
+/// Income statement of a business for one round, with materials at their current market value.
+/// Returns the requested step between rounds.
+pub fn draw_ledger_panel(
+    x: f32,
+    y: f32,
+    graph: &Graph,
+    registry: &Registry,
+    business_id: usize,
+    round_opt: Option<u32>,
+) -> (i32, Vec2) {
+    let columns = [("", 300.0), ("In", 90.0), ("Out", 90.0), ("Net", 90.0)];
+    let column_w: f32 = columns.iter().map(|(_, w)| w).sum();
+    let row_h = 30.0;
+    let market_values = graph.get_market_values(registry);
+    let income_statement = round_opt
+        .map(|round| {
+            graph.businesses[business_id]
+                .ledger
+                .get_income_statement(round, &market_values)
+        })
+        .unwrap_or_default();
+    let value_rows: Vec<(String, &QuantityInfo)> = income_statement
+        .assets
+        .iter()
+        .map(|(asset_id, info)| (get_asset_label(graph, *asset_id), info))
+        .chain(
+            income_statement
+                .other
+                .iter()
+                .map(|(kind, info)| (kind.to_string(), info)),
+        )
+        .collect();
+    let material_rows: Vec<(String, &QuantityInfo)> = income_statement
+        .materials
+        .iter()
+        .map(|(material, info)| (material.to_string(), info))
+        .collect();
+    let rows = value_rows.len().max(material_rows.len()) + 2;
+    let w = 2.0 * column_w + 3.0 * MARGIN;
+    let h = 40.0 + row_h * rows as f32 + 2.0 * MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+
+    let round = match round_opt {
+        Some(round) => format!("Round {}", round),
+        None => "No rounds".to_string(),
+    };
+    let (step, _) = draw_stepper(x + MARGIN, y + MARGIN, round.as_str());
+    let y_ = y + 40.0 + MARGIN;
+    for (section_id, (title, section_rows)) in [
+        ("Value by asset and source", value_rows),
+        ("Materials", material_rows),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let section_x = x + MARGIN + section_id as f32 * (column_w + MARGIN);
+        draw_text(title, section_x, y_ + row_h - 5.0, 24.0, WHITE);
+        let mut column_x = section_x;
+        for (label, w) in columns {
+            draw_text(label, column_x, y_ + 2.0 * row_h - 5.0, 20.0, WHITE);
+            column_x += w;
+        }
+        for (row_id, (label, info)) in section_rows.iter().enumerate() {
+            let cells = [
+                label.clone(),
+                info.gross_in.to_string(),
+                info.gross_out.to_string(),
+                format!("{:+}", info.net_in()),
+            ];
+            let row_y = y_ + (row_id + 3) as f32 * row_h - 5.0;
+            let color = match info.net_in() {
+                net_in if net_in < 0 => RED,
+                net_in if net_in > 0 => GREEN,
+                _ => WHITE,
+            };
+            let mut column_x = section_x;
+            for (cell_id, (cell, (_, w))) in cells.iter().zip(columns).enumerate() {
+                draw_text(
+                    cell,
+                    column_x,
+                    row_y,
+                    20.0,
+                    if cell_id == 3 { color } else { WHITE },
+                );
+                column_x += w;
+            }
+        }
+    }
+    (step, Vec2::new(w, h))
+}
+
+/// Line chart of a metric over the rounds played, one line per business.
+pub fn draw_stats_panel(x: f32, y: f32, graph: &Graph, metric: &Metric) -> (i32, Vec2) {
+    let w = 1000.0;
+    let h = 600.0;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+    let step = draw_selector(x + MARGIN, y + MARGIN, metric.to_string().as_str());
+    let mut legend_x = x + 400.0;
+    for business in &graph.businesses {
+        let [r, g, b] = business.color;
+        draw_text(
+            business.name.as_str(),
+            legend_x,
+            y + 35.0,
+            24.0,
+            Color::from_rgba(r, g, b, 255),
+        );
+        legend_x += 150.0;
+    }
+
+    let series: Vec<(Color, Vec<(u32, i32)>)> = graph
+        .businesses
+        .iter()
+        .map(|business| {
+            let [r, g, b] = business.color;
+            (
+                Color::from_rgba(r, g, b, 255),
+                business
+                    .history
+                    .iter()
+                    .map(|snapshot| (snapshot.round, snapshot.get(metric)))
+                    .collect(),
+            )
+        })
+        .collect();
+    let points = series.iter().flat_map(|(_, points)| points.iter());
+    let (Some(first_round), Some(last_round)) = (
+        points.clone().map(|(round, _)| *round).min(),
+        points.clone().map(|(round, _)| *round).max(),
+    ) else {
+        draw_text("No rounds played yet", x + MARGIN, y + 100.0, 24.0, WHITE);
+        return (step, Vec2::new(w, h));
+    };
+    let min_value = points
+        .clone()
+        .map(|(_, value)| *value)
+        .min()
+        .unwrap()
+        .min(0);
+    let max_value = points
+        .map(|(_, value)| *value)
+        .max()
+        .unwrap()
+        .max(min_value + 1);
+
+    let chart = Rect::new(x + 80.0, y + 70.0, w - 80.0 - 2.0 * MARGIN, h - 110.0);
+    let to_screen = |round: u32, value: i32| {
+        let round_span = (last_round - first_round).max(1) as f32;
+        Vec2::new(
+            chart.x + chart.w * (round - first_round) as f32 / round_span,
+            chart.bottom() - chart.h * (value - min_value) as f32 / (max_value - min_value) as f32,
+        )
+    };
+    draw_line(chart.x, chart.y, chart.x, chart.bottom(), 2.0, WHITE);
+    let zero = to_screen(first_round, 0);
+    draw_line(chart.x, zero.y, chart.right(), zero.y, 2.0, WHITE);
+    for (value, label_y) in [(max_value, chart.y + 10.0), (min_value, chart.bottom())] {
+        draw_text(value.to_string().as_str(), x + MARGIN, label_y, 20.0, WHITE);
+    }
+    for (round, label_x) in [(first_round, chart.x), (last_round, chart.right() - 60.0)] {
+        draw_text(
+            format!("Round {}", round).as_str(),
+            label_x,
+            chart.bottom() + 25.0,
+            20.0,
+            WHITE,
+        );
+    }
+    for (color, points) in &series {
+        for pair in points.windows(2) {
+            let start = to_screen(pair[0].0, pair[0].1);
+            let end = to_screen(pair[1].0, pair[1].1);
+            draw_line(start.x, start.y, end.x, end.y, 3.0, *color);
+        }
+        if let [(round, value)] = points.as_slice() {
+            let point = to_screen(*round, *value);
+            draw_circle(point.x, point.y, 3.0, *color);
+        }
+    }
+    (step, Vec2::new(w, h))
+}
+
+/// Recipes and scales needed to export a material, with what is owned, for sale and missing.
+pub fn draw_planner_panel(
+    x: f32,
+    y: f32,
+    registry: &Registry,
+    target: &mut Target,
+    plan: &Plan,
+) -> Vec2 {
+    let columns = [
+        ("Recipe", 230.0),
+        ("Scale", 80.0),
+        ("Owned", 80.0),
+        ("For sale", 90.0),
+        ("Missing", 90.0),
+        ("Building types", 400.0),
+    ];
+    let w: f32 = columns.iter().map(|(_, w)| w).sum::<f32>() + 2.0 * MARGIN;
+    let row_h = 30.0;
+    let mut notes = vec![];
+    if !plan.unsourced.is_empty() {
+        let unsourced: Vec<String> = plan.unsourced.iter().map(|m| m.to_string()).collect();
+        notes.push((format!("No source for {}", unsourced.join(", ")), RED));
+    }
+    if !plan.surplus.is_empty() {
+        let surplus: Vec<(MaterialId, i32)> = plan.surplus.clone().into_iter().collect();
+        notes.push((format!("Surplus: {}", trade::format_goods(&surplus)), WHITE));
+    }
+    notes.push((
+        format!(
+            "Money: {:+}$/round at market prices, before upkeep",
+            plan.money_per_round
+        ),
+        WHITE,
+    ));
+    let h = 40.0 + row_h * (plan.steps.len() + notes.len() + 1) as f32 + 3.0 * MARGIN;
+    draw_rectangle(x, y, w, h, UI_BACKGROUND_COLOR);
+
+    let x_ = x + MARGIN;
+    let mut y_ = y + MARGIN;
+    draw_text("Export", x_, y_ + 28.0, 24.0, WHITE);
+    let step = draw_selector(x_ + 80.0, y_ + 5.0, target.material.0.as_str());
+    if step != 0 {
+        target.material = cycle(&Target::get_materials(registry), &target.material, step);
+    }
+    let (step, _) = draw_stepper(x_ + 300.0, y_, target.quantity.to_string().as_str());
+    target.quantity = (target.quantity + step).max(1);
+    draw_text("per round", x_ + 530.0, y_ + 28.0, 24.0, WHITE);
+    y_ += 40.0 + MARGIN;
+
+    let mut column_x = x_;
+    for (label, w) in columns {
+        draw_text(label, column_x, y_ + row_h - 5.0, 20.0, WHITE);
+        column_x += w;
+    }
+    y_ += row_h;
+    for plan_step in &plan.steps {
+        let building_types: Vec<String> = plan_step
+            .building_types
+            .iter()
+            .map(|building_type| building_type.to_string())
+            .collect();
+        let cells = [
+            plan_step.valid_recipe.to_string(),
+            plan_step.scale.to_string(),
+            plan_step.owned_scale.to_string(),
+            plan_step.reachable_scale.to_string(),
+            plan_step.get_gap().to_string(),
+            building_types.join(", "),
+        ];
+        let mut column_x = x_;
+        for (cell_id, (cell, (_, w))) in cells.iter().zip(columns).enumerate() {
+            let color = match cell_id {
+                2 if plan_step.owned_scale >= plan_step.scale => GREEN,
+                4 if plan_step.get_gap() > 0 => RED,
+                _ => WHITE,
+            };
+            draw_text(cell, column_x, y_ + row_h - 5.0, 20.0, color);
+            column_x += w;
+        }
+        y_ += row_h;
+    }
+    y_ += MARGIN;
+    for (note, color) in notes {
+        draw_text(note.as_str(), x_, y_ + row_h - 5.0, 20.0, color);
+        y_ += row_h;
+    }
     Vec2::new(w, h)
 }